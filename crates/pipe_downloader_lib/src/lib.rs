@@ -1,13 +1,39 @@
 #![allow(clippy::redundant_closure)]
 #[deny(missing_docs)]
 mod options;
+mod pipe_bench;
+mod pipe_control;
 mod pipe_downloader;
+mod pipe_durability;
 mod pipe_engine;
+mod pipe_error;
+mod pipe_events;
+mod pipe_format;
+mod pipe_history;
+mod pipe_inspect;
+mod pipe_list;
+mod pipe_metrics;
 mod pipe_progress;
 mod pipe_utils;
+mod pipe_verify;
 mod pipe_wrapper;
 mod tsutils;
 
 pub use crate::pipe_downloader::PipeDownloader;
-pub use options::PipeDownloaderOptions;
-pub use pipe_progress::PipeDownloaderProgress;
+pub use options::{DurabilityMode, OutputMode, PipeDownloaderOptions};
+pub use pipe_bench::{
+    bench, BenchOptions, BenchRecommendation, BenchReport, BenchStage, DecodeBench, NetworkBench,
+    WriteBench,
+};
+pub use pipe_control::{ControlAction, ControlLogEntry};
+pub use pipe_error::{DownloadError, DownloadSummary};
+pub use pipe_events::{PipeDownloaderEvent, PipeDownloaderObserver, PipelineStage};
+pub use pipe_format::{
+    detect_archive, detect_compression, ArchiveInfo, CompressionFormat, UnpackSizeSource,
+};
+pub use pipe_history::{HistoryPoint, PipeDownloaderHistory};
+pub use pipe_inspect::{inspect, InspectOptions, InspectReport, InspectTarEntry, SpeedSample};
+pub use pipe_list::{glob_match, ListReport, ListedEntry};
+pub use pipe_metrics::PrometheusMetrics;
+pub use pipe_progress::{DownloadThreadStats, PipeDownloaderProgress, StageBlockedTime};
+pub use pipe_verify::{VerifyDifference, VerifyDifferenceKind, VerifyReport};
@@ -1,5 +1,72 @@
-use crate::PipeDownloader;
+use crate::{PipeDownloader, PipeDownloaderObserver};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// What the last stage of the pipeline does with the decoded stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Unpack archive (or write single file) into the target path
+    #[default]
+    Unpack,
+    /// Compare archive entries with files already present in the target directory.
+    /// Nothing is written to disk, result is available as [crate::VerifyReport]
+    Verify,
+    /// Walk archive entries without writing them (like `tar -tv`), entries are emitted as
+    /// [crate::PipeDownloaderEvent::EntryListed], result is available as [crate::ListReport]
+    List,
+    /// Write the decoded stream to stdout instead of unpacking it
+    Stdout,
+    /// Write the downloaded (compressed) stream to stdout without decoding it,
+    /// the file does not have to be an archive. Expected sha256 is checked at the end,
+    /// the data is already written then, so failure is only reported by the outcome.
+    StdoutRaw,
+}
+
+impl OutputMode {
+    /// Output goes to stdout, target path is not used
+    pub fn is_stdout(&self) -> bool {
+        matches!(self, OutputMode::Stdout | OutputMode::StdoutRaw)
+    }
+}
+
+/// How hard to try to make unpacked data survive power loss
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DurabilityMode {
+    /// Leave flushing to the operating system
+    #[default]
+    None,
+    /// Fsync every file right after it's written
+    FsyncFiles,
+    /// Sync whole filesystem at the end of unpacking (syncfs on Linux)
+    SyncAtEnd,
+}
+
+impl DurabilityMode {
+    /// Name accepted by [FromStr]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DurabilityMode::None => "none",
+            DurabilityMode::FsyncFiles => "fsync",
+            DurabilityMode::SyncAtEnd => "syncfs",
+        }
+    }
+}
+
+impl FromStr for DurabilityMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DurabilityMode::None),
+            "fsync" => Ok(DurabilityMode::FsyncFiles),
+            "syncfs" => Ok(DurabilityMode::SyncAtEnd),
+            _ => Err(anyhow::anyhow!(
+                "Unknown durability mode {s}, expected one of: none, fsync, syncfs"
+            )),
+        }
+    }
+}
 
 /// Pipe Downloader Options.
 #[derive(Debug, Clone)]
@@ -22,6 +89,24 @@ pub struct PipeDownloaderOptions {
     pub ignore_symlinks: bool,
     /// Ignore directory exists error
     pub ignore_directory_exists: bool,
+    /// Unpack or only verify existing output
+    pub output_mode: OutputMode,
+    /// Check before start if the output filesystem has enough free space
+    /// for known or estimated unpacked size
+    pub check_disk_space: bool,
+    /// Pause download when free space on output filesystem drops below this value (in bytes),
+    /// download is resumed when space is available again
+    pub min_free_disk_space: Option<u64>,
+    /// Sync unpacked files and directories to disk before reporting finish.
+    /// Directories are always synced when mode other than [DurabilityMode::None] is used.
+    pub durability: DurabilityMode,
+    /// Sha256 (hex) of the downloaded (compressed) file, download fails when it does not match.
+    /// The whole file is downloaded even when the archive ends earlier.
+    pub expected_sha256: Option<String>,
+    /// Glob pattern of entries listed in [OutputMode::List], all entries are listed when not set
+    pub list_filter: Option<String>,
+    /// Stop the download in [OutputMode::List] once the entry with this path was listed
+    pub list_stop_after_path: Option<String>,
 }
 
 impl Default for PipeDownloaderOptions {
@@ -34,6 +119,13 @@ impl Default for PipeDownloaderOptions {
             download_threads: 2,
             ignore_symlinks: false,
             ignore_directory_exists: false,
+            output_mode: OutputMode::Unpack,
+            check_disk_space: true,
+            min_free_disk_space: Some(200_000_000),
+            durability: DurabilityMode::None,
+            expected_sha256: None,
+            list_filter: None,
+            list_stop_after_path: None,
         }
     }
 }
@@ -49,4 +141,17 @@ impl PipeDownloaderOptions {
         pd.start_download().await?;
         Ok(pd)
     }
+
+    /// Constructs downloader from given options, observer receives all events including the first ones.
+    pub async fn start_download_with_observer(
+        self,
+        url: &str,
+        target_path: Option<PathBuf>,
+        observer: Arc<dyn PipeDownloaderObserver>,
+    ) -> anyhow::Result<PipeDownloader> {
+        let mut pd = PipeDownloader::new(url, target_path, self);
+        pd.add_observer(observer);
+        pd.start_download().await?;
+        Ok(pd)
+    }
 }
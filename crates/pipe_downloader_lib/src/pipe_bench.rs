@@ -0,0 +1,632 @@
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_LENGTH};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::pipe_engine::{
+    check_status, header_string, request_chunk, resolve_link, supports_partial_content,
+    user_agent_headers, SourceIdentity,
+};
+use crate::pipe_error::DownloadError;
+use crate::pipe_events::PipelineStage;
+use crate::pipe_format::{
+    detect_archive, detect_compression, is_tar_header, ArchiveInfo, CompressionFormat,
+};
+use crate::pipe_inspect::sample_decoder;
+
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+/// Decoded data kept in memory for the write measurement
+const MAX_DECODED_SAMPLE: usize = 256_000_000;
+/// Decoded data compressed with other codecs when comparing them
+const MAX_ENCODE_SAMPLE: usize = 32_000_000;
+/// Network throughput within this ratio of the best one is good enough for fewer threads
+const THREADS_SPEED_RATIO: f64 = 0.9;
+/// Download buffer (chunk) should take about this long per connection
+const CHUNK_DOWNLOAD_TIME_SEC: f64 = 2.0;
+/// Unpack buffer should hold about this much of decoded data
+const UNPACK_BUFFER_TIME_SEC: f64 = 0.5;
+
+/// Options of [bench]
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Time limit of each network measurement
+    pub max_time: Duration,
+    /// Bytes limit of each network measurement
+    pub max_bytes: usize,
+    /// Numbers of connections measured
+    pub thread_counts: Vec<usize>,
+    /// Size of range requests used by the network measurement
+    pub chunk_size: usize,
+    /// Compressed bytes from the beginning of the file used to measure decoding and writing
+    pub sample_size: usize,
+    /// Compare decoding of the sample compressed with all supported codecs,
+    /// only the codec of the archive is measured otherwise
+    pub all_codecs: bool,
+    /// Write throughput is measured in a temporary directory created here
+    pub target_dir: PathBuf,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            max_time: Duration::from_secs(5),
+            max_bytes: 200_000_000,
+            thread_counts: vec![1, 2, 4, 8, 16],
+            chunk_size: 4_000_000,
+            sample_size: 32_000_000,
+            all_codecs: false,
+            target_dir: PathBuf::from("."),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct NetworkBench {
+    pub threads: usize,
+    pub bytes: usize,
+    pub elapsed_time_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct DecodeBench {
+    pub format: CompressionFormat,
+    /// Measured on the downloaded sample, otherwise on the sample compressed again with this codec
+    pub archive_codec: bool,
+    pub compressed_bytes: usize,
+    pub decoded_bytes: usize,
+    pub elapsed_time_sec: f64,
+    pub compressed_bytes_per_sec: f64,
+    pub decoded_bytes_per_sec: f64,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct WriteBench {
+    pub path: String,
+    pub bytes: usize,
+    pub files: usize,
+    pub elapsed_time_sec: f64,
+    pub bytes_per_sec: f64,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchStage {
+    Network,
+    Decode,
+    Write,
+}
+
+/// Options for [crate::PipeDownloaderOptions] derived from the measurements
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct BenchRecommendation {
+    pub download_threads: usize,
+    pub chunk_size_downloader: usize,
+    pub chunk_size_decoder: usize,
+}
+
+/// Result of [bench], throughputs of the stages compared in downloaded (compressed) bytes per second
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub url: String,
+    /// Local file was measured, network is not involved
+    pub local_file: bool,
+    pub archive: Option<ArchiveInfo>,
+    pub format: Option<CompressionFormat>,
+    pub total_download_size: Option<usize>,
+    pub partial_content_support: bool,
+    pub network: Vec<NetworkBench>,
+    pub decode: Vec<DecodeBench>,
+    pub write: Option<WriteBench>,
+    /// Decoded bytes per compressed byte of the sample
+    pub compression_ratio: Option<f64>,
+    pub network_bytes_per_sec: Option<f64>,
+    pub decode_bytes_per_sec: Option<f64>,
+    pub write_bytes_per_sec: Option<f64>,
+    /// Slowest stage, it limits the speed of the whole pipeline
+    pub bottleneck: Option<BenchStage>,
+    /// Expected download speed of the whole pipeline
+    pub expected_bytes_per_sec: Option<f64>,
+    pub recommendation: BenchRecommendation,
+    pub warnings: Vec<String>,
+}
+
+/// Measure the stages of the pipeline separately: network throughput for each number of
+/// connections (bounded by time and bytes), decoding of a sample from the beginning of the file
+/// and unpacking the decoded sample to the target filesystem. The url can be a local file,
+/// then only decoding and writing are measured. Blocking, do not call it from async context.
+pub fn bench(url: &str, options: &BenchOptions) -> Result<BenchReport, DownloadError> {
+    bench_url(url, options).map_err(|err| DownloadError::from_stage(PipelineStage::Download, &err))
+}
+
+fn is_local_file(url: &str) -> bool {
+    !url.starts_with("http://") && !url.starts_with("https://") && Path::new(url).is_file()
+}
+
+fn read_local_sample(
+    path: &str,
+    sample_size: usize,
+    report: &mut BenchReport,
+) -> std::io::Result<Vec<u8>> {
+    report.total_download_size = Some(fs::metadata(path)?.len() as usize);
+    let mut sample = Vec::new();
+    fs::File::open(path)?
+        .take(sample_size as u64)
+        .read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+/// Errors are mapped to [DownloadError] by the stage that failed
+fn bench_url(url: &str, options: &BenchOptions) -> anyhow::Result<BenchReport> {
+    let defaults = crate::PipeDownloaderOptions::default();
+    let mut report = BenchReport {
+        url: url.to_string(),
+        local_file: is_local_file(url),
+        archive: None,
+        format: None,
+        total_download_size: None,
+        partial_content_support: false,
+        network: Vec::new(),
+        decode: Vec::new(),
+        write: None,
+        compression_ratio: None,
+        network_bytes_per_sec: None,
+        decode_bytes_per_sec: None,
+        write_bytes_per_sec: None,
+        bottleneck: None,
+        expected_bytes_per_sec: None,
+        recommendation: BenchRecommendation {
+            download_threads: defaults.download_threads,
+            chunk_size_downloader: defaults.chunk_size_downloader,
+            chunk_size_decoder: defaults.chunk_size_decoder,
+        },
+        warnings: Vec::new(),
+    };
+
+    let sample = if report.local_file {
+        report.archive = detect_archive(None, &[url]);
+        read_local_sample(url, options.sample_size, &mut report)
+            .map_err(|err| DownloadError::from_io(&err))?
+    } else {
+        bench_network(url, options, &mut report)?
+    };
+
+    report.format = detect_compression(&sample).or(report.archive.as_ref().map(|a| a.format));
+    if let Some(format) = report.format {
+        bench_decode_and_write(format, &sample, options, &mut report)?;
+    } else {
+        report
+            .warnings
+            .push("Compression not recognized, decoding is not measured".to_string());
+    }
+    recommend(&mut report);
+    Ok(report)
+}
+
+/// Measures connections and returns sample from the beginning of the file
+fn bench_network(
+    url: &str,
+    options: &BenchOptions,
+    report: &mut BenchReport,
+) -> anyhow::Result<Vec<u8>> {
+    let client = reqwest::blocking::Client::new();
+    let download_url = resolve_link(&client, url)?;
+    let response = client
+        .head(&download_url)
+        .headers(user_agent_headers())
+        .send()?;
+    check_status(&response)?;
+    let final_url = response.url().to_string();
+    let total_length = header_string(&response, CONTENT_LENGTH)
+        .and_then(|length| usize::from_str(&length).ok())
+        .filter(|length| *length > 0);
+    report.total_download_size = total_length;
+    report.archive = detect_archive(
+        header_string(&response, CONTENT_DISPOSITION).as_deref(),
+        &[&final_url, &download_url, url],
+    );
+    let source = SourceIdentity::from_response(&response, total_length);
+    report.partial_content_support =
+        total_length.is_some() && supports_partial_content(&client, &download_url)?;
+
+    let mut thread_counts = options.thread_counts.clone();
+    if !report.partial_content_support {
+        report.warnings.push(
+            "Server does not support partial content, only one connection is measured".to_string(),
+        );
+        thread_counts = vec![1];
+    }
+    thread_counts.sort_unstable();
+    thread_counts.dedup();
+    for threads in thread_counts.into_iter().filter(|threads| *threads > 0) {
+        let result = measure_network(
+            &download_url,
+            &source,
+            total_length.filter(|_| report.partial_content_support),
+            threads,
+            options,
+        )?;
+        log::info!(
+            "Network with {} connections: {:.0} B/s",
+            threads,
+            result.bytes_per_sec
+        );
+        report.network.push(result);
+    }
+
+    let mut request = client.get(&download_url).headers(user_agent_headers());
+    if report.partial_content_support && options.sample_size > 0 {
+        request = request.header("Range", format!("bytes=0-{}", options.sample_size - 1));
+    }
+    let response = request.send()?;
+    check_status(&response)?;
+    let mut sample = Vec::with_capacity(options.sample_size);
+    response
+        .take(options.sample_size as u64)
+        .read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+/// Connections download consecutive ranges (wrapping around the end of the file)
+/// until time or bytes limit is reached. Without total length one plain request is used.
+fn measure_network(
+    url: &str,
+    source: &SourceIdentity,
+    total_length: Option<usize>,
+    threads: usize,
+    options: &BenchOptions,
+) -> anyhow::Result<NetworkBench> {
+    let downloaded = Arc::new(AtomicUsize::new(0));
+    let next_offset = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let url = url.to_string();
+            let source = source.clone();
+            let downloaded = downloaded.clone();
+            let next_offset = next_offset.clone();
+            let options = options.clone();
+            thread::spawn(move || -> anyhow::Result<()> {
+                let client = reqwest::blocking::Client::new();
+                let limit_reached = || {
+                    start.elapsed() >= options.max_time
+                        || downloaded.load(Ordering::Relaxed) >= options.max_bytes
+                };
+                let mut buf = vec![0u8; READ_BUFFER_SIZE];
+                while !limit_reached() {
+                    let mut response = match total_length {
+                        Some(total_length) => {
+                            let offset = next_offset
+                                .fetch_add(options.chunk_size, Ordering::Relaxed)
+                                % total_length;
+                            let range = offset..(offset + options.chunk_size).min(total_length);
+                            request_chunk(&url, &client, &range, &source)?
+                        }
+                        None => {
+                            let response = client.get(&url).headers(user_agent_headers()).send()?;
+                            check_status(&response)?;
+                            response
+                        }
+                    };
+                    loop {
+                        let n = response.read(&mut buf)?;
+                        if n == 0 {
+                            break;
+                        }
+                        downloaded.fetch_add(n, Ordering::Relaxed);
+                        if limit_reached() {
+                            break;
+                        }
+                    }
+                    if total_length.is_none() {
+                        break;
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Bench thread panicked"))??;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let bytes = downloaded.load(Ordering::Relaxed);
+    Ok(NetworkBench {
+        threads,
+        bytes,
+        elapsed_time_sec: elapsed,
+        bytes_per_sec: per_sec(bytes, elapsed),
+    })
+}
+
+fn per_sec(bytes: usize, elapsed_sec: f64) -> f64 {
+    if elapsed_sec > 0.0 {
+        bytes as f64 / elapsed_sec
+    } else {
+        0.0
+    }
+}
+
+fn bench_decode_and_write(
+    format: CompressionFormat,
+    sample: &[u8],
+    options: &BenchOptions,
+    report: &mut BenchReport,
+) -> anyhow::Result<()> {
+    let (decode, decoded) = measure_decode(format, sample, true)
+        .map_err(|err| DownloadError::from_stage(PipelineStage::Decode, &err))?;
+    report.compression_ratio =
+        Some(decode.decoded_bytes as f64 / decode.compressed_bytes.max(1) as f64);
+    report.decode.push(decode);
+
+    if options.all_codecs {
+        let to_encode = &decoded[..decoded.len().min(MAX_ENCODE_SAMPLE)];
+        for other in [
+            CompressionFormat::Gzip,
+            CompressionFormat::Lz4,
+            CompressionFormat::Bzip2,
+            CompressionFormat::Xz,
+            CompressionFormat::Zstd,
+        ] {
+            if other == format {
+                continue;
+            }
+            let result =
+                encode(other, to_encode).and_then(|encoded| measure_decode(other, &encoded, false));
+            match result {
+                Ok((mut decode, _)) => {
+                    decode.archive_codec = false;
+                    report.decode.push(decode);
+                }
+                Err(err) => report
+                    .warnings
+                    .push(format!("Cannot measure {other:?} decoding: {err}")),
+            }
+        }
+    }
+
+    let is_tar = is_tar_header(&decoded);
+    if report
+        .archive
+        .as_ref()
+        .is_some_and(|archive| archive.is_tar)
+        && !is_tar
+    {
+        report
+            .warnings
+            .push("File name suggests tar archive, but decoded content is not tar".to_string());
+    }
+    report.write = Some(
+        measure_write(&decoded, is_tar, &options.target_dir)
+            .map_err(|err| DownloadError::from_stage(PipelineStage::Unpack, &err))?,
+    );
+    Ok(())
+}
+
+/// Decodes until the end of the sample, error at the end of truncated sample is expected
+fn measure_decode(
+    format: CompressionFormat,
+    compressed: &[u8],
+    keep_decoded: bool,
+) -> anyhow::Result<(DecodeBench, Vec<u8>)> {
+    let mut remaining = compressed;
+    let mut decoded = Vec::new();
+    let mut decoded_bytes = 0;
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    let start = Instant::now();
+    {
+        let mut decoder = sample_decoder(format, &mut remaining)?;
+        while decoded_bytes < MAX_DECODED_SAMPLE {
+            match decoder.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    decoded_bytes += n;
+                    if keep_decoded {
+                        decoded.extend_from_slice(&buf[..n]);
+                    }
+                }
+                Err(_) if decoded_bytes > 0 => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let compressed_bytes = compressed.len() - remaining.len();
+    Ok((
+        DecodeBench {
+            format,
+            archive_codec: true,
+            compressed_bytes,
+            decoded_bytes,
+            elapsed_time_sec: elapsed,
+            compressed_bytes_per_sec: per_sec(compressed_bytes, elapsed),
+            decoded_bytes_per_sec: per_sec(decoded_bytes, elapsed),
+        },
+        decoded,
+    ))
+}
+
+/// Fastest levels, decoding speed barely depends on them
+fn encode(format: CompressionFormat, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Ok(match format {
+        CompressionFormat::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        CompressionFormat::Lz4 => {
+            #[cfg(feature = "lz4-rust")]
+            {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                return Ok(encoder.finish()?);
+            }
+            #[cfg(all(feature = "with-lz4", not(feature = "lz4-rust")))]
+            {
+                let mut encoder = lz4::EncoderBuilder::new().level(1).build(Vec::new())?;
+                encoder.write_all(data)?;
+                let (encoded, result) = encoder.finish();
+                result?;
+                return Ok(encoded);
+            }
+            #[cfg(not(any(feature = "lz4-rust", feature = "with-lz4")))]
+            return Err(anyhow::anyhow!("lz4 is not supported"));
+        }
+        CompressionFormat::Bzip2 => {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        CompressionFormat::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 1);
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        CompressionFormat::Zstd => zstd::stream::encode_all(data, 1)?,
+    })
+}
+
+/// Unpacks decoded sample to a temporary directory on the target filesystem, which is removed
+/// afterwards. Like the default durability, files are not synced.
+fn measure_write(decoded: &[u8], is_tar: bool, target_dir: &Path) -> anyhow::Result<WriteBench> {
+    if !target_dir.is_dir() {
+        return Err(DownloadError::Io {
+            message: format!("Directory does not exist: {}", target_dir.display()),
+        }
+        .into());
+    }
+    let dir = target_dir.join(format!(".pipe_downloader_bench_{}", std::process::id()));
+    fs::create_dir(&dir)?;
+    let start = Instant::now();
+    let result = write_sample(decoded, is_tar, &dir);
+    let elapsed = start.elapsed().as_secs_f64();
+    if let Err(err) = fs::remove_dir_all(&dir) {
+        log::warn!("Cannot remove bench directory {}: {}", dir.display(), err);
+    }
+    let (bytes, files) = result?;
+    Ok(WriteBench {
+        path: target_dir.display().to_string(),
+        bytes,
+        files,
+        elapsed_time_sec: elapsed,
+        bytes_per_sec: per_sec(bytes, elapsed),
+    })
+}
+
+fn write_sample(decoded: &[u8], is_tar: bool, dir: &Path) -> std::io::Result<(usize, usize)> {
+    if !is_tar {
+        fs::write(dir.join("sample"), decoded)?;
+        return Ok((decoded.len(), 1));
+    }
+    let (mut bytes, mut files) = (0, 0);
+    let mut archive = tar::Archive::new(decoded);
+    for entry in archive.entries()? {
+        // the last entry of the sample is usually truncated
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => break,
+        };
+        let size = entry.header().size().unwrap_or_default() as usize;
+        match entry.unpack_in(dir) {
+            Ok(_) => {
+                bytes += size;
+                files += 1;
+            }
+            Err(err) if files == 0 => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok((bytes, files))
+}
+
+fn round_to_mb(bytes: f64, min: usize, max: usize) -> usize {
+    ((bytes / 1_000_000.0).round() as usize * 1_000_000).clamp(min, max)
+}
+
+fn recommend(report: &mut BenchReport) {
+    let ratio = report
+        .compression_ratio
+        .unwrap_or(1.0)
+        .max(f64::MIN_POSITIVE);
+    let best_network = report
+        .network
+        .iter()
+        .map(|network| network.bytes_per_sec)
+        .fold(None, |best: Option<f64>, speed| {
+            Some(best.map_or(speed, |b| b.max(speed)))
+        });
+    report.network_bytes_per_sec = best_network;
+    report.decode_bytes_per_sec = report
+        .decode
+        .iter()
+        .find(|decode| decode.archive_codec)
+        .map(|decode| decode.compressed_bytes_per_sec);
+    report.write_bytes_per_sec = report
+        .write
+        .as_ref()
+        .map(|write| write.bytes_per_sec / ratio);
+
+    let stages = [
+        (BenchStage::Network, report.network_bytes_per_sec),
+        (BenchStage::Decode, report.decode_bytes_per_sec),
+        (BenchStage::Write, report.write_bytes_per_sec),
+    ];
+    let slowest = stages
+        .iter()
+        .filter_map(|(stage, speed)| Some((*stage, (*speed)?)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    report.bottleneck = slowest.map(|(stage, _)| stage);
+    report.expected_bytes_per_sec = slowest.map(|(_, speed)| speed);
+
+    // more connections than needed to saturate the slowest of the other stages only use memory
+    let slowest_other = stages
+        .iter()
+        .filter(|(stage, _)| *stage != BenchStage::Network)
+        .filter_map(|(_, speed)| *speed)
+        .fold(f64::INFINITY, f64::min);
+    if let Some(best_network) = best_network {
+        let target = (best_network * THREADS_SPEED_RATIO).min(slowest_other);
+        if let Some(network) = report
+            .network
+            .iter()
+            .find(|network| network.bytes_per_sec >= target)
+        {
+            report.recommendation.download_threads = network.threads;
+            // chunks bigger than the part of the file per connection don't help
+            let max_chunk = report.total_download_size.map_or(100_000_000, |size| {
+                size.div_ceil(network.threads)
+                    .div_ceil(1_000_000)
+                    .clamp(1, 100)
+                    * 1_000_000
+            });
+            report.recommendation.chunk_size_downloader = round_to_mb(
+                network.bytes_per_sec / network.threads as f64 * CHUNK_DOWNLOAD_TIME_SEC,
+                1_000_000,
+                max_chunk,
+            );
+        }
+    }
+    if let Some(expected) = report.expected_bytes_per_sec {
+        report.recommendation.chunk_size_decoder = round_to_mb(
+            expected * ratio * UNPACK_BUFFER_TIME_SEC,
+            1_000_000,
+            50_000_000,
+        );
+    }
+}
@@ -0,0 +1,58 @@
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Number of control actions kept in progress
+const MAX_CONTROL_LOG_ENTRIES: usize = 50;
+
+/// Action requested by the user while the download is running
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(
+        tag = "action",
+        rename_all = "camelCase",
+        rename_all_fields = "camelCase"
+    )
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAction {
+    Pause,
+    Resume,
+    /// Graceful stop finishes the file currently being extracted
+    Stop {
+        graceful: bool,
+    },
+    SetSpeedLimit {
+        max_download_speed: Option<usize>,
+    },
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct ControlLogEntry {
+    pub time: chrono::DateTime<chrono::Utc>,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub action: ControlAction,
+}
+
+/// Recent control actions, oldest are dropped
+#[derive(Debug, Clone, Default)]
+pub struct ControlLog {
+    entries: Vec<ControlLogEntry>,
+}
+
+impl ControlLog {
+    pub fn push(&mut self, action: ControlAction) {
+        self.entries.push(ControlLogEntry {
+            time: chrono::Utc::now(),
+            action,
+        });
+        if self.entries.len() > MAX_CONTROL_LOG_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn entries(&self) -> &[ControlLogEntry] {
+        &self.entries
+    }
+}
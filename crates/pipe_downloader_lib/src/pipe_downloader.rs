@@ -1,6 +1,6 @@
-use anyhow::anyhow;
 use flate2::read::GzDecoder;
 use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::sync_channel;
 use std::sync::{Arc, Mutex, MutexGuard};
@@ -10,23 +10,34 @@ use std::{fs, thread};
 #[cfg(all(feature = "with-lz4", not(feature = "lz4-rust")))]
 use lz4::Decoder as Lz4Decoder;
 
-use crate::options::PipeDownloaderOptions;
+use crate::options::{OutputMode, PipeDownloaderOptions};
 
 use bzip2::read::BzDecoder;
 
 #[cfg(feature = "lz4-rust")]
 use lz4_flex::frame::FrameDecoder;
 
+use crate::pipe_control::ControlAction;
+use crate::pipe_durability::DurabilityTracker;
 use crate::pipe_engine::download_loop;
 use crate::pipe_engine::{decode_loop, init_download_loop};
+use crate::pipe_error::{DownloadError, DownloadSummary};
+use crate::pipe_events::{PipeDownloaderEvent, PipeDownloaderObserver, PipelineStage};
+use crate::pipe_format::CompressionFormat;
+use crate::pipe_history::{history_sampler, PipeDownloaderHistory};
+use crate::pipe_list::Lister;
+use crate::pipe_metrics::PrometheusMetrics;
 use crate::pipe_progress::{InternalProgress, UnpackedFileInfo};
 use crate::pipe_utils::bytes_to_human;
-use crate::pipe_utils::resolve_url;
+use crate::pipe_utils::{available_disk_space, disk_space_watchdog, entry_target_path};
+use crate::pipe_verify::Verifier;
 use crate::pipe_wrapper::{DataChunk, MpscReaderFromReceiver};
 use crate::tsutils::TimePair;
 use crate::PipeDownloaderProgress;
 use tar::Archive;
 
+const STDOUT_BUFFER_SIZE: usize = 1024 * 1024;
+
 /// Created from [PipeDownloaderOptions]
 pub struct PipeDownloader {
     url: String,
@@ -43,7 +54,15 @@ fn tar_unpack(
     options: PipeDownloaderOptions,
     pc: Arc<Mutex<InternalProgress>>,
 ) -> std::io::Result<()> {
-    if dst.symlink_metadata().is_err() {
+    let verify = options.output_mode == OutputMode::Verify;
+    let list = options.output_mode == OutputMode::List;
+    if !list && dst.symlink_metadata().is_err() {
+        if verify {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Directory to verify not found: {}", dst.display()),
+            ));
+        }
         fs::create_dir_all(dst)?
     }
 
@@ -58,7 +77,35 @@ fn tar_unpack(
     // descendants), to ensure that directory permissions do not interfer with descendant
     // extraction.
     let mut directories = Vec::new();
+    let mut durability = DurabilityTracker::new(options.durability, dst, pc.clone());
+    let events = pc.lock().unwrap().events.clone();
+    let mut verifier = if verify {
+        Some(Verifier::new(dst))
+    } else {
+        None
+    };
+    let mut lister = if list {
+        Some(Lister::new(
+            options.list_filter.as_deref(),
+            options.list_stop_after_path.as_deref(),
+        ))
+    } else {
+        None
+    };
+    let mut stopped_gracefully = false;
     for entry in tar.entries()? {
+        {
+            let mut pc = pc.lock().unwrap();
+            if pc.graceful_stop_requested {
+                // stop other stages before reading the next entry
+                pc.stop_requested = true;
+                stopped_gracefully = true;
+            }
+        }
+        if stopped_gracefully {
+            log::info!("Graceful stop requested, stopping after the last complete file");
+            break;
+        }
         let mut file = entry?;
         log::debug!(
             "entry: {:?}, path {}",
@@ -87,26 +134,136 @@ fn tar_unpack(
             }
         }
 
-        if file.header().entry_type() == tar::EntryType::Directory {
+        if let Some(lister) = lister.as_mut() {
+            if let Some(entry) = lister.list_entry(&file)? {
+                events.emit(PipeDownloaderEvent::EntryListed { entry });
+            }
+        } else if let Some(verifier) = verifier.as_mut() {
+            verifier.check_entry(&mut file)?;
+        } else if file.header().entry_type() == tar::EntryType::Directory {
             directories.push(file);
-        } else {
-            file.unpack_in(dst)?;
+        } else if file.unpack_in(dst)? {
+            if let Some(target) = entry_target_path(dst, &file.path()?) {
+                let is_file = file.header().entry_type().is_file();
+                durability.entry_written(&target, is_file)?;
+            }
         }
-        {
+        let event = {
             let mut pc = pc.lock().unwrap();
             if let Some(last_unp_file) = pc.last_unpacked_files.back_mut() {
                 last_unp_file.finished = true;
             }
             pc.unpacked_files += 1;
+            pc.last_unpacked_files
+                .back()
+                .map(|f| PipeDownloaderEvent::EntryExtracted {
+                    file_no: f.file_no,
+                    file_name: f.file_name.clone(),
+                    file_size: f.file_size,
+                })
+        };
+        if let Some(event) = event {
+            events.emit(event);
+        }
+        if lister
+            .as_ref()
+            .is_some_and(|lister| lister.stop_after_path_found())
+        {
+            log::info!("Requested path listed, stopping download");
+            pc.lock().unwrap().stop_requested = true;
+            break;
         }
     }
 
     for mut dir in directories {
-        dir.unpack_in(dst)?;
+        if dir.unpack_in(dst)? {
+            if let Some(target) = entry_target_path(dst, &dir.path()?) {
+                durability.entry_written(&target, false)?;
+            }
+        }
+    }
+    if options.output_mode == OutputMode::Unpack {
+        durability.finish()?;
+    }
+    if stopped_gracefully {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "Stopped after the last complete file",
+        ));
+    }
+    if let Some(verifier) = verifier {
+        let report = verifier.finish()?;
+        log::info!(
+            "Verify finished: {} matching, {} missing, {} extra, {} different",
+            report.matching,
+            report.missing.len(),
+            report.extra.len(),
+            report.different.len()
+        );
+        pc.lock().unwrap().verify_report = Some(report);
+    }
+    if let Some(lister) = lister {
+        let report = lister.finish();
+        log::info!(
+            "List finished: {} of {} entries listed",
+            report.listed,
+            report.entries
+        );
+        pc.lock().unwrap().list_report = Some(report);
     }
     Ok(())
 }
 
+/// Decoded (or raw) stream goes to stdout for shell pipelines, stdout is line buffered,
+/// so it's wrapped in a bigger buffer
+fn write_to_stdout(reader: &mut MpscReaderFromReceiver) -> std::io::Result<()> {
+    let mut stdout = BufWriter::with_capacity(STDOUT_BUFFER_SIZE, std::io::stdout().lock());
+    std::io::copy(reader, &mut stdout)?;
+    stdout.flush()
+}
+
+/// Read rest of the download and compare its sha256 with the expected one
+fn verify_sha256(
+    reader: &mut MpscReaderFromReceiver,
+    expected: &str,
+    pc: &Arc<Mutex<InternalProgress>>,
+) -> anyhow::Result<()> {
+    std::io::copy(reader, &mut std::io::sink())?;
+    let actual = reader.finish_sha256().unwrap_or_default();
+    pc.lock().unwrap().download_sha256 = Some(actual.clone());
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(DownloadError::ChecksumMismatch {
+            expected: expected.trim().to_lowercase(),
+            actual,
+        }
+        .into());
+    }
+    log::info!("Checksum of downloaded file verified: {}", actual);
+    Ok(())
+}
+
+/// Record error of the pipeline stage and stop other stages.
+/// Errors caused by already requested stop are only recorded as messages,
+/// the first error is the reason of the failure.
+fn stage_failed(pc: &Arc<Mutex<InternalProgress>>, stage: PipelineStage, error: DownloadError) {
+    let events = {
+        let mut pc = pc.lock().unwrap();
+        let caused_by_stop = pc.stop_requested;
+        pc.stop_requested = true;
+        match stage {
+            PipelineStage::Download => pc.error_message_download = Some(error.to_string()),
+            PipelineStage::Decode => pc.error_message_unpack = Some(error.to_string()),
+            PipelineStage::Unpack => pc.error_message = Some(error.to_string()),
+        }
+        if caused_by_stop {
+            return;
+        }
+        pc.error = Some(error.clone());
+        pc.events.clone()
+    };
+    events.emit(PipeDownloaderEvent::StageError { stage, error });
+}
+
 impl PipeDownloader {
     pub(crate) fn new(
         url: &str,
@@ -123,11 +280,31 @@ impl PipeDownloader {
         }
     }
 
-    /// Process inputs and try to start download
+    /// Process inputs and try to start download.
+    /// Failure of the setup is returned as [DownloadError] and stored as the outcome of the download.
     pub(crate) async fn start_download(self: &mut PipeDownloader) -> anyhow::Result<()> {
         if self.download_started {
             return Err(anyhow::anyhow!("Download already started"));
         }
+        if let Err(err) = self.start_pipeline().await {
+            let error = DownloadError::from_stage(PipelineStage::Download, &err);
+            let events = {
+                let mut pc = self.get_progress_guard();
+                pc.error = Some(error.clone());
+                pc.outcome = Some(Err(error.clone()));
+                pc.events.clone()
+            };
+            events.emit(PipeDownloaderEvent::Finished {
+                success: false,
+                error: Some(error.clone()),
+                summary: None,
+            });
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    async fn start_pipeline(self: &mut PipeDownloader) -> anyhow::Result<()> {
         self.progress_context
             .lock()
             .expect("Failed to obtain lock")
@@ -136,28 +313,16 @@ impl PipeDownloader {
         let url = self.url.clone();
         //let url = "https://github.com/golemfactory/ya-runtime-http-auth/releases/download/v0.1.0/ya-runtime-http-auth-linux-v0.1.0.tar.gz";
 
-        let target_path = if let Some(target_path) = self.target_path.clone() {
-            target_path
-        } else {
-            let last_segment = url.split('/').last().unwrap();
-            if last_segment.starts_with(".tar.") {
-                //handle case when split with .tar. returns empty string
-                return Err(anyhow!("Cannot infer output directory from url, specify output directory with --output-dir"));
-            }
-            if last_segment.contains(".tar.") {
-                let last_segment = last_segment.split(".tar.").next().unwrap();
-                println!("Output directory from url: {}", last_segment);
-                //check if directory or file exists:
-                PathBuf::from(last_segment)
-            } else {
-                return Err(anyhow!("Cannot infer output directory from url, specify output directory with --output-dir"));
+        log::info!("starting download...");
+        if let Some(expected) = &self.options.expected_sha256 {
+            let expected = expected.trim();
+            if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(DownloadError::Other {
+                    message: format!("Invalid sha256 {expected}, expected 64 hex characters"),
+                }
+                .into());
             }
-        };
-        if !self.options.ignore_directory_exists && target_path.exists() {
-            return Err(anyhow!("Output directory from url already exists: {}. Remove it or specify --force flag", target_path.display()));
         }
-
-        log::info!("starting download...");
         let (send_download_chunks, receive_download_chunks) = sync_channel(1);
 
         let download_thread_count = self.options.download_threads;
@@ -188,31 +353,159 @@ impl PipeDownloader {
             }
         };
 
+        let output_mode = self.options.output_mode;
+        // raw stream is not decoded, so any file can be downloaded
+        let raw = output_mode == OutputMode::StdoutRaw;
+        let archive = download_loop_init_result.archive.clone();
+        if archive.is_none() && !raw {
+            return Err(DownloadError::UnsupportedArchive {
+                message: format!(
+                    "Unknown archive type of {}, supported are tar.gz, tar.lz4, tar.bz2, tar.xz, tar.zst (and their short aliases) or single compressed files",
+                    download_loop_init_result.final_url
+                ),
+            }
+            .into());
+        }
+        let is_tar = archive.as_ref().is_some_and(|archive| archive.is_tar);
+        let list = output_mode == OutputMode::List;
+        let uses_target = !list && !output_mode.is_stdout();
+        let target_path = match (self.target_path.clone(), &archive) {
+            // nothing is written to the target path
+            _ if !uses_target => PathBuf::new(),
+            (Some(target_path), _) => target_path,
+            (None, Some(archive)) if archive.output_name.is_some() => {
+                let output_name = archive.output_name.clone().unwrap_or_default();
+                // stdout is reserved for the output of the application (e.g. --ndjson records)
+                log::info!(
+                    "Output {} from {}: {}",
+                    if archive.is_tar { "directory" } else { "file" },
+                    archive.file_name,
+                    output_name
+                );
+                PathBuf::from(output_name)
+            }
+            (None, archive) => {
+                return Err(DownloadError::Other {
+                    message: format!(
+                        "Cannot infer output name from {}, specify output directory with --output-dir",
+                        archive
+                            .as_ref()
+                            .map_or(&download_loop_init_result.final_url, |a| &a.file_name)
+                    ),
+                }
+                .into());
+            }
+        };
+        if list {
+            if !is_tar {
+                return Err(DownloadError::UnsupportedArchive {
+                    message: "List mode is supported only for tar archives".to_string(),
+                }
+                .into());
+            }
+            if self.options.list_stop_after_path.is_some() && self.options.expected_sha256.is_some()
+            {
+                return Err(DownloadError::Other {
+                    message: "Stopping after a listed path can't be combined with sha256 check, the whole file is needed for it".to_string(),
+                }
+                .into());
+            }
+        } else if output_mode == OutputMode::Verify {
+            if !is_tar {
+                return Err(DownloadError::UnsupportedArchive {
+                    message: "Verify mode is supported only for tar archives".to_string(),
+                }
+                .into());
+            }
+            if !target_path.is_dir() {
+                return Err(DownloadError::Io {
+                    message: format!(
+                        "Directory to verify does not exist: {}",
+                        target_path.display()
+                    ),
+                }
+                .into());
+            }
+        } else if output_mode == OutputMode::Unpack
+            && !self.options.ignore_directory_exists
+            && target_path.exists()
+        {
+            return Err(DownloadError::OutputExists {
+                path: target_path.display().to_string(),
+            }
+            .into());
+        }
+        {
+            let mut pc = self.get_progress_guard();
+            if uses_target {
+                pc.target_path = Some(target_path.clone());
+            }
+            if raw {
+                // unpack stage receives the downloaded bytes
+                pc.total_unpack_size = Some(download_loop_init_result.total_length);
+                pc.total_unpack_size_source = None;
+                pc.total_unpack_size_estimated = false;
+            }
+            pc.max_download_speed = self.options.max_download_speed.filter(|speed| *speed > 0);
+        }
+
+        if self.options.check_disk_space && self.options.output_mode == OutputMode::Unpack {
+            self.check_free_disk_space(&target_path)?;
+        }
+
+        let events = self.get_progress_guard().events.clone();
+        events.emit(PipeDownloaderEvent::Started {
+            url: download_loop_init_result.download_url.clone(),
+            total_download_size: Some(download_loop_init_result.total_length),
+            archive: archive.clone(),
+        });
+
         let mut threads = Vec::new();
 
+        {
+            let pc = self.progress_context.clone();
+            thread::spawn(move || history_sampler(pc));
+        }
+
+        if let (Some(threshold), OutputMode::Unpack) =
+            (self.options.min_free_disk_space, self.options.output_mode)
+        {
+            let pc = self.progress_context.clone();
+            let path = target_path.clone();
+            thread::spawn(move || disk_space_watchdog(path, threshold, pc));
+        }
+
         for thread_no in 0..download_loop_init_result.threads_to_spawn {
             let pc = self.progress_context.clone();
-            let options = self.options.clone();
             let send = send_download_chunks.clone();
             let download_loop_init_result = download_loop_init_result.clone();
             threads.push(thread::spawn(move || {
+                // sender is kept until the error is recorded, otherwise the decoder
+                // could report the closed channel as the first error
                 match download_loop(
                     thread_no,
-                    options,
                     pc.clone(),
-                    send,
+                    send.clone(),
                     download_loop_init_result,
                 ) {
                     Ok(_) => {
                         log::info!("Download loop finished, finishing thread");
                     }
                     Err(err) => {
-                        log::error!("Error in download loop: {:?}, finishing thread", err);
+                        if pc.lock().unwrap().stop_requested {
+                            log::info!("Download loop stopped: {:?}, finishing thread", err);
+                        } else {
+                            log::error!("Error in download loop: {:?}, finishing thread", err);
+                        }
                         //stop other threads as well
-                        pc.lock().unwrap().stop_requested = true;
-                        pc.lock().unwrap().error_message_download = Some(err.to_string());
+                        stage_failed(
+                            &pc,
+                            PipelineStage::Download,
+                            DownloadError::from_stage(PipelineStage::Download, &err),
+                        );
                     }
                 }
+                drop(send);
             }));
         }
 
@@ -222,51 +515,66 @@ impl PipeDownloader {
             self.progress_context.clone(),
             true,
         );
+        let verify_checksum = self.options.expected_sha256.is_some();
+        if verify_checksum {
+            p = p.with_sha256();
+        }
 
         let (send_unpack_chunks, receive_unpack_chunks) = sync_channel::<DataChunk>(1);
 
         let pc = self.progress_context.clone();
-        let download_url = url.clone();
         let options = self.options.clone();
+        // without format the stream is passed to the last stage as it is
+        let format = archive
+            .as_ref()
+            .map(|archive| archive.format)
+            .filter(|_| !raw);
         let t2 = thread::spawn(move || {
-            let download_url = match resolve_url(download_url, pc.clone()) {
-                Ok(url) => url,
-                Err(err) => {
-                    pc.lock().unwrap().error_message_unpack = Some(err.to_string());
-                    return;
+            // like in download threads, sender is kept until the error is recorded,
+            // otherwise the last stage could take the closed channel for end of the stream
+            let res = match format {
+                None => decode_loop(pc.clone(), &options, &mut p, &send_unpack_chunks),
+                Some(CompressionFormat::Gzip) => {
+                    let mut gz = GzDecoder::new(&mut p);
+                    decode_loop(pc.clone(), &options, &mut gz, &send_unpack_chunks)
+                }
+                Some(CompressionFormat::Lz4) => {
+                    #[cfg(feature = "lz4-rust")]
+                    let mut lz4 = FrameDecoder::new(&mut p);
+                    #[cfg(all(feature = "with-lz4", not(feature = "lz4-rust")))]
+                    let mut lz4 = Lz4Decoder::new(&mut p).unwrap();
+                    #[cfg(not(any(feature = "lz4-rust", feature = "with-lz4")))]
+                    panic!("lz4 is not supported");
+                    #[cfg(any(feature = "lz4-rust", feature = "with-lz4"))]
+                    decode_loop(pc.clone(), &options, &mut lz4, &send_unpack_chunks)
+                }
+                Some(CompressionFormat::Bzip2) => {
+                    let mut bz2 = BzDecoder::new(&mut p);
+                    decode_loop(pc.clone(), &options, &mut bz2, &send_unpack_chunks)
+                }
+                Some(CompressionFormat::Xz) => {
+                    let mut xz_dec = xz2::read::XzDecoder::new(&mut p);
+                    decode_loop(pc.clone(), &options, &mut xz_dec, &send_unpack_chunks)
+                }
+                Some(CompressionFormat::Zstd) => {
+                    let mut zstd_dec = zstd::stream::read::Decoder::new(&mut p).unwrap();
+                    decode_loop(pc.clone(), &options, &mut zstd_dec, &send_unpack_chunks)
                 }
             };
-
-            let res = if download_url.ends_with(".gz") {
-                let mut gz = GzDecoder::new(&mut p);
-                decode_loop(pc.clone(), &options, &mut gz, send_unpack_chunks)
-            } else if download_url.ends_with(".lz4") {
-                #[cfg(feature = "lz4-rust")]
-                let mut lz4 = FrameDecoder::new(&mut p);
-                #[cfg(all(feature = "with-lz4", not(feature = "lz4-rust")))]
-                let mut lz4 = Lz4Decoder::new(&mut p).unwrap();
-                #[cfg(not(any(feature = "lz4-rust", feature = "with-lz4")))]
-                panic!("lz4 is not supported");
-                #[cfg(any(feature = "lz4-rust", feature = "with-lz4"))]
-                decode_loop(pc.clone(), &options, &mut lz4, send_unpack_chunks)
-            } else if download_url.ends_with(".bz2") {
-                let mut bz2 = BzDecoder::new(&mut p);
-                decode_loop(pc.clone(), &options, &mut bz2, send_unpack_chunks)
-            } else if download_url.ends_with(".xz") {
-                let mut xz_dec = xz2::read::XzDecoder::new(&mut p);
-                decode_loop(pc.clone(), &options, &mut xz_dec, send_unpack_chunks)
-            } else if download_url.ends_with(".zst") {
-                let mut zstd_dec = zstd::stream::read::Decoder::new(&mut p).unwrap();
-                decode_loop(pc.clone(), &options, &mut zstd_dec, send_unpack_chunks)
-            } else {
-                panic!("Unknown file type");
-            };
+            let res = res.and_then(|_| match &options.expected_sha256 {
+                Some(expected) => verify_sha256(&mut p, expected, &pc),
+                None => Ok(()),
+            });
             if let Err(err) = res {
                 log::error!("Error in decode loop: {:?}, finishing thread", err);
                 //stop other threads as well
-                pc.lock().unwrap().stop_requested = true;
-                pc.lock().unwrap().error_message_unpack = Some(err.to_string());
+                stage_failed(
+                    &pc,
+                    PipelineStage::Decode,
+                    DownloadError::from_stage(PipelineStage::Decode, &err),
+                );
             };
+            drop(send_unpack_chunks);
             log::info!("Decode loop finished, finishing thread");
         });
 
@@ -277,23 +585,32 @@ impl PipeDownloader {
             false,
         );
 
-        let download_url = url;
-
         let pc = self.progress_context.clone();
         let options = self.options.clone();
         self.thread_last_stage = Some(thread::spawn(move || {
-            let download_url = match resolve_url(download_url, pc.clone()) {
-                Ok(url) => url,
-                Err(err) => {
-                    pc.lock().unwrap().error_message = Some(err.to_string());
-                    return;
+            let res = if output_mode.is_stdout() {
+                match write_to_stdout(&mut p2) {
+                    Ok(_) => {
+                        log::info!("Successfully written to stdout");
+                        Ok(())
+                    }
+                    Err(err) => {
+                        log::error!("Error while writing to stdout {:?}", err);
+                        Err(err)
+                    }
                 }
-            };
-
-            let res = if download_url.contains(".tar.") {
+            } else if is_tar {
                 let mut archive = Archive::new(p2);
 
-                match tar_unpack(&target_path, &mut archive, options, pc.clone()) {
+                let res =
+                    tar_unpack(&target_path, &mut archive, options, pc.clone()).and_then(|_| {
+                        if verify_checksum {
+                            // rest of the download (after end of archive) is needed for the checksum
+                            std::io::copy(&mut archive.into_inner(), &mut std::io::sink())?;
+                        }
+                        Ok(())
+                    });
+                match res {
                     Ok(_) => {
                         log::info!("Successfully unpacked");
                         Ok(())
@@ -315,8 +632,23 @@ impl PipeDownloader {
                     }
                 }*/
             } else {
-                let mut output_file = File::create(&target_path).unwrap();
-                match std::io::copy(&mut p2, &mut output_file) {
+                // dropped at the end of the block, so the decoder is not blocked on error
+                let mut p2 = p2;
+                let res = File::create(&target_path)
+                    .map_err(|err| {
+                        std::io::Error::new(
+                            err.kind(),
+                            format!("Cannot create {}: {}", target_path.display(), err),
+                        )
+                    })
+                    .and_then(|mut output_file| std::io::copy(&mut p2, &mut output_file))
+                    .and_then(|_| {
+                        let mut durability =
+                            DurabilityTracker::new(options.durability, &target_path, pc.clone());
+                        durability.entry_written(&target_path, true)?;
+                        durability.finish()
+                    });
+                match res {
                     Ok(_) => {
                         log::info!("Successfully written file {:?}", target_path);
                         Ok(())
@@ -327,35 +659,139 @@ impl PipeDownloader {
                     }
                 }
             };
-            match res {
+            let events = pc.lock().unwrap().events.clone();
+            let mut t2 = Some(t2);
+            let res = match res {
+                Ok(()) if verify_checksum => {
+                    // decoder verifies the checksum after reading the whole download
+                    t2.take().unwrap().join().unwrap();
+                    match pc.lock().unwrap().error.clone() {
+                        Some(err) => Err(std::io::Error::other(err.to_string())),
+                        None => Ok(()),
+                    }
+                }
+                res => res,
+            };
+            let outcome = match res {
                 Ok(_) => {
                     pc.lock().unwrap().stop_requested = true;
                     for t1 in threads {
                         t1.join().unwrap();
                     }
-                    t2.join().unwrap();
-                    pc.lock().unwrap().finish_time = Some(TimePair::now());
+                    if let Some(t2) = t2 {
+                        t2.join().unwrap();
+                    }
+                    let mut pc = pc.lock().unwrap();
+                    if let Some(error) = pc.error.clone() {
+                        // other stage failed after the last stage read everything it needed
+                        pc.error_time = Some(Instant::now());
+                        Err(error)
+                    } else {
+                        // tar reader stops at end of archive marker, padding after it is not decoded
+                        pc.total_unpack_size = Some(pc.total_unpacked);
+                        pc.total_unpack_size_estimated = false;
+                        pc.finish_time = Some(TimePair::now());
+                        Ok(pc.summary())
+                    }
                 }
                 Err(err) => {
-                    pc.lock().unwrap().error_message = Some(format!("{err:?}"));
-                    pc.lock().unwrap().stop_requested = true;
+                    let unpack_error = DownloadError::from_io(&err);
+                    stage_failed(&pc, PipelineStage::Unpack, unpack_error.clone());
                     for t1 in threads {
                         t1.join().unwrap();
                     }
-                    t2.join().unwrap();
-                    pc.lock().unwrap().error_time = Some(Instant::now());
+                    if let Some(t2) = t2 {
+                        t2.join().unwrap();
+                    }
+                    let mut pc = pc.lock().unwrap();
+                    pc.error_time = Some(Instant::now());
+                    Err(match (pc.error.clone(), pc.stopped_by_user) {
+                        (Some(error), _) => error,
+                        (None, true) => DownloadError::StoppedByUser,
+                        (None, false) => unpack_error,
+                    })
                 }
+            };
+            {
+                let mut pc = pc.lock().unwrap();
+                pc.record_history(true);
+                pc.outcome = Some(outcome.clone());
             }
+            events.emit(PipeDownloaderEvent::Finished {
+                success: outcome.is_ok(),
+                summary: outcome.as_ref().ok().cloned().map(Box::new),
+                error: outcome.err(),
+            });
         }));
 
         Ok(())
     }
 
+    fn check_free_disk_space(&self, target_path: &Path) -> anyhow::Result<()> {
+        let (required, estimated) = {
+            let pc = self.get_progress_guard();
+            match (pc.total_unpack_size, pc.total_download_size) {
+                (Some(unpack_size), _) => (unpack_size, pc.total_unpack_size_estimated),
+                // compressed size is the lower bound of unpacked size
+                (None, Some(download_size)) => (download_size, true),
+                (None, None) => return Ok(()),
+            }
+        };
+        let available = available_disk_space(target_path)?;
+        self.get_progress_guard().free_disk_space = Some(available);
+        let required_with_reserve =
+            (required as u64).saturating_add(self.options.min_free_disk_space.unwrap_or(0));
+        if available < required_with_reserve {
+            return Err(DownloadError::InsufficientDiskSpace {
+                path: target_path.display().to_string(),
+                required: required_with_reserve,
+                available,
+            }
+            .into());
+        }
+        log::info!(
+            "Free disk space: {}, required {}{}",
+            bytes_to_human(available as usize),
+            if estimated { "at least " } else { "" },
+            bytes_to_human(required_with_reserve as usize)
+        );
+        Ok(())
+    }
+
     /// Returns serializable [PipeDownloaderProgress] object
     pub fn get_progress(self: &PipeDownloader) -> PipeDownloaderProgress {
         self.get_progress_guard().progress()
     }
 
+    /// Returns downsampled speed history of the whole run.
+    /// Range is given in seconds since start, max_points limits number of returned points.
+    pub fn get_history(
+        self: &PipeDownloader,
+        from_sec: Option<f64>,
+        to_sec: Option<f64>,
+        max_points: Option<usize>,
+    ) -> PipeDownloaderHistory {
+        let pc = self.get_progress_guard();
+        PipeDownloaderHistory {
+            start_time: pc.start_time.to_utc().unwrap(),
+            interval_sec: pc.history.interval().as_secs_f64(),
+            points: pc.history.query(from_sec, to_sec, max_points),
+        }
+    }
+
+    /// Add metrics of this download to the Prometheus output, labeled with url and given labels
+    pub fn add_metrics(
+        self: &PipeDownloader,
+        metrics: &mut PrometheusMetrics,
+        labels: &[(&str, &str)],
+    ) {
+        let pc = self.get_progress_guard();
+        let url = pc.download_url.clone().unwrap_or_else(|| self.url.clone());
+        let mut labels = labels.to_vec();
+        labels.push(("url", &url));
+        metrics.add_progress(&pc, &labels);
+    }
+
     /// Returns progress as human readable line
     pub fn get_progress_human_line(self: &PipeDownloader) -> String {
         let progress = self.get_progress_guard();
@@ -379,15 +815,29 @@ impl PipeDownloader {
         } else {
             "".to_string()
         };
+        let unpack_percent_string = if let Some(total_unpack_size) = progress.total_unpack_size {
+            format!(
+                " [{}{:.2}%]",
+                if progress.total_unpack_size_estimated {
+                    "~"
+                } else {
+                    ""
+                },
+                (progress.total_unpacked as f64 / total_unpack_size as f64) * 100.0
+            )
+        } else {
+            "".to_string()
+        };
 
         format!(
-            "Downloaded: {} [{}/s now: {}/s], Unpack: {} [{}/s now: {}/s] - {} {}",
+            "Downloaded: {} [{}/s now: {}/s], Unpack: {}{} [{}/s now: {}/s] - {} {}",
             bytes_to_human(
                 progress.total_downloaded + progress.chunk_downloaded.iter().sum::<usize>()
             ),
             bytes_to_human(progress.get_download_speed()),
             bytes_to_human(progress.progress_buckets_download.get_speed()),
             bytes_to_human(progress.total_unpacked),
+            unpack_percent_string,
             bytes_to_human(progress.get_unpack_speed()),
             bytes_to_human(progress.progress_buckets_unpack.get_speed()),
             eta_string,
@@ -395,34 +845,127 @@ impl PipeDownloader {
         )
     }
 
-    /// Cancel download
+    /// Cancel download, outcome of the download will be [DownloadError::StoppedByUser]
     pub fn signal_stop(self: &PipeDownloader) {
-        let mut pc = self
-            .progress_context
-            .lock()
-            .expect("Failed to lock progress context");
-        pc.stop_requested = true;
+        let events = {
+            let mut pc = self
+                .progress_context
+                .lock()
+                .expect("Failed to lock progress context");
+            if pc.outcome.is_none() {
+                pc.stopped_by_user = true;
+            }
+            pc.stop_requested = true;
+            pc.control_log.push(ControlAction::Stop { graceful: false });
+            pc.events.clone()
+        };
+        events.emit(PipeDownloaderEvent::StopRequested { graceful: false });
+    }
+
+    /// Stop after the file currently being extracted is written, so no partially written file is left.
+    /// Outcome of the download will be [DownloadError::StoppedByUser].
+    /// Single file downloads and paused downloads are stopped immediately.
+    pub fn signal_graceful_stop(self: &PipeDownloader) {
+        let events = {
+            let mut pc = self
+                .progress_context
+                .lock()
+                .expect("Failed to lock progress context");
+            if pc.outcome.is_none() {
+                pc.stopped_by_user = true;
+            }
+            let is_tar = pc.archive.as_ref().map(|a| a.is_tar).unwrap_or(false);
+            if is_tar && !pc.paused {
+                pc.graceful_stop_requested = true;
+            } else {
+                pc.stop_requested = true;
+            }
+            pc.control_log.push(ControlAction::Stop { graceful: true });
+            pc.events.clone()
+        };
+        events.emit(PipeDownloaderEvent::StopRequested { graceful: true });
     }
 
     ///Downloader supports pausing and resuming, you can call this method to pause download
     pub fn pause_download(self: &PipeDownloader) {
-        let mut pc = self
-            .progress_context
-            .lock()
-            .expect("Failed to lock progress context");
-        pc.paused = true;
+        self.set_paused(true);
     }
 
     ///Downloader supports pausing and resuming, you can call this method to resume download
     pub fn resume_download(self: &PipeDownloader) {
-        let mut pc = self
-            .progress_context
-            .lock()
-            .expect("Failed to lock progress context");
-        pc.paused = false;
+        self.set_paused(false);
+    }
+
+    fn set_paused(self: &PipeDownloader, paused: bool) {
+        let (event, events) = {
+            let mut pc = self
+                .progress_context
+                .lock()
+                .expect("Failed to lock progress context");
+            pc.paused_low_disk_space = false;
+            pc.control_log.push(if paused {
+                ControlAction::Pause
+            } else {
+                ControlAction::Resume
+            });
+            (pc.set_paused(paused), pc.events.clone())
+        };
+        if let Some(event) = event {
+            events.emit(event);
+        }
     }
 
-    /// Check if download is finished
+    /// Change speed limit per download thread while downloading, None or 0 removes the limit
+    pub fn set_max_download_speed(self: &PipeDownloader, max_download_speed: Option<usize>) {
+        let max_download_speed = max_download_speed.filter(|speed| *speed > 0);
+        let events = {
+            let mut pc = self
+                .progress_context
+                .lock()
+                .expect("Failed to lock progress context");
+            pc.max_download_speed = max_download_speed;
+            pc.control_log
+                .push(ControlAction::SetSpeedLimit { max_download_speed });
+            pc.events.clone()
+        };
+        events.emit(PipeDownloaderEvent::SpeedLimitChanged { max_download_speed });
+    }
+
+    /// Subscribe to [PipeDownloaderEvent]s emitted from now on.
+    /// Receiver that falls more than 1024 events behind gets `Lagged` error and skips the oldest ones.
+    pub fn subscribe(
+        self: &PipeDownloader,
+    ) -> tokio::sync::broadcast::Receiver<PipeDownloaderEvent> {
+        self.get_progress_guard().events.subscribe()
+    }
+
+    /// Register callback called for every [PipeDownloaderEvent].
+    /// Use [PipeDownloaderOptions::start_download_with_observer] to receive events from the very start.
+    pub fn add_observer(self: &PipeDownloader, observer: Arc<dyn PipeDownloaderObserver>) {
+        self.get_progress_guard().events.add_observer(observer);
+    }
+
+    /// Result of the download, None while download is still running
+    pub fn outcome(self: &PipeDownloader) -> Option<Result<DownloadSummary, DownloadError>> {
+        self.get_progress_guard().outcome.clone()
+    }
+
+    /// Wait until the pipeline finishes and return its outcome
+    pub async fn wait(self: &PipeDownloader) -> Result<DownloadSummary, DownloadError> {
+        loop {
+            if let Some(outcome) = self.outcome() {
+                return outcome;
+            }
+            if !self.download_started {
+                return Err(DownloadError::Other {
+                    message: "Download not started".to_string(),
+                });
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Check if download is finished (successfully or not), see [PipeDownloader::outcome]
     pub fn is_finished(self: &PipeDownloader) -> bool {
         if let Some(thread_last_stage) = self.thread_last_stage.as_ref() {
             thread_last_stage.is_finished()
@@ -431,7 +974,7 @@ impl PipeDownloader {
         }
     }
 
-    fn get_progress_guard(self: &PipeDownloader) -> MutexGuard<InternalProgress> {
+    fn get_progress_guard(self: &PipeDownloader) -> MutexGuard<'_, InternalProgress> {
         self.progress_context
             .lock()
             .expect("Failed to lock progress context")
@@ -442,3 +985,49 @@ impl PipeDownloader {
         self.download_started
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(
+        total_unpack_size: Option<usize>,
+        min_free_disk_space: Option<u64>,
+    ) -> Result<(), DownloadError> {
+        let pd = PipeDownloader::new(
+            "http://127.0.0.1/archive.tar.gz",
+            None,
+            PipeDownloaderOptions {
+                min_free_disk_space,
+                ..Default::default()
+            },
+        );
+        pd.get_progress_guard().total_unpack_size = total_unpack_size;
+        pd.check_free_disk_space(&std::env::temp_dir())
+            .map_err(|err| err.downcast::<DownloadError>().unwrap())
+    }
+
+    #[test]
+    fn test_free_disk_space_check() {
+        // nothing is known about the size
+        assert!(check(None, Some(u64::MAX / 2)).is_ok());
+        assert!(check(Some(1), None).is_ok());
+        assert!(matches!(
+            check(Some(usize::MAX / 2), None),
+            Err(DownloadError::InsufficientDiskSpace { .. })
+        ));
+        // reserve is added to the required size
+        assert!(matches!(
+            check(Some(1), Some(u64::MAX / 2)),
+            Err(DownloadError::InsufficientDiskSpace { .. })
+        ));
+        // huge reserve doesn't overflow
+        assert!(matches!(
+            check(Some(usize::MAX), Some(u64::MAX)),
+            Err(DownloadError::InsufficientDiskSpace {
+                required: u64::MAX,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::options::DurabilityMode;
+use crate::pipe_progress::InternalProgress;
+
+/// Keeps track of written files and directories and syncs them according to [DurabilityMode]
+pub struct DurabilityTracker {
+    mode: DurabilityMode,
+    dst: PathBuf,
+    directories: BTreeSet<PathBuf>,
+    #[cfg(not(unix))]
+    files: Vec<PathBuf>,
+    pc: Arc<Mutex<InternalProgress>>,
+}
+
+fn fsync_path(path: &Path) -> std::io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+#[cfg(target_os = "linux")]
+fn sync_filesystem(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let dir = File::open(path)?;
+    // SAFETY: syncfs is called with valid file descriptor owned by dir
+    if unsafe { libc::syncfs(dir.as_raw_fd()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn sync_filesystem(_path: &Path) -> std::io::Result<()> {
+    // SAFETY: sync has no arguments and cannot fail
+    unsafe { libc::sync() };
+    Ok(())
+}
+
+impl DurabilityTracker {
+    pub fn new(mode: DurabilityMode, dst: &Path, pc: Arc<Mutex<InternalProgress>>) -> Self {
+        Self {
+            mode,
+            dst: dst.to_path_buf(),
+            directories: BTreeSet::new(),
+            #[cfg(not(unix))]
+            files: Vec::new(),
+            pc,
+        }
+    }
+
+    fn add_sync_time(&self, start: Instant) {
+        self.pc.lock().unwrap().sync_time += start.elapsed();
+    }
+
+    /// Called after entry is written to target path
+    pub fn entry_written(&mut self, target: &Path, is_file: bool) -> std::io::Result<()> {
+        if self.mode == DurabilityMode::None {
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            // parents of already known directory are known as well
+            if !self.directories.contains(parent) {
+                for dir in parent.ancestors() {
+                    if !dir.starts_with(&self.dst) || !self.directories.insert(dir.to_path_buf()) {
+                        break;
+                    }
+                }
+            }
+        }
+        if !is_file {
+            if target.is_dir() {
+                self.directories.insert(target.to_path_buf());
+            }
+            return Ok(());
+        }
+        match self.mode {
+            DurabilityMode::FsyncFiles => {
+                let start = Instant::now();
+                fsync_path(target)?;
+                self.add_sync_time(start);
+            }
+            DurabilityMode::SyncAtEnd => {
+                #[cfg(not(unix))]
+                self.files.push(target.to_path_buf());
+            }
+            DurabilityMode::None => {}
+        }
+        Ok(())
+    }
+
+    /// Flush everything to disk, returns after durability is reached
+    pub fn finish(self) -> std::io::Result<()> {
+        if self.mode == DurabilityMode::None {
+            return Ok(());
+        }
+        self.pc.lock().unwrap().syncing = true;
+        let start = Instant::now();
+        log::info!("Syncing unpacked data to disk ({:?})", self.mode);
+        if self.mode == DurabilityMode::SyncAtEnd {
+            #[cfg(unix)]
+            sync_filesystem(&self.dst)?;
+            #[cfg(not(unix))]
+            for file in &self.files {
+                fsync_path(file)?;
+            }
+        }
+        // directory entries have to be synced to make new file names durable
+        #[cfg(unix)]
+        for dir in self.directories.iter().rev() {
+            fsync_path(dir)?;
+        }
+        #[cfg(unix)]
+        if let Some(parent) = self.dst.parent() {
+            fsync_path(if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            })?;
+        }
+        self.add_sync_time(start);
+        let mut pc = self.pc.lock().unwrap();
+        pc.syncing = false;
+        log::info!("Sync finished in {:?}", pc.sync_time);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    fn write_entry(tracker: &mut DurabilityTracker, target: &Path) {
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::write(target, b"data").unwrap();
+        tracker.entry_written(target, true).unwrap();
+    }
+
+    #[test]
+    fn test_none_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("out");
+        let pc = Arc::new(Mutex::new(InternalProgress::default()));
+        let mut tracker = DurabilityTracker::new(DurabilityMode::None, &dst, pc.clone());
+        // nothing is touched, not even missing files
+        tracker
+            .entry_written(&dst.join("missing.txt"), true)
+            .unwrap();
+        assert!(tracker.directories.is_empty());
+        tracker.finish().unwrap();
+        assert_eq!(pc.lock().unwrap().sync_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_fsync_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("out");
+        let pc = Arc::new(Mutex::new(InternalProgress::default()));
+        let mut tracker = DurabilityTracker::new(DurabilityMode::FsyncFiles, &dst, pc.clone());
+        write_entry(&mut tracker, &dst.join("a/b/file.txt"));
+        write_entry(&mut tracker, &dst.join("a/other.txt"));
+        fs::create_dir_all(dst.join("empty")).unwrap();
+        tracker.entry_written(&dst.join("empty"), false).unwrap();
+
+        // every directory holding new names is tracked, nothing outside of dst
+        let expected: BTreeSet<PathBuf> = [
+            dst.clone(),
+            dst.join("a"),
+            dst.join("a/b"),
+            dst.join("empty"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(tracker.directories, expected);
+        assert!(pc.lock().unwrap().sync_time > Duration::ZERO);
+
+        // file has to exist when it is synced
+        assert_eq!(
+            tracker
+                .entry_written(&dst.join("a/missing.txt"), true)
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        tracker.finish().unwrap();
+        let pc = pc.lock().unwrap();
+        assert!(!pc.syncing);
+    }
+
+    #[test]
+    fn test_sync_at_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("out");
+        let pc = Arc::new(Mutex::new(InternalProgress::default()));
+        let mut tracker = DurabilityTracker::new(DurabilityMode::SyncAtEnd, &dst, pc.clone());
+        write_entry(&mut tracker, &dst.join("file.txt"));
+        // files are not synced one by one
+        assert_eq!(pc.lock().unwrap().sync_time, Duration::ZERO);
+        assert!(tracker.directories.contains(&dst));
+
+        tracker.finish().unwrap();
+        let pc = pc.lock().unwrap();
+        assert!(pc.sync_time > Duration::ZERO);
+        assert!(!pc.syncing);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_finish_fails_on_removed_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("out");
+        let pc = Arc::new(Mutex::new(InternalProgress::default()));
+        let mut tracker = DurabilityTracker::new(DurabilityMode::SyncAtEnd, &dst, pc);
+        write_entry(&mut tracker, &dst.join("sub/file.txt"));
+        fs::remove_dir_all(dst.join("sub")).unwrap();
+        // durability can't be reached when directory entry can't be synced
+        assert!(tracker.finish().is_err());
+    }
+}
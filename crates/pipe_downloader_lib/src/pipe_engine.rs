@@ -1,4 +1,6 @@
-use reqwest::header::{HeaderValue, CONTENT_LENGTH};
+use reqwest::header::{
+    HeaderValue, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, ETAG, LAST_MODIFIED,
+};
 use reqwest::{header, StatusCode};
 
 use std::io::Read;
@@ -9,12 +11,19 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 
 use crate::options::PipeDownloaderOptions;
+use crate::pipe_error::DownloadError;
+use crate::pipe_events::PipeDownloaderEvent;
+use crate::pipe_format::{
+    detect_archive, gzip_content_size, lz4_content_size, xz_content_size, xz_index_size,
+    zstd_content_size, ArchiveInfo, CompressionFormat, UnpackSizeSource, FRAME_HEADER_PROBE_SIZE,
+    GZIP_TRAILER_SIZE, XZ_FOOTER_SIZE,
+};
 use anyhow::anyhow;
 
 use reqwest::blocking::Response;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::pipe_progress::{DownloadChunkProgress, InternalProgress};
+use crate::pipe_progress::{DownloadChunkProgress, DownloadThreadStats, InternalProgress};
 use crate::pipe_utils::bytes_to_human;
 use crate::pipe_wrapper::DataChunk;
 
@@ -23,14 +32,16 @@ fn download_chunk(
     thread_no: usize,
     progress_context: Arc<Mutex<InternalProgress>>,
     range: &std::ops::Range<usize>,
-    max_speed: Option<usize>,
     response: &mut reqwest::blocking::Response,
 ) -> anyhow::Result<Vec<u8>> {
     let mut buf_vec: Vec<u8> = Vec::with_capacity(range.end - range.start);
 
     let mut buf = vec![0; 1024 * 1024];
     let mut total_downloaded: usize = 0;
-    let start_time = std::time::Instant::now();
+    // limit can be changed while downloading, throttling starts over when it does
+    let mut max_speed = progress_context.lock().unwrap().max_download_speed;
+    let mut throttle_start = Instant::now();
+    let mut throttle_downloaded: usize = 0;
     loop {
         let left_to_download = (range.end - range.start) - total_downloaded;
         let max_buf_size = std::cmp::min(buf.len(), left_to_download);
@@ -42,6 +53,7 @@ fn download_chunk(
             return Err(anyhow!("Unexpected end of file"));
         }
         total_downloaded += n;
+        throttle_downloaded += n;
 
         buf_vec.extend_from_slice(&buf[..n]);
         {
@@ -53,26 +65,53 @@ fn download_chunk(
             if let Some(cd) = progress_context.chunk_downloaded.get_mut(thread_no) {
                 *cd += n;
             }
+            if let Some(stats) = progress_context.thread_stats.get_mut(thread_no) {
+                stats.bytes_downloaded += n;
+                stats.speed_buckets.add_bytes(n);
+            }
             progress_context.progress_buckets_download.add_bytes(n);
-            if progress_context.paused {
+            // without range requests the connection can't be reopened, so it is kept while paused
+            if progress_context.paused && progress_context.server_chunk_support {
                 return Err(anyhow::anyhow!("Download paused"));
             }
             if progress_context.stop_requested {
                 return Err(anyhow::anyhow!("Stop requested"));
             }
+            if progress_context.max_download_speed != max_speed {
+                max_speed = progress_context.max_download_speed;
+                throttle_start = Instant::now();
+                throttle_downloaded = 0;
+            }
+        }
+        while progress_context.lock().unwrap().paused {
+            if progress_context.lock().unwrap().stop_requested {
+                return Err(anyhow::anyhow!("Stop requested"));
+            }
+            std::thread::sleep(Duration::from_millis(100));
         }
         //Speed throttling is not perfect by any means, but it's good enough for now
-        if let Some(max_speed) = max_speed {
+        while let Some(speed) = max_speed {
             let should_take_time =
-                Duration::from_secs_f64(total_downloaded as f64 / max_speed as f64);
-            log::debug!("Should take time: {:?}", should_take_time);
-            loop {
-                let elapsed = std::time::Instant::now().duration_since(start_time);
-                if should_take_time > elapsed {
-                    std::thread::sleep(Duration::from_millis(1));
-                } else {
-                    break;
-                }
+                Duration::from_secs_f64(throttle_downloaded as f64 / speed as f64);
+            let elapsed = throttle_start.elapsed();
+            if should_take_time <= elapsed {
+                break;
+            }
+            std::thread::sleep(std::cmp::min(
+                should_take_time - elapsed,
+                Duration::from_millis(50),
+            ));
+            let progress_context = progress_context.lock().unwrap();
+            if progress_context.paused && progress_context.server_chunk_support {
+                return Err(anyhow::anyhow!("Download paused"));
+            }
+            if progress_context.stop_requested {
+                return Err(anyhow::anyhow!("Stop requested"));
+            }
+            if progress_context.max_download_speed != max_speed {
+                max_speed = progress_context.max_download_speed;
+                throttle_start = Instant::now();
+                throttle_downloaded = 0;
             }
         }
     }
@@ -92,10 +131,80 @@ fn download_chunk(
     Ok(buf_vec)
 }
 
-fn request_chunk(
+/// Validators of the remote file taken from the first response,
+/// every following response is compared with them to detect that the file changed
+#[derive(Debug, Clone, Default)]
+pub struct SourceIdentity {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub total_length: Option<usize>,
+}
+
+pub(crate) fn header_string(response: &Response, name: header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+impl SourceIdentity {
+    pub(crate) fn from_response(response: &Response, total_length: Option<usize>) -> Self {
+        Self {
+            etag: header_string(response, ETAG),
+            last_modified: header_string(response, LAST_MODIFIED),
+            total_length,
+        }
+    }
+
+    fn check(&self, response: &Response) -> Result<(), DownloadError> {
+        let compare = |name: &str, expected: &Option<String>, found: Option<String>| match (
+            expected, found,
+        ) {
+            (Some(expected), Some(found)) if *expected != found => {
+                Err(DownloadError::SourceChanged {
+                    message: format!("{name} changed from {expected} to {found}"),
+                })
+            }
+            _ => Ok(()),
+        };
+        compare("ETag", &self.etag, header_string(response, ETAG))?;
+        compare(
+            "Last-Modified",
+            &self.last_modified,
+            header_string(response, LAST_MODIFIED),
+        )?;
+        // Content-Range: bytes start-end/total
+        let range_total = header_string(response, CONTENT_RANGE)
+            .and_then(|range| range.rsplit('/').next().map(|s| s.trim().to_string()))
+            .and_then(|total| usize::from_str(&total).ok());
+        if let (Some(expected), Some(found)) = (self.total_length, range_total) {
+            if expected != found {
+                return Err(DownloadError::SourceChanged {
+                    message: format!("size changed from {expected} to {found}"),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn check_status(response: &Response) -> Result<(), DownloadError> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(DownloadError::HttpStatus {
+            status: response.status().as_u16(),
+            url: response.url().to_string(),
+        })
+    }
+}
+
+pub(crate) fn request_chunk(
     url: &str,
     client: &reqwest::blocking::Client,
     range: &std::ops::Range<usize>,
+    source: &SourceIdentity,
 ) -> anyhow::Result<Response> {
     log::debug!(
         "Downloading chunk: range {:?} / {}",
@@ -104,20 +213,15 @@ fn request_chunk(
     );
 
     let header = format!("bytes={}-{}", range.start, range.end - 1);
-    const VERSION: &str = env!("CARGO_PKG_VERSION");
-
-    let mut headers = header::HeaderMap::new();
-    headers.insert(
-        "User-Agent",
-        HeaderValue::from_str(&format!("pipe_downloader/{VERSION}")).unwrap(),
-    );
 
     let response = client
         .get(url)
-        .headers(headers)
+        .headers(user_agent_headers())
         .header("Range", header)
         .send()?;
 
+    check_status(&response)?;
+    source.check(&response)?;
     let status = response.status();
     let content_length = response
         .headers()
@@ -149,13 +253,84 @@ fn request_chunk(
     Ok(response)
 }
 
+fn request_range_bytes(
+    url: &str,
+    client: &reqwest::blocking::Client,
+    range: std::ops::Range<usize>,
+    source: &SourceIdentity,
+) -> anyhow::Result<Vec<u8>> {
+    let mut response = request_chunk(url, client, &range, source)?;
+    let mut buf = Vec::with_capacity(range.end - range.start);
+    response.read_to_end(&mut buf)?;
+    if buf.len() != range.end - range.start {
+        return Err(anyhow!("unexpected content length: {}", buf.len()));
+    }
+    Ok(buf)
+}
+
+/// Read unpacked size from format metadata using small range requests.
+/// Returns size, source of the information and flag if the size is only estimated.
+pub(crate) fn probe_unpack_size(
+    url: &str,
+    client: &reqwest::blocking::Client,
+    format: CompressionFormat,
+    total_length: usize,
+    source: &SourceIdentity,
+) -> anyhow::Result<Option<(usize, UnpackSizeSource, bool)>> {
+    let header_range = 0..std::cmp::min(FRAME_HEADER_PROBE_SIZE, total_length);
+    let res = match format {
+        CompressionFormat::Zstd => {
+            let header = request_range_bytes(url, client, header_range, source)?;
+            zstd_content_size(&header).map(|s| (s, UnpackSizeSource::ZstdFrameHeader, false))
+        }
+        CompressionFormat::Lz4 => {
+            let header = request_range_bytes(url, client, header_range, source)?;
+            lz4_content_size(&header).map(|s| (s, UnpackSizeSource::Lz4FrameHeader, false))
+        }
+        CompressionFormat::Gzip => match total_length.checked_sub(GZIP_TRAILER_SIZE) {
+            Some(trailer_start) => {
+                let trailer =
+                    request_range_bytes(url, client, trailer_start..total_length, source)?;
+                gzip_content_size(&trailer, total_length as u64)
+                    .map(|(s, ambiguous)| (s, UnpackSizeSource::GzipTrailer, ambiguous))
+            }
+            // too short to be valid, decoder reports the error
+            None => None,
+        },
+        CompressionFormat::Xz => {
+            let footer_start = match total_length.checked_sub(XZ_FOOTER_SIZE) {
+                Some(footer_start) => footer_start,
+                None => return Ok(None),
+            };
+            let footer = request_range_bytes(url, client, footer_start..total_length, source)?;
+            match xz_index_size(&footer) {
+                Some(index_size) if index_size + XZ_FOOTER_SIZE < total_length => {
+                    let index_end = total_length - XZ_FOOTER_SIZE;
+                    let index = request_range_bytes(
+                        url,
+                        client,
+                        index_end - index_size..index_end,
+                        source,
+                    )?;
+                    xz_content_size(&index, total_length as u64)
+                        .map(|s| (s, UnpackSizeSource::XzIndex, false))
+                }
+                _ => None,
+            }
+        }
+        CompressionFormat::Bzip2 => None,
+    };
+    Ok(res.map(|(size, source, estimated)| (size as usize, source, estimated)))
+}
+
 pub fn decode_loop<T: Read>(
     progress_context: Arc<Mutex<InternalProgress>>,
     options: &PipeDownloaderOptions,
     decoder: &mut T,
-    send: std::sync::mpsc::SyncSender<DataChunk>,
+    send: &std::sync::mpsc::SyncSender<DataChunk>,
 ) -> anyhow::Result<()> {
     let mut unpacked_size = 0;
+    let mut reached_eof = false;
     loop {
         let mut buf = vec![0u8; options.chunk_size_decoder];
         let bytes_read = match decoder.read(&mut buf) {
@@ -169,12 +344,14 @@ pub fn decode_loop<T: Read>(
             }
         };
         if bytes_read == 0 {
+            reached_eof = true;
             break;
         }
         unpacked_size += bytes_read;
         {
             let mut progress = progress_context.lock().unwrap();
             progress.total_unpacked = unpacked_size;
+            progress.update_unpack_size_estimate();
             progress.progress_buckets_unpack.add_bytes(bytes_read);
             if progress.stop_requested {
                 break;
@@ -191,7 +368,19 @@ pub fn decode_loop<T: Read>(
             data: buf,
             range: unpacked_size - bytes_read..unpacked_size,
         };
+        let send_start = Instant::now();
         send.send(data_chunk)?;
+        progress_context
+            .lock()
+            .unwrap()
+            .stage_blocked
+            .decode_output_sec += send_start.elapsed().as_secs_f64();
+    }
+    if reached_eof {
+        // whole stream is decoded, so the size is known exactly now
+        let mut progress = progress_context.lock().unwrap();
+        progress.total_unpack_size = Some(unpacked_size);
+        progress.total_unpack_size_estimated = false;
     }
     log::info!("Finishing decode loop");
     Ok(())
@@ -204,43 +393,90 @@ pub struct DownloadLoopInitResult {
     pub total_length: usize,
     pub use_chunks: bool,
     pub download_url: String,
+    /// Url after following redirects
+    pub final_url: String,
+    pub archive: Option<ArchiveInfo>,
+    pub source: SourceIdentity,
     pub threads_to_spawn: usize,
 }
 
+pub(crate) fn user_agent_headers() -> header::HeaderMap {
+    const VERSION: &str = env!("CARGO_PKG_VERSION");
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        "User-Agent",
+        HeaderValue::from_str(&format!("pipe_downloader/{VERSION}")).unwrap(),
+    );
+    headers
+}
+
+/// `.link` extension means that the file contains url of the file that we need to download
+pub(crate) fn resolve_link(
+    client: &reqwest::blocking::Client,
+    original_url: &str,
+) -> anyhow::Result<String> {
+    if !original_url.ends_with(".link") {
+        return Ok(original_url.to_string());
+    }
+    let url = client.get(original_url).send()?.text()?.trim().to_string();
+    if url.is_empty() {
+        return Err(anyhow::anyhow!("Empty url from link"));
+    }
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(anyhow::anyhow!(
+            "Wrong url, should start from http:// or https://"
+        ));
+    }
+    log::info!("Got download address from link: {}", url);
+    Ok(url)
+}
+
+/// Check if server supports partial content with small range request
+pub(crate) fn supports_partial_content(
+    client: &reqwest::blocking::Client,
+    url: &str,
+) -> anyhow::Result<bool> {
+    let response = client
+        .head(url)
+        .headers(user_agent_headers())
+        .header("Range", format!("bytes={}-{}", 1000, 2000))
+        .send()?;
+    Ok(response.status() == StatusCode::PARTIAL_CONTENT)
+}
+
 pub fn init_download_loop(
     thread_count: usize,
     options: PipeDownloaderOptions,
     progress_context: Arc<Mutex<InternalProgress>>,
-    download_url: &str,
+    original_url: &str,
 ) -> anyhow::Result<DownloadLoopInitResult> {
     let mut use_chunks = !options.force_no_chunks;
     let client = reqwest::blocking::Client::new();
 
-    //.link extension means that the files point to the file that we need to download
-    let download_url = if download_url.ends_with(".link") {
-        let url = client.get(download_url).send()?.text()?.trim().to_string();
-        if url.is_empty() {
-            return Err(anyhow::anyhow!("Empty url from link"));
-        }
-        if !url.starts_with("http://") && !url.starts_with("https://") {
-            return Err(anyhow::anyhow!(
-                "Wrong url, should start from http:// or https://"
-            ));
-        }
-        log::info!("Got download address from link: {}", url);
-        url
-    } else {
-        download_url.to_string()
-    };
+    let download_url = resolve_link(&client, original_url)?;
     progress_context.lock().unwrap().download_url = Some(download_url.clone());
-    const VERSION: &str = env!("CARGO_PKG_VERSION");
-    let mut headers = header::HeaderMap::new();
-    headers.insert(
-        "User-Agent",
-        HeaderValue::from_str(&format!("pipe_downloader/{VERSION}")).unwrap(),
-    );
 
-    let response = client.head(&download_url).headers(headers.clone()).send()?;
+    let response = client
+        .head(&download_url)
+        .headers(user_agent_headers())
+        .send()?;
+    check_status(&response)?;
+
+    let final_url = response.url().to_string();
+    if final_url != download_url {
+        log::info!("Download url redirected to: {}", final_url);
+    }
+    let content_disposition = response
+        .headers()
+        .get(CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let archive = detect_archive(
+        content_disposition.as_deref(),
+        &[&final_url, &download_url, original_url],
+    );
+    log::info!("Detected archive: {:?}", archive);
+    progress_context.lock().unwrap().archive = archive.clone();
 
     let total_length = match response
         .headers()
@@ -259,6 +495,8 @@ pub fn init_download_loop(
     };
 
     progress_context.lock().unwrap().total_download_size = total_length;
+    let source = SourceIdentity::from_response(&response, total_length);
+    log::debug!("Source identity: {:?}", source);
 
     if total_length
         .map(|total_length| total_length == 0)
@@ -277,32 +515,57 @@ pub fn init_download_loop(
         use_chunks = false;
     }
 
-    //check if server supports partial content
-    if use_chunks {
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            "User-Agent",
-            HeaderValue::from_str(&format!("pipe_downloader/{VERSION}")).unwrap(),
-        );
-        headers.insert(
-            "Range",
-            HeaderValue::from_str(&format!("bytes={}-{}", 1000, 2000)).unwrap(),
-        );
-
-        let response = client.head(&download_url).headers(headers.clone()).send()?;
-
-        if response.status() != StatusCode::PARTIAL_CONTENT {
-            log::warn!("Server does not support partial content, falling back to single request. No retries after connection error will be possible");
-            use_chunks = false;
+    if use_chunks && !supports_partial_content(&client, &download_url)? {
+        log::warn!("Server does not support partial content, falling back to single request. No retries after connection error will be possible");
+        use_chunks = false;
+    }
+    if let (true, Some(total_length), Some(archive)) = (use_chunks, total_length, &archive) {
+        let format = archive.format;
+        match probe_unpack_size(&download_url, &client, format, total_length, &source) {
+            Ok(Some((unpack_size, source, estimated))) => {
+                log::info!(
+                    "Unpack size from {:?}: {}{}",
+                    source,
+                    bytes_to_human(unpack_size),
+                    if estimated { " (estimated)" } else { "" }
+                );
+                let mut pc = progress_context.lock().unwrap();
+                pc.total_unpack_size = Some(unpack_size);
+                pc.total_unpack_size_source = Some(source);
+                pc.total_unpack_size_estimated = estimated;
+            }
+            Ok(None) => {
+                log::info!("Unpack size not available in {:?} metadata", format);
+            }
+            Err(err) => {
+                log::warn!("Failed to probe unpack size: {:?}", err);
+            }
         }
     }
+
+    if let Some(archive) = &archive {
+        let (event, events) = {
+            let pc = progress_context.lock().unwrap();
+            let event = PipeDownloaderEvent::FormatDetected {
+                archive: archive.clone(),
+                total_download_size: total_length,
+                total_unpack_size: pc.total_unpack_size,
+                unpack_size_source: pc.total_unpack_size_source,
+                unpack_size_estimated: pc.total_unpack_size_estimated,
+                server_chunk_support: use_chunks,
+            };
+            (event, pc.events.clone())
+        };
+        events.emit(event);
+    }
+
     let thread_count = if use_chunks { thread_count } else { 1 };
 
-    progress_context
-        .lock()
-        .unwrap()
-        .chunk_downloaded
-        .resize(thread_count, 0);
+    {
+        let mut pc = progress_context.lock().unwrap();
+        pc.chunk_downloaded.resize(thread_count, 0);
+        pc.thread_stats = (0..thread_count).map(DownloadThreadStats::new).collect();
+    }
 
     let chunk_size = options.chunk_size_downloader;
 
@@ -331,13 +594,30 @@ pub fn init_download_loop(
         total_length,
         use_chunks,
         download_url,
+        final_url,
+        archive,
+        source,
         threads_to_spawn: thread_count,
     })
 }
 
+fn update_thread_stats(
+    progress_context: &Arc<Mutex<InternalProgress>>,
+    thread_no: usize,
+    update: impl FnOnce(&mut DownloadThreadStats),
+) {
+    if let Some(stats) = progress_context
+        .lock()
+        .unwrap()
+        .thread_stats
+        .get_mut(thread_no)
+    {
+        update(stats);
+    }
+}
+
 pub fn download_loop(
     thread_no: usize,
-    options: PipeDownloaderOptions,
     progress_context: Arc<Mutex<InternalProgress>>,
     send_download_chunks: SyncSender<DataChunk>,
     download_loop_init_result: DownloadLoopInitResult,
@@ -350,19 +630,26 @@ pub fn download_loop(
     let thread_count = download_loop_init_result.threads_to_spawn;
 
     let client = reqwest::blocking::Client::new();
+    let events = progress_context.lock().unwrap().events.clone();
 
-    const VERSION: &str = env!("CARGO_PKG_VERSION");
-    let mut headers = header::HeaderMap::new();
-    headers.insert(
-        "User-Agent",
-        HeaderValue::from_str(&format!("pipe_downloader/{VERSION}")).unwrap(),
-    );
+    let headers = user_agent_headers();
 
+    let source = &download_loop_init_result.source;
     let mut download_response = if !use_chunks {
-        Some(client.get(download_url).headers(headers).send()?)
+        let request_start = Instant::now();
+        let response = client.get(download_url).headers(headers).send()?;
+        update_thread_stats(&progress_context, thread_no, |stats| {
+            stats.requests += 1;
+            stats.time_to_first_byte_ms = Some(request_start.elapsed().as_millis() as u64);
+        });
+        check_status(&response)?;
+        source.check(&response)?;
+        Some(response)
     } else {
         None
     };
+    // next request is made to recover from an error
+    let mut after_error = false;
 
     for chunk_no in 0..chunk_count {
         let max_length = std::cmp::min(chunk_size, total_length - chunk_no * chunk_size);
@@ -373,6 +660,7 @@ pub fn download_loop(
         if chunk_no % thread_count != thread_no {
             continue;
         }
+        let wait_start = Instant::now();
         loop {
             let smallest_unfinished = *progress_context
                 .lock()
@@ -389,6 +677,12 @@ pub fn download_loop(
             }
             break;
         }
+        // thread is too far ahead, downloaded data waits for the decoder
+        progress_context
+            .lock()
+            .unwrap()
+            .stage_blocked
+            .download_output_sec += wait_start.elapsed().as_secs_f64();
         let range = std::ops::Range {
             start: chunk_no * chunk_size,
             end: chunk_no * chunk_size + max_length,
@@ -396,6 +690,9 @@ pub fn download_loop(
 
         {
             let mut progress = progress_context.lock().unwrap();
+            if let Some(stats) = progress.thread_stats.get_mut(thread_no) {
+                stats.current_chunk = Some(chunk_no);
+            }
             progress.current_chunks.insert(
                 chunk_no,
                 DownloadChunkProgress {
@@ -406,14 +703,24 @@ pub fn download_loop(
                 },
             );
         }
+        events.emit(PipeDownloaderEvent::ChunkStarted {
+            chunk_no,
+            thread_no,
+            range_start: range.start,
+            range_end: range.end,
+        });
 
         loop {
-            let progress = { progress_context.lock().unwrap().clone() };
+            // only the flags, the progress is big (history, reports)
+            let (stop_requested, paused) = {
+                let pc = progress_context.lock().unwrap();
+                (pc.stop_requested, pc.paused)
+            };
 
-            if progress.stop_requested {
+            if stop_requested {
                 return Err(anyhow::anyhow!("Stop requested"));
             }
-            if progress.paused {
+            if paused {
                 log::info!("Download still paused...");
                 thread::sleep(Duration::from_secs(5));
                 continue;
@@ -429,7 +736,6 @@ pub fn download_loop(
                     thread_no,
                     progress_context.clone(),
                     &range,
-                    options.max_download_speed,
                     download_response,
                 )
             } else {
@@ -444,14 +750,25 @@ pub fn download_loop(
                     range.clone()
                 };
 
-                match request_chunk(download_url, &client, &new_range) {
+                let request_start = Instant::now();
+                let response = request_chunk(download_url, &client, &new_range, source);
+                update_thread_stats(&progress_context, thread_no, |stats| {
+                    stats.requests += 1;
+                    if after_error {
+                        stats.reconnects += 1;
+                    }
+                    if response.is_ok() {
+                        stats.time_to_first_byte_ms =
+                            Some(request_start.elapsed().as_millis() as u64);
+                    }
+                });
+                match response {
                     Ok(mut new_response) => {
                         let res = download_chunk(
                             chunk_no,
                             thread_no,
                             progress_context.clone(),
                             &range,
-                            options.max_download_speed,
                             &mut new_response,
                         );
                         download_response = Some(new_response);
@@ -465,6 +782,7 @@ pub fn download_loop(
             };
             match result {
                 Ok(buf) => {
+                    after_error = false;
                     {
                         let mut progress = progress_context.lock().unwrap();
                         progress.total_downloaded += progress.chunk_downloaded[thread_no];
@@ -473,6 +791,11 @@ pub fn download_loop(
                             return Err(anyhow::anyhow!("Stop requested"));
                         }
                     }
+                    events.emit(PipeDownloaderEvent::ChunkFinished {
+                        chunk_no,
+                        thread_no,
+                        size: buf.len(),
+                    });
                     let dc = DataChunk {
                         chunk_no,
                         range: range.clone(),
@@ -495,34 +818,59 @@ pub fn download_loop(
                         assert_eq!(chunk_no, pc.unfinished_chunks[idx_to_remove]);
                         log::info!("Removing chunk {} at idx {}", chunk_no, idx_to_remove);
                         pc.unfinished_chunks.remove(idx_to_remove);
+                        if let Some(stats) = pc.thread_stats.get_mut(thread_no) {
+                            stats.chunks_downloaded += 1;
+                            stats.current_chunk = None;
+                        }
 
                         // remove from current chunks - it's easier, because there is only few of them
                         //pc.current_chunks.remove(&chunk_no);
                     }
+                    let send_start = Instant::now();
                     if let Err(err) = send_download_chunks.send(dc) {
                         log::error!("Error while sending chunk: {:?}", err);
                         return Err(anyhow::anyhow!("Error while sending chunk: {:?}", err));
                     }
+                    progress_context
+                        .lock()
+                        .unwrap()
+                        .stage_blocked
+                        .download_output_sec += send_start.elapsed().as_secs_f64();
                     break;
                 }
                 Err(err) => {
                     //reset response to force reconnection
                     download_response = None;
-                    let progress = {
+                    after_error = true;
+                    let (stop_requested, paused) = {
                         let mut progress = progress_context.lock().unwrap();
                         progress.chunk_downloaded[thread_no] = 0;
-                        progress.clone()
+                        (progress.stop_requested, progress.paused)
                     };
-                    if progress.stop_requested {
+                    if stop_requested {
                         return Err(anyhow::anyhow!("Stop requested"));
                     }
                     if !use_chunks {
-                        return Err(anyhow::anyhow!("Error while downloading: {:?}", err));
+                        return Err(err.context("Error while downloading"));
                     }
-                    if progress.paused {
+                    if let Some(download_error) = err.downcast_ref::<DownloadError>() {
+                        if download_error.is_fatal() {
+                            return Err(err);
+                        }
+                    }
+                    if paused {
                         log::info!("Download paused, trying again");
                     } else {
                         log::warn!("Error while downloading chunk, trying again: {:?}", err);
+                        update_thread_stats(&progress_context, thread_no, |stats| {
+                            stats.retries += 1;
+                            stats.last_error = Some(err.to_string());
+                        });
+                        events.emit(PipeDownloaderEvent::ChunkRetried {
+                            chunk_no,
+                            thread_no,
+                            error: err.to_string(),
+                        });
                     }
                     thread::sleep(Duration::from_secs(5));
                 }
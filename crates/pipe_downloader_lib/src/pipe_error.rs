@@ -0,0 +1,257 @@
+use std::fmt::{Display, Formatter};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::pipe_events::PipelineStage;
+use crate::pipe_format::ArchiveInfo;
+use crate::pipe_list::ListReport;
+use crate::pipe_utils::bytes_to_human;
+use crate::pipe_verify::VerifyReport;
+
+/// Reason why the download did not finish successfully
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(
+        tag = "kind",
+        rename_all = "camelCase",
+        rename_all_fields = "camelCase"
+    )
+)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadError {
+    /// Connection failed or was interrupted
+    Network {
+        message: String,
+    },
+    /// Server responded with unexpected status code
+    HttpStatus {
+        status: u16,
+        url: String,
+    },
+    /// Remote file changed between requests (ETag, Last-Modified or size differs)
+    SourceChanged {
+        message: String,
+    },
+    UnsupportedArchive {
+        message: String,
+    },
+    OutputExists {
+        path: String,
+    },
+    InsufficientDiskSpace {
+        path: String,
+        required: u64,
+        available: u64,
+    },
+    /// Disk or quota became full while writing
+    DiskFull {
+        message: String,
+    },
+    /// Compressed stream is corrupted
+    Decode {
+        message: String,
+    },
+    /// Tar archive is corrupted
+    Archive {
+        message: String,
+    },
+    /// Error while writing or reading local files
+    Io {
+        message: String,
+    },
+    /// Sha256 of the downloaded file differs from [crate::PipeDownloaderOptions::expected_sha256]
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    StoppedByUser,
+    Other {
+        message: String,
+    },
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Network { message } => write!(f, "Network error: {message}"),
+            DownloadError::HttpStatus { status, url } => {
+                write!(f, "Unexpected HTTP status {status} from {url}")
+            }
+            DownloadError::SourceChanged { message } => {
+                write!(f, "Source changed during download: {message}")
+            }
+            DownloadError::UnsupportedArchive { message } => write!(f, "{message}"),
+            DownloadError::OutputExists { path } => write!(
+                f,
+                "Output already exists: {path}. Remove it or specify --force flag"
+            ),
+            DownloadError::InsufficientDiskSpace {
+                path,
+                required,
+                available,
+            } => write!(
+                f,
+                "Not enough free disk space for {path}: required {}, available {}. Use --skip-disk-space-check to override",
+                bytes_to_human(*required as usize),
+                bytes_to_human(*available as usize)
+            ),
+            DownloadError::DiskFull { message } => write!(f, "Disk full: {message}"),
+            DownloadError::Decode { message } => write!(f, "Decode error: {message}"),
+            DownloadError::Archive { message } => write!(f, "Archive error: {message}"),
+            DownloadError::Io { message } => write!(f, "I/O error: {message}"),
+            DownloadError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch of downloaded file: expected sha256 {expected}, got {actual}"
+            ),
+            DownloadError::StoppedByUser => write!(f, "Stopped by user"),
+            DownloadError::Other { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl DownloadError {
+    /// Machine readable kind, the same as serialized `kind` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::Network { .. } => "network",
+            DownloadError::HttpStatus { .. } => "httpStatus",
+            DownloadError::SourceChanged { .. } => "sourceChanged",
+            DownloadError::UnsupportedArchive { .. } => "unsupportedArchive",
+            DownloadError::OutputExists { .. } => "outputExists",
+            DownloadError::InsufficientDiskSpace { .. } => "insufficientDiskSpace",
+            DownloadError::DiskFull { .. } => "diskFull",
+            DownloadError::Decode { .. } => "decode",
+            DownloadError::Archive { .. } => "archive",
+            DownloadError::Io { .. } => "io",
+            DownloadError::ChecksumMismatch { .. } => "checksumMismatch",
+            DownloadError::StoppedByUser => "stoppedByUser",
+            DownloadError::Other { .. } => "other",
+        }
+    }
+
+    /// Retrying the request won't help
+    pub(crate) fn is_fatal(&self) -> bool {
+        match self {
+            DownloadError::SourceChanged { .. } => true,
+            DownloadError::HttpStatus { status, .. } => {
+                (400..500).contains(status) && *status != 408 && *status != 429
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn from_io(err: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        let message = err.to_string();
+        // tar crate keeps the kind of the underlying error, but not the os error code
+        match err.kind() {
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => {
+                DownloadError::DiskFull { message }
+            }
+            ErrorKind::PermissionDenied
+            | ErrorKind::NotFound
+            | ErrorKind::AlreadyExists
+            | ErrorKind::ReadOnlyFilesystem
+            | ErrorKind::IsADirectory
+            | ErrorKind::NotADirectory
+            | ErrorKind::DirectoryNotEmpty
+            | ErrorKind::FileTooLarge
+            | ErrorKind::InvalidFilename
+            | ErrorKind::CrossesDevices
+            | ErrorKind::ResourceBusy
+            | ErrorKind::WriteZero => DownloadError::Io { message },
+            _ if err.raw_os_error().is_some() => DownloadError::Io { message },
+            // tar crate reports malformed archive as Other, InvalidData or UnexpectedEof
+            _ => DownloadError::Archive { message },
+        }
+    }
+
+    /// Classify error returned from pipeline stage, already typed errors are kept as they are
+    pub(crate) fn from_stage(stage: PipelineStage, err: &anyhow::Error) -> Self {
+        if let Some(err) = err.downcast_ref::<DownloadError>() {
+            return err.clone();
+        }
+        let message = format!("{err:#}");
+        match stage {
+            PipelineStage::Download => match err.downcast_ref::<reqwest::Error>() {
+                Some(req_err) => match (req_err.status(), req_err.url()) {
+                    (Some(status), Some(url)) => DownloadError::HttpStatus {
+                        status: status.as_u16(),
+                        url: url.to_string(),
+                    },
+                    _ => DownloadError::Network { message },
+                },
+                None => DownloadError::Network { message },
+            },
+            PipelineStage::Decode => DownloadError::Decode { message },
+            PipelineStage::Unpack => match err.downcast_ref::<std::io::Error>() {
+                Some(io_err) => DownloadError::from_io(io_err),
+                None => DownloadError::Archive { message },
+            },
+        }
+    }
+}
+
+/// Result of successfully finished download, see [crate::PipeDownloader::wait]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Default)]
+pub struct DownloadSummary {
+    pub download_url: Option<String>,
+    pub archive: Option<ArchiveInfo>,
+    pub target_path: Option<String>,
+    pub downloaded: usize,
+    pub unpacked: usize,
+    pub unpacked_files: usize,
+    pub elapsed_time_sec: f64,
+    pub sync_time_sec: f64,
+    pub verify_report: Option<VerifyReport>,
+    pub list_report: Option<ListReport>,
+    /// Sha256 of the downloaded file, computed when expected checksum is given
+    pub sha256: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn test_from_io() {
+        // errors wrapped by the tar crate keep only the kind
+        let wrapped = |kind| Error::new(kind, "failed to unpack `dir/file`");
+        assert_eq!(
+            DownloadError::from_io(&wrapped(ErrorKind::PermissionDenied)).kind(),
+            "io"
+        );
+        assert_eq!(
+            DownloadError::from_io(&wrapped(ErrorKind::StorageFull)).kind(),
+            "diskFull"
+        );
+        assert_eq!(
+            DownloadError::from_io(&wrapped(ErrorKind::ReadOnlyFilesystem)).kind(),
+            "io"
+        );
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                DownloadError::from_io(&Error::from_raw_os_error(libc::ENOSPC)).kind(),
+                "diskFull"
+            );
+            assert_eq!(
+                DownloadError::from_io(&Error::from_raw_os_error(libc::EIO)).kind(),
+                "io"
+            );
+        }
+        for kind in [
+            ErrorKind::Other,
+            ErrorKind::InvalidData,
+            ErrorKind::UnexpectedEof,
+        ] {
+            assert_eq!(DownloadError::from_io(&wrapped(kind)).kind(), "archive");
+        }
+    }
+}
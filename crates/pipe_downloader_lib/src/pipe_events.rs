@@ -0,0 +1,190 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::pipe_error::{DownloadError, DownloadSummary};
+use crate::pipe_format::{ArchiveInfo, UnpackSizeSource};
+use crate::pipe_list::ListedEntry;
+
+/// Number of events kept for slow broadcast subscribers, older events are dropped (Lagged error)
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    Download,
+    Decode,
+    Unpack,
+}
+
+/// Events emitted by the pipeline as they happen
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(
+        tag = "type",
+        rename_all = "camelCase",
+        rename_all_fields = "camelCase"
+    )
+)]
+#[derive(Debug, Clone)]
+pub enum PipeDownloaderEvent {
+    Started {
+        url: String,
+        total_download_size: Option<usize>,
+        archive: Option<ArchiveInfo>,
+    },
+    /// Archive format and sizes known from the headers and metadata, before the download starts
+    FormatDetected {
+        archive: ArchiveInfo,
+        total_download_size: Option<usize>,
+        total_unpack_size: Option<usize>,
+        unpack_size_source: Option<UnpackSizeSource>,
+        unpack_size_estimated: bool,
+        server_chunk_support: bool,
+    },
+    ChunkStarted {
+        chunk_no: usize,
+        thread_no: usize,
+        range_start: usize,
+        range_end: usize,
+    },
+    ChunkFinished {
+        chunk_no: usize,
+        thread_no: usize,
+        size: usize,
+    },
+    ChunkRetried {
+        chunk_no: usize,
+        thread_no: usize,
+        error: String,
+    },
+    EntryExtracted {
+        file_no: usize,
+        file_name: String,
+        file_size: u64,
+    },
+    /// Entry matching the filter in [crate::OutputMode::List]
+    EntryListed {
+        entry: ListedEntry,
+    },
+    Paused,
+    Resumed,
+    StopRequested {
+        graceful: bool,
+    },
+    SpeedLimitChanged {
+        max_download_speed: Option<usize>,
+    },
+    StageError {
+        stage: PipelineStage,
+        error: DownloadError,
+    },
+    Finished {
+        success: bool,
+        error: Option<DownloadError>,
+        /// Present when the download succeeded
+        summary: Option<Box<DownloadSummary>>,
+    },
+}
+
+/// Callback interface for embedding applications.
+/// Events are delivered from pipeline threads, so the implementation should return quickly.
+pub trait PipeDownloaderObserver: Send + Sync {
+    fn on_event(&self, event: &PipeDownloaderEvent);
+}
+
+/// Delivers events to registered observers and broadcast subscribers
+#[derive(Clone)]
+pub struct EventSender {
+    broadcast: broadcast::Sender<PipeDownloaderEvent>,
+    observers: Arc<Mutex<Vec<Arc<dyn PipeDownloaderObserver>>>>,
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        let (broadcast, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            broadcast,
+            observers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl Debug for EventSender {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventSender")
+            .field("subscribers", &self.broadcast.receiver_count())
+            .field("observers", &self.observers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl EventSender {
+    pub fn emit(&self, event: PipeDownloaderEvent) {
+        log::trace!("Event: {:?}", event);
+        // not locked while calling observers, they can add observers or emit events
+        let observers = self.observers.lock().unwrap().clone();
+        for observer in observers {
+            observer.on_event(&event);
+        }
+        // error means there are no subscribers at the moment
+        let _ = self.broadcast.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PipeDownloaderEvent> {
+        self.broadcast.subscribe()
+    }
+
+    pub fn add_observer(&self, observer: Arc<dyn PipeDownloaderObserver>) {
+        self.observers.lock().unwrap().push(observer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl PipeDownloaderObserver for Counter {
+        fn on_event(&self, _event: &PipeDownloaderEvent) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Adds an observer and emits another event from the callback
+    struct Reentrant {
+        events: EventSender,
+        added: Arc<Counter>,
+    }
+
+    impl PipeDownloaderObserver for Reentrant {
+        fn on_event(&self, event: &PipeDownloaderEvent) {
+            if let PipeDownloaderEvent::Paused = event {
+                self.events.add_observer(self.added.clone());
+                self.events.emit(PipeDownloaderEvent::Resumed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_observer_reentrancy() {
+        let events = EventSender::default();
+        let added = Arc::new(Counter::default());
+        events.add_observer(Arc::new(Reentrant {
+            events: events.clone(),
+            added: added.clone(),
+        }));
+        events.emit(PipeDownloaderEvent::Paused);
+        // observer added during delivery gets the nested event
+        assert_eq!(added.0.load(Ordering::SeqCst), 1);
+        events.emit(PipeDownloaderEvent::Resumed);
+        assert_eq!(added.0.load(Ordering::SeqCst), 2);
+    }
+}
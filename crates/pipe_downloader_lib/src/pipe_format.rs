@@ -0,0 +1,455 @@
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Compression of the downloaded stream
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionFormat {
+    Gzip,
+    Lz4,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+/// Known archive suffixes, longer suffixes have to be before their shorter variants
+const ARCHIVE_SUFFIXES: &[(&str, CompressionFormat, bool)] = &[
+    (".tar.gz", CompressionFormat::Gzip, true),
+    (".tar.lz4", CompressionFormat::Lz4, true),
+    (".tar.bz2", CompressionFormat::Bzip2, true),
+    (".tar.xz", CompressionFormat::Xz, true),
+    (".tar.zst", CompressionFormat::Zstd, true),
+    (".tar.zstd", CompressionFormat::Zstd, true),
+    (".tgz", CompressionFormat::Gzip, true),
+    (".tlz4", CompressionFormat::Lz4, true),
+    (".tbz2", CompressionFormat::Bzip2, true),
+    (".tbz", CompressionFormat::Bzip2, true),
+    (".txz", CompressionFormat::Xz, true),
+    (".tzst", CompressionFormat::Zstd, true),
+    (".gz", CompressionFormat::Gzip, false),
+    (".lz4", CompressionFormat::Lz4, false),
+    (".bz2", CompressionFormat::Bzip2, false),
+    (".xz", CompressionFormat::Xz, false),
+    (".zst", CompressionFormat::Zstd, false),
+    (".zstd", CompressionFormat::Zstd, false),
+];
+
+/// Result of archive detection, see [detect_archive]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub format: CompressionFormat,
+    /// Stream is tar archive to unpack, otherwise single compressed file
+    pub is_tar: bool,
+    /// Name of the file the detection was based on
+    pub file_name: String,
+    /// Output directory (tar) or file name inferred from file name, None if it would be empty
+    pub output_name: Option<String>,
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// File name from Content-Disposition header, filename* has priority over filename
+pub fn content_disposition_file_name(header: &str) -> Option<String> {
+    let mut file_name = None;
+    for param in header.split(';').map(|p| p.trim()) {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+            None => continue,
+        };
+        if key == "filename*" {
+            // RFC 5987: charset'language'percent-encoded-value
+            let encoded = value.splitn(3, '\'').nth(2).unwrap_or(value);
+            return Some(percent_decode(encoded.trim_matches('"')));
+        }
+        if key == "filename" {
+            file_name = Some(value.trim_matches('"').to_string());
+        }
+    }
+    file_name
+}
+
+/// Last path segment of the url without query string and fragment
+pub fn url_file_name(url: &str) -> String {
+    let without_fragment = url.split('#').next().unwrap_or(url);
+    let without_query = without_fragment
+        .split('?')
+        .next()
+        .unwrap_or(without_fragment);
+    percent_decode(without_query.split('/').next_back().unwrap_or(""))
+}
+
+fn detect_from_file_name(file_name: &str) -> Option<ArchiveInfo> {
+    // do not allow paths coming from headers
+    let file_name = file_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(file_name)
+        .trim();
+    let lower = file_name.to_ascii_lowercase();
+    let (suffix, format, is_tar) = ARCHIVE_SUFFIXES
+        .iter()
+        .find(|(suffix, _, _)| lower.ends_with(suffix))?;
+    let stem = &file_name[..file_name.len() - suffix.len()];
+    let output_name = if stem.is_empty() || stem == "." || stem == ".." {
+        None
+    } else {
+        Some(stem.to_string())
+    };
+    Some(ArchiveInfo {
+        format: *format,
+        is_tar: *is_tar,
+        file_name: file_name.to_string(),
+        output_name,
+    })
+}
+
+/// Detect compression and output name of the download.
+/// Content-Disposition file name is checked first, then urls in given order
+/// (final url after redirects should go before the original one).
+pub fn detect_archive(content_disposition: Option<&str>, urls: &[&str]) -> Option<ArchiveInfo> {
+    content_disposition
+        .and_then(content_disposition_file_name)
+        .and_then(|name| detect_from_file_name(&name))
+        .or_else(|| {
+            urls.iter()
+                .find_map(|url| detect_from_file_name(&url_file_name(url)))
+        })
+}
+
+/// Magic bytes at the beginning of compressed streams
+const COMPRESSION_MAGIC: &[(&[u8], CompressionFormat)] = &[
+    (&[0x1f, 0x8b], CompressionFormat::Gzip),
+    (&[0x04, 0x22, 0x4d, 0x18], CompressionFormat::Lz4),
+    (b"BZh", CompressionFormat::Bzip2),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], CompressionFormat::Xz),
+    (&[0x28, 0xb5, 0x2f, 0xfd], CompressionFormat::Zstd),
+];
+
+/// Detect compression from the first bytes of the file, independent of its name
+pub fn detect_compression(header: &[u8]) -> Option<CompressionFormat> {
+    COMPRESSION_MAGIC
+        .iter()
+        .find(|(magic, _)| header.starts_with(magic))
+        .map(|(_, format)| *format)
+}
+
+/// ustar and gnu tar headers, old v7 headers have no magic
+pub(crate) fn is_tar_header(header: &[u8]) -> bool {
+    header.get(257..262) == Some(b"ustar".as_slice())
+}
+
+/// Where the value of total unpack size comes from
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnpackSizeSource {
+    ZstdFrameHeader,
+    Lz4FrameHeader,
+    XzIndex,
+    GzipTrailer,
+    /// Extrapolated from observed compression ratio
+    Estimated,
+}
+
+/// Number of bytes from the beginning of the file needed to read frame content size
+pub const FRAME_HEADER_PROBE_SIZE: usize = 32;
+pub const GZIP_TRAILER_SIZE: usize = 4;
+pub const XZ_FOOTER_SIZE: usize = 12;
+pub const XZ_HEADER_SIZE: usize = 12;
+
+/// Content size from zstd frame header, only present when compressor knew the input size
+pub fn zstd_content_size(header: &[u8]) -> Option<u64> {
+    zstd::zstd_safe::get_frame_content_size(header)
+        .ok()
+        .flatten()
+}
+
+/// Content size from lz4 frame header, present only when compressed with --content-size
+pub fn lz4_content_size(header: &[u8]) -> Option<u64> {
+    const LZ4_MAGIC: u32 = 0x184D2204;
+    const CONTENT_SIZE_FLAG: u8 = 0x08;
+    if header.len() < 14 || u32::from_le_bytes(header[0..4].try_into().ok()?) != LZ4_MAGIC {
+        return None;
+    }
+    let flg = header[4];
+    if flg & CONTENT_SIZE_FLAG == 0 {
+        return None;
+    }
+    Some(u64::from_le_bytes(header[6..14].try_into().ok()?))
+}
+
+/// Gzip stores uncompressed size modulo 2^32 in the last four bytes (ISIZE).
+/// Returns the smallest size matching ISIZE that is not smaller than compressed size
+/// and flag telling if the value is ambiguous (archives bigger than 4GiB).
+pub fn gzip_content_size(trailer: &[u8], compressed_size: u64) -> Option<(u64, bool)> {
+    let isize = u32::from_le_bytes(trailer.try_into().ok()?) as u64;
+    let wrap = 1u64 << 32;
+    let mut candidate = isize;
+    while candidate < compressed_size {
+        candidate += wrap;
+    }
+    Some((candidate, compressed_size >= wrap || candidate >= wrap))
+}
+
+/// Size of the xz index, read from the stream footer
+pub fn xz_index_size(footer: &[u8]) -> Option<usize> {
+    if footer.len() != XZ_FOOTER_SIZE || &footer[10..12] != b"YZ" {
+        return None;
+    }
+    let backward_size = u32::from_le_bytes(footer[4..8].try_into().ok()?) as usize;
+    Some((backward_size + 1) * 4)
+}
+
+fn read_xz_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Sum of uncompressed sizes of all blocks listed in the xz index.
+/// The index describes only the last stream, so None is returned when the stream
+/// is not the whole file of `file_size` bytes (concatenated streams or stream padding).
+pub fn xz_content_size(index: &[u8], file_size: u64) -> Option<u64> {
+    if index.first() != Some(&0) {
+        return None;
+    }
+    let mut pos = 1;
+    let records = read_xz_varint(index, &mut pos)?;
+    let mut total: u64 = 0;
+    let mut blocks_size: u64 = 0;
+    for _ in 0..records {
+        let unpadded_size = read_xz_varint(index, &mut pos)?;
+        // blocks are padded to multiple of four bytes
+        blocks_size = blocks_size.checked_add(unpadded_size.checked_add(3)? / 4 * 4)?;
+        total = total.checked_add(read_xz_varint(index, &mut pos)?)?;
+    }
+    let stream_size = (XZ_HEADER_SIZE + index.len() + XZ_FOOTER_SIZE) as u64 + blocks_size;
+    if stream_size != file_size {
+        return None;
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(format: CompressionFormat, is_tar: bool, file_name: &str, output: &str) -> ArchiveInfo {
+        ArchiveInfo {
+            format,
+            is_tar,
+            file_name: file_name.to_string(),
+            output_name: Some(output.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_detect_from_url() {
+        assert_eq!(
+            detect_archive(None, &["https://example.com/dir/snapshot.tar.lz4"]),
+            Some(info(
+                CompressionFormat::Lz4,
+                true,
+                "snapshot.tar.lz4",
+                "snapshot"
+            ))
+        );
+        assert_eq!(
+            detect_archive(None, &["https://example.com/file.txt.gz?token=abc#frag"]),
+            Some(info(
+                CompressionFormat::Gzip,
+                false,
+                "file.txt.gz",
+                "file.txt"
+            ))
+        );
+        assert_eq!(
+            detect_archive(None, &["https://example.com/my%20data.tar.zst?x=1.tar.gz"]),
+            Some(info(
+                CompressionFormat::Zstd,
+                true,
+                "my data.tar.zst",
+                "my data"
+            ))
+        );
+        assert_eq!(
+            detect_archive(None, &["https://example.com/file.zip"]),
+            None
+        );
+        assert_eq!(
+            detect_archive(None, &["https://example.com/download?id=5"]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_aliases() {
+        for (name, format) in [
+            ("a.tgz", CompressionFormat::Gzip),
+            ("a.TBZ2", CompressionFormat::Bzip2),
+            ("a.tbz", CompressionFormat::Bzip2),
+            ("a.txz", CompressionFormat::Xz),
+            ("a.tzst", CompressionFormat::Zstd),
+            ("a.tlz4", CompressionFormat::Lz4),
+        ] {
+            let detected = detect_archive(None, &[&format!("http://host/{name}")]).unwrap();
+            assert_eq!(detected.format, format);
+            assert!(detected.is_tar);
+            assert_eq!(detected.output_name.as_deref(), Some("a"));
+        }
+    }
+
+    #[test]
+    fn test_detect_priority() {
+        let urls = [
+            "https://cdn.example.com/blob/123.tar.gz",
+            "https://example.com/latest.tar.bz2",
+        ];
+        assert_eq!(
+            detect_archive(Some("attachment; filename=\"state.tar.xz\""), &urls),
+            Some(info(CompressionFormat::Xz, true, "state.tar.xz", "state"))
+        );
+        assert_eq!(
+            detect_archive(Some("attachment; filename=unknown.bin"), &urls),
+            Some(info(CompressionFormat::Gzip, true, "123.tar.gz", "123"))
+        );
+        assert_eq!(
+            detect_archive(None, &["https://example.com/download", urls[1]]),
+            Some(info(
+                CompressionFormat::Bzip2,
+                true,
+                "latest.tar.bz2",
+                "latest"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition_file_name(
+                "attachment; filename=\"fallback.tgz\"; filename*=UTF-8''z%C5%82oty.tgz"
+            ),
+            Some("złoty.tgz".to_string())
+        );
+        assert_eq!(
+            content_disposition_file_name("attachment; filename=plain.tar.gz"),
+            Some("plain.tar.gz".to_string())
+        );
+        assert_eq!(content_disposition_file_name("inline"), None);
+        // path components are never used as output name
+        assert_eq!(
+            detect_archive(Some("attachment; filename=\"../../etc/x.tar.gz\""), &[])
+                .and_then(|i| i.output_name),
+            Some("x".to_string())
+        );
+    }
+
+    #[test]
+    fn test_detect_compression() {
+        assert_eq!(
+            detect_compression(&[0x1f, 0x8b, 0x08, 0x00]),
+            Some(CompressionFormat::Gzip)
+        );
+        assert_eq!(
+            detect_compression(&[0x04, 0x22, 0x4d, 0x18, 0x64]),
+            Some(CompressionFormat::Lz4)
+        );
+        assert_eq!(
+            detect_compression(b"BZh91AY&SY"),
+            Some(CompressionFormat::Bzip2)
+        );
+        assert_eq!(
+            detect_compression(&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00]),
+            Some(CompressionFormat::Xz)
+        );
+        assert_eq!(
+            detect_compression(&[0x28, 0xb5, 0x2f, 0xfd, 0x04]),
+            Some(CompressionFormat::Zstd)
+        );
+        assert_eq!(detect_compression(b"ustar"), None);
+        assert_eq!(detect_compression(&[0x1f]), None);
+    }
+
+    #[test]
+    fn test_empty_output_name() {
+        let detected = detect_archive(None, &["https://example.com/.tar.gz"]).unwrap();
+        assert_eq!(detected.output_name, None);
+    }
+
+    #[test]
+    fn test_gzip_content_size() {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&[7u8; 100_000]).unwrap();
+        let data = encoder.finish().unwrap();
+        let trailer = &data[data.len() - GZIP_TRAILER_SIZE..];
+        assert_eq!(
+            gzip_content_size(trailer, data.len() as u64),
+            Some((100_000, false))
+        );
+        // ISIZE is modulo 2^32, bigger files are ambiguous
+        assert_eq!(
+            gzip_content_size(&10u32.to_le_bytes(), 5_000_000_000),
+            Some((10 + 2 * (1 << 32), true))
+        );
+        assert_eq!(gzip_content_size(&trailer[..2], data.len() as u64), None);
+    }
+
+    #[test]
+    fn test_lz4_content_size() {
+        let mut header = vec![0x04, 0x22, 0x4D, 0x18, 0x64 | 0x08, 0x40];
+        header.extend_from_slice(&12345u64.to_le_bytes());
+        header.push(0);
+        assert_eq!(lz4_content_size(&header), Some(12345));
+        assert_eq!(lz4_content_size(&header[..10]), None);
+        header[4] = 0x64;
+        assert_eq!(lz4_content_size(&header), None);
+        assert_eq!(lz4_content_size(b"not an lz4 frame header"), None);
+    }
+
+    #[test]
+    fn test_xz_content_size() {
+        use std::io::Write;
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 1);
+        encoder.write_all(&[7u8; 100_000]).unwrap();
+        let data = encoder.finish().unwrap();
+        let index_end = data.len() - XZ_FOOTER_SIZE;
+        let index_size = xz_index_size(&data[index_end..]).unwrap();
+        let index = &data[index_end - index_size..index_end];
+        assert_eq!(xz_content_size(index, data.len() as u64), Some(100_000));
+
+        assert_eq!(xz_index_size(&data[index_end + 1..]), None);
+        assert_eq!(xz_index_size(&[0u8; XZ_FOOTER_SIZE]), None);
+        assert_eq!(xz_content_size(&index[..2], data.len() as u64), None);
+        assert_eq!(xz_content_size(&[], data.len() as u64), None);
+
+        // index of the last stream doesn't describe the first one
+        let concatenated = [data.as_slice(), data.as_slice()].concat();
+        assert_eq!(xz_content_size(index, concatenated.len() as u64), None);
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::pipe_progress::InternalProgress;
+
+/// Maximum number of samples kept, when exceeded every other sample is dropped
+/// and the sampling interval is doubled, so the whole run always fits
+const MAX_HISTORY_SAMPLES: usize = 2000;
+const BASE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cumulative counters at given time, rates are computed from differences between samples,
+/// so dropping samples keeps the averages exact
+#[derive(Debug, Clone)]
+pub struct HistorySample {
+    pub time_sec: f64,
+    pub downloaded: usize,
+    pub unpacked: usize,
+    pub unpacked_files: usize,
+}
+
+/// Downsampled history of the whole run
+#[derive(Debug, Clone)]
+pub struct RunHistory {
+    samples: Vec<HistorySample>,
+    interval: Duration,
+}
+
+impl Default for RunHistory {
+    fn default() -> Self {
+        Self {
+            samples: vec![HistorySample {
+                time_sec: 0.0,
+                downloaded: 0,
+                unpacked: 0,
+                unpacked_files: 0,
+            }],
+            interval: BASE_SAMPLE_INTERVAL,
+        }
+    }
+}
+
+/// Speeds averaged over the time since previous point
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct HistoryPoint {
+    /// Seconds since start of the download
+    pub time_sec: f64,
+    pub download_speed: f64,
+    pub unpack_speed: f64,
+    pub files_per_sec: f64,
+    pub downloaded: usize,
+    pub unpacked: usize,
+    pub unpacked_files: usize,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct PipeDownloaderHistory {
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    /// Current resolution of the stored history
+    pub interval_sec: f64,
+    pub points: Vec<HistoryPoint>,
+}
+
+impl RunHistory {
+    /// Add sample if at least one interval passed since the last one (or when forced)
+    pub fn add_sample(&mut self, sample: HistorySample, force: bool) {
+        if let Some(last) = self.samples.last() {
+            if !force && sample.time_sec - last.time_sec < self.interval.as_secs_f64() {
+                return;
+            }
+        }
+        self.samples.push(sample);
+        if self.samples.len() > MAX_HISTORY_SAMPLES {
+            let last = self.samples.pop();
+            let mut idx = 0;
+            self.samples.retain(|_| {
+                idx += 1;
+                idx % 2 == 1
+            });
+            self.samples.extend(last);
+            self.interval *= 2;
+            log::debug!("History downsampled, new interval {:?}", self.interval);
+        }
+    }
+
+    /// Points with time in given range (seconds since start),
+    /// reduced to at most max_points by averaging neighbouring samples
+    pub fn query(
+        &self,
+        from_sec: Option<f64>,
+        to_sec: Option<f64>,
+        max_points: Option<usize>,
+    ) -> Vec<HistoryPoint> {
+        let in_range = |s: &HistorySample| {
+            from_sec.map(|from| s.time_sec >= from).unwrap_or(true)
+                && to_sec.map(|to| s.time_sec <= to).unwrap_or(true)
+        };
+        // the first sample (zeros at start) is only a base for the rate of the next one
+        let first = match self.samples.iter().skip(1).position(in_range) {
+            Some(first) => first + 1,
+            None => return Vec::new(),
+        };
+        let last = self.samples.iter().rposition(in_range).unwrap_or(first);
+        // previous sample is needed to compute rate of the first point in range
+        let start = first.saturating_sub(1);
+        let count = last - first + 1;
+        let step = match max_points {
+            Some(max_points) if max_points > 0 && count > max_points => count.div_ceil(max_points),
+            _ => 1,
+        };
+        let mut points = Vec::with_capacity(count / step + 1);
+        let mut prev = &self.samples[start];
+        let mut idx = first;
+        while idx <= last {
+            // always include the last sample of the range
+            let cur_idx = std::cmp::min(idx + step - 1, last);
+            let cur = &self.samples[cur_idx];
+            let elapsed = cur.time_sec - prev.time_sec;
+            let rate = |cur: usize, prev: usize| {
+                if elapsed > 0.0 {
+                    cur.saturating_sub(prev) as f64 / elapsed
+                } else {
+                    0.0
+                }
+            };
+            points.push(HistoryPoint {
+                time_sec: cur.time_sec,
+                download_speed: rate(cur.downloaded, prev.downloaded),
+                unpack_speed: rate(cur.unpacked, prev.unpacked),
+                files_per_sec: rate(cur.unpacked_files, prev.unpacked_files),
+                downloaded: cur.downloaded,
+                unpacked: cur.unpacked,
+                unpacked_files: cur.unpacked_files,
+            });
+            prev = cur;
+            idx = cur_idx + 1;
+        }
+        points
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Record history samples until the pipeline finishes
+pub fn history_sampler(pc: Arc<Mutex<InternalProgress>>) {
+    loop {
+        thread::sleep(Duration::from_millis(250));
+        let mut pc = pc.lock().unwrap();
+        // final sample is recorded together with the outcome
+        if pc.outcome.is_some() {
+            break;
+        }
+        pc.record_history(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time_sec: f64) -> HistorySample {
+        HistorySample {
+            time_sec,
+            downloaded: time_sec as usize * 100,
+            unpacked: time_sec as usize * 300,
+            unpacked_files: time_sec as usize,
+        }
+    }
+
+    #[test]
+    fn test_downsampling_keeps_whole_run() {
+        let mut history = RunHistory::default();
+        for sec in 1..=(MAX_HISTORY_SAMPLES * 3) {
+            history.add_sample(sample(sec as f64), false);
+        }
+        assert!(history.samples.len() <= MAX_HISTORY_SAMPLES);
+        assert_eq!(history.interval(), BASE_SAMPLE_INTERVAL * 4);
+        assert_eq!(history.samples.first().unwrap().time_sec, 0.0);
+        let points = history.query(None, None, None);
+        assert_eq!(
+            points.last().unwrap().time_sec,
+            (MAX_HISTORY_SAMPLES * 3) as f64
+        );
+        // constant speed survives downsampling
+        assert!(points.iter().all(|p| p.download_speed == 100.0));
+        assert!(points.iter().all(|p| p.unpack_speed == 300.0));
+    }
+
+    #[test]
+    fn test_query_range() {
+        let mut history = RunHistory::default();
+        for sec in 1..=100 {
+            history.add_sample(sample(sec as f64), false);
+        }
+        let points = history.query(Some(10.0), Some(20.0), None);
+        assert_eq!(points.len(), 11);
+        assert_eq!(points[0].time_sec, 10.0);
+        assert_eq!(points[0].files_per_sec, 1.0);
+        let points = history.query(Some(10.0), Some(20.0), Some(4));
+        assert_eq!(points.len(), 4);
+        assert_eq!(points.last().unwrap().time_sec, 20.0);
+        assert!(history.query(Some(200.0), None, None).is_empty());
+    }
+}
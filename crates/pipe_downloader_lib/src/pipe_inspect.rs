@@ -0,0 +1,308 @@
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+use std::io::{Cursor, Read};
+use std::str::FromStr;
+use std::time::Instant;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::pipe_engine::{
+    check_status, header_string, probe_unpack_size, resolve_link, supports_partial_content,
+    user_agent_headers, SourceIdentity,
+};
+use crate::pipe_error::DownloadError;
+use crate::pipe_events::PipelineStage;
+use crate::pipe_format::{
+    detect_archive, detect_compression, is_tar_header, ArchiveInfo, CompressionFormat,
+    UnpackSizeSource,
+};
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Options of [inspect]
+#[derive(Debug, Clone)]
+pub struct InspectOptions {
+    /// Bytes downloaded from the beginning of the file to measure speed and list tar entries
+    pub sample_size: usize,
+    /// Max number of tar entries listed
+    pub max_entries: usize,
+}
+
+impl Default for InspectOptions {
+    fn default() -> Self {
+        Self {
+            sample_size: 4_000_000,
+            max_entries: 10,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct InspectTarEntry {
+    pub path: String,
+    pub size: u64,
+    /// regular, directory, symlink, ...
+    pub entry_type: String,
+}
+
+/// Download of the beginning of the file with one connection
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct SpeedSample {
+    pub bytes: usize,
+    pub elapsed_time_sec: f64,
+    pub time_to_first_byte_ms: u64,
+    pub bytes_per_sec: f64,
+}
+
+/// What is known about the download without downloading the whole file, see [inspect]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct InspectReport {
+    pub url: String,
+    /// Url read from `.link` file, the same as url otherwise
+    pub download_url: String,
+    /// Url after following redirects
+    pub final_url: String,
+    pub content_type: Option<String>,
+    pub total_download_size: Option<usize>,
+    pub partial_content_support: bool,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Detected from Content-Disposition header or url
+    pub archive: Option<ArchiveInfo>,
+    /// Detected from the first bytes of the file
+    pub detected_compression: Option<CompressionFormat>,
+    pub total_unpack_size: Option<usize>,
+    pub total_unpack_size_source: Option<UnpackSizeSource>,
+    pub total_unpack_size_estimated: bool,
+    /// Decoded sample starts with tar header, None when the sample can't be decoded
+    pub is_tar: Option<bool>,
+    pub tar_entries: Vec<InspectTarEntry>,
+    /// Listing stopped at max entries or at the end of the sample, more entries may follow
+    pub tar_entries_truncated: bool,
+    pub speed_sample: Option<SpeedSample>,
+    /// Download time of the whole file with one connection at the sampled speed
+    pub estimated_download_time_sec: Option<f64>,
+    /// Problems found, e.g. content not matching the file name
+    pub warnings: Vec<String>,
+}
+
+/// Probe the url the same way the download does (`.link` resolution, HEAD and range test),
+/// download a short sample from the beginning of the file to detect compression,
+/// list the first tar entries and estimate download time. Nothing is written to disk.
+/// Blocking, do not call it from async context.
+pub fn inspect(url: &str, options: &InspectOptions) -> Result<InspectReport, DownloadError> {
+    inspect_url(url, options)
+        .map_err(|err| DownloadError::from_stage(PipelineStage::Download, &err))
+}
+
+fn inspect_url(url: &str, options: &InspectOptions) -> anyhow::Result<InspectReport> {
+    let client = reqwest::blocking::Client::new();
+    let download_url = resolve_link(&client, url)?;
+    let response = client
+        .head(&download_url)
+        .headers(user_agent_headers())
+        .send()?;
+    check_status(&response)?;
+    let final_url = response.url().to_string();
+    let total_download_size = header_string(&response, CONTENT_LENGTH)
+        .and_then(|length| usize::from_str(&length).ok())
+        .filter(|length| *length > 0);
+    let archive = detect_archive(
+        header_string(&response, CONTENT_DISPOSITION).as_deref(),
+        &[&final_url, &download_url, url],
+    );
+    let source = SourceIdentity::from_response(&response, total_download_size);
+    let partial_content_support = supports_partial_content(&client, &download_url)?;
+
+    let mut report = InspectReport {
+        url: url.to_string(),
+        download_url: download_url.clone(),
+        final_url,
+        content_type: header_string(&response, CONTENT_TYPE),
+        total_download_size,
+        partial_content_support,
+        etag: source.etag.clone(),
+        last_modified: source.last_modified.clone(),
+        archive,
+        detected_compression: None,
+        total_unpack_size: None,
+        total_unpack_size_source: None,
+        total_unpack_size_estimated: false,
+        is_tar: None,
+        tar_entries: Vec::new(),
+        tar_entries_truncated: false,
+        speed_sample: None,
+        estimated_download_time_sec: None,
+        warnings: Vec::new(),
+    };
+    if !partial_content_support {
+        report.warnings.push(
+            "Server does not support partial content, download will use one connection without retries"
+                .to_string(),
+        );
+    }
+    if total_download_size.is_none() {
+        report
+            .warnings
+            .push("Content-Length is missing, size of the download is unknown".to_string());
+    }
+
+    let (sample, speed_sample) = download_sample(
+        &client,
+        &download_url,
+        partial_content_support,
+        total_download_size.map_or(options.sample_size, |size| size.min(options.sample_size)),
+    )?;
+    report.estimated_download_time_sec = total_download_size
+        .filter(|_| speed_sample.bytes_per_sec > 0.0)
+        .map(|size| size as f64 / speed_sample.bytes_per_sec);
+    report.speed_sample = Some(speed_sample);
+    report.detected_compression = detect_compression(&sample);
+
+    let name_format = report.archive.as_ref().map(|archive| archive.format);
+    match (name_format, report.detected_compression) {
+        (Some(expected), Some(detected)) if expected != detected => report.warnings.push(format!(
+            "File name suggests {expected:?} compression, but the content is {detected:?}"
+        )),
+        (_, None) => report
+            .warnings
+            .push("Compression not recognized from the first bytes".to_string()),
+        _ => {}
+    }
+
+    let format = report.detected_compression.or(name_format);
+    if let (true, Some(total_length), Some(format)) =
+        (partial_content_support, total_download_size, format)
+    {
+        match probe_unpack_size(&download_url, &client, format, total_length, &source) {
+            Ok(Some((size, source, estimated))) => {
+                report.total_unpack_size = Some(size);
+                report.total_unpack_size_source = Some(source);
+                report.total_unpack_size_estimated = estimated;
+            }
+            Ok(None) => {}
+            Err(err) => report
+                .warnings
+                .push(format!("Failed to probe unpack size: {err}")),
+        }
+    }
+
+    if let Some(format) = format {
+        match sample_decoder(format, sample.as_slice()) {
+            Ok(decoder) => list_tar_entries(decoder, options.max_entries, &mut report),
+            Err(err) => report
+                .warnings
+                .push(format!("Cannot decode {format:?} sample: {err}")),
+        }
+    }
+    if report
+        .archive
+        .as_ref()
+        .is_some_and(|archive| archive.is_tar)
+        && report.is_tar == Some(false)
+    {
+        report
+            .warnings
+            .push("File name suggests tar archive, but decoded content is not tar".to_string());
+    }
+    Ok(report)
+}
+
+fn download_sample(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    partial_content_support: bool,
+    sample_size: usize,
+) -> anyhow::Result<(Vec<u8>, SpeedSample)> {
+    let request_start = Instant::now();
+    let mut request = client.get(url).headers(user_agent_headers());
+    if partial_content_support && sample_size > 0 {
+        request = request.header("Range", format!("bytes=0-{}", sample_size - 1));
+    }
+    let response = request.send()?;
+    check_status(&response)?;
+    let time_to_first_byte = request_start.elapsed();
+    // without range support the rest of the response is dropped
+    let mut sample = Vec::with_capacity(sample_size);
+    response.take(sample_size as u64).read_to_end(&mut sample)?;
+    let elapsed = request_start.elapsed().as_secs_f64();
+    let speed_sample = SpeedSample {
+        bytes: sample.len(),
+        elapsed_time_sec: elapsed,
+        time_to_first_byte_ms: time_to_first_byte.as_millis() as u64,
+        bytes_per_sec: if elapsed > 0.0 {
+            sample.len() as f64 / elapsed
+        } else {
+            0.0
+        },
+    };
+    Ok((sample, speed_sample))
+}
+
+pub(crate) fn sample_decoder<'a, R: Read + 'a>(
+    format: CompressionFormat,
+    sample: R,
+) -> anyhow::Result<Box<dyn Read + 'a>> {
+    Ok(match format {
+        CompressionFormat::Gzip => Box::new(GzDecoder::new(sample)),
+        CompressionFormat::Lz4 => {
+            #[cfg(feature = "lz4-rust")]
+            return Ok(Box::new(lz4_flex::frame::FrameDecoder::new(sample)));
+            #[cfg(all(feature = "with-lz4", not(feature = "lz4-rust")))]
+            return Ok(Box::new(lz4::Decoder::new(sample)?));
+            #[cfg(not(any(feature = "lz4-rust", feature = "with-lz4")))]
+            return Err(anyhow::anyhow!("lz4 is not supported"));
+        }
+        CompressionFormat::Bzip2 => Box::new(BzDecoder::new(sample)),
+        CompressionFormat::Xz => Box::new(xz2::read::XzDecoder::new(sample)),
+        CompressionFormat::Zstd => Box::new(zstd::stream::read::Decoder::new(sample)?),
+    })
+}
+
+/// Errors are expected at the end of the sample, entries read until then are kept
+fn list_tar_entries(
+    mut decoder: Box<dyn Read + '_>,
+    max_entries: usize,
+    report: &mut InspectReport,
+) {
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    if decoder.read_exact(&mut header).is_err() {
+        report.is_tar = Some(false);
+        return;
+    }
+    let is_tar = is_tar_header(&header);
+    report.is_tar = Some(is_tar);
+    if !is_tar {
+        return;
+    }
+    let mut archive = tar::Archive::new(Cursor::new(header).chain(decoder));
+    let entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    report.tar_entries_truncated = true;
+    for entry in entries {
+        if report.tar_entries.len() >= max_entries {
+            return;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => return,
+        };
+        report.tar_entries.push(InspectTarEntry {
+            path: entry
+                .path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&entry.path_bytes()).to_string()),
+            size: entry.header().size().unwrap_or_default(),
+            entry_type: format!("{:?}", entry.header().entry_type()).to_lowercase(),
+        });
+    }
+    // end of archive found in the sample
+    report.tar_entries_truncated = false;
+}
@@ -0,0 +1,201 @@
+use std::io::Read;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Archive entry walked in [crate::OutputMode::List], fields shown by `tar -tv`
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct ListedEntry {
+    pub path: String,
+    /// regular, directory, symlink, ...
+    pub entry_type: String,
+    pub size: u64,
+    /// Permission bits
+    pub mode: u32,
+    /// Modification time, seconds since unix epoch
+    pub mtime: u64,
+    /// User name, uid when the name is not stored
+    pub owner: String,
+    /// Group name, gid when the name is not stored
+    pub group: String,
+    /// Target of symlinks and hard links
+    pub link_target: Option<String>,
+}
+
+/// Result of listing archive entries
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Default)]
+pub struct ListReport {
+    /// All entries walked, including those not matching the filter
+    pub entries: usize,
+    /// Entries matching the filter
+    pub listed: usize,
+    pub stop_after_path: Option<String>,
+    /// Requested path was seen and the download was stopped before the end of the archive
+    pub stop_after_path_found: bool,
+}
+
+/// Paths are compared without leading `./` and trailing `/`
+fn normalize_path(path: &str) -> &str {
+    let mut path = path;
+    while let Some(stripped) = path.strip_prefix("./") {
+        path = stripped;
+    }
+    path.trim_end_matches('/')
+}
+
+/// Matches `[...]` class starting after `[`, returns match result and rest of the pattern
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negated, mut idx) = match pattern.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    while idx < pattern.len() {
+        let start = pattern[idx];
+        // `]` right after `[` is a member of the class
+        if start == ']' && !first {
+            return Some((matched != negated, &pattern[idx + 1..]));
+        }
+        first = false;
+        if idx + 2 < pattern.len() && pattern[idx + 1] == '-' && pattern[idx + 2] != ']' {
+            matched |= start <= c && c <= pattern[idx + 2];
+            idx += 3;
+        } else {
+            matched |= start == c;
+            idx += 1;
+        }
+    }
+    // unclosed class
+    None
+}
+
+fn glob_match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**/` matches zero or more directories, `**` anything
+            let dirs_only = pattern.get(2) == Some(&'/');
+            let rest = if dirs_only {
+                &pattern[3..]
+            } else {
+                &pattern[2..]
+            };
+            (0..=path.len()).any(|skip| {
+                (!dirs_only || skip == 0 || path[skip - 1] == '/')
+                    && glob_match_chars(rest, &path[skip..])
+            })
+        }
+        Some('*') => (0..=path.len())
+            .take_while(|skip| *skip == 0 || path[skip - 1] != '/')
+            .any(|skip| glob_match_chars(&pattern[1..], &path[skip..])),
+        Some('?') => {
+            !path.is_empty() && path[0] != '/' && glob_match_chars(&pattern[1..], &path[1..])
+        }
+        Some('[') if !path.is_empty() && path[0] != '/' => {
+            match match_class(&pattern[1..], path[0]) {
+                Some((matched, rest)) => matched && glob_match_chars(rest, &path[1..]),
+                None => pattern[0] == path[0] && glob_match_chars(&pattern[1..], &path[1..]),
+            }
+        }
+        Some(c) => !path.is_empty() && *c == path[0] && glob_match_chars(&pattern[1..], &path[1..]),
+    }
+}
+
+/// Shell like glob: `*` and `?` do not match `/`, `**` matches across directories,
+/// `[a-z]` and `[!a-z]` match character classes
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = normalize_path(pattern).chars().collect();
+    let path: Vec<char> = normalize_path(path).chars().collect();
+    glob_match_chars(&pattern, &path)
+}
+
+pub struct Lister {
+    filter: Option<String>,
+    report: ListReport,
+}
+
+impl Lister {
+    pub fn new(filter: Option<&str>, stop_after_path: Option<&str>) -> Self {
+        Self {
+            filter: filter.map(|filter| filter.to_string()),
+            report: ListReport {
+                stop_after_path: stop_after_path.map(|path| path.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Returns the entry when it matches the filter, its data is not read
+    pub fn list_entry<R: Read>(
+        &mut self,
+        entry: &tar::Entry<R>,
+    ) -> std::io::Result<Option<ListedEntry>> {
+        let path = entry.path()?.display().to_string();
+        self.report.entries += 1;
+        if let Some(stop_after_path) = &self.report.stop_after_path {
+            if normalize_path(stop_after_path) == normalize_path(&path) {
+                self.report.stop_after_path_found = true;
+            }
+        }
+        if let Some(filter) = &self.filter {
+            if !glob_match(filter, &path) {
+                return Ok(None);
+            }
+        }
+        self.report.listed += 1;
+        let header = entry.header();
+        let name_or_id = |name: Option<&str>, id: std::io::Result<u64>| match name {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => id.unwrap_or_default().to_string(),
+        };
+        Ok(Some(ListedEntry {
+            path,
+            entry_type: format!("{:?}", header.entry_type()).to_lowercase(),
+            size: header.size().unwrap_or_default(),
+            mode: header.mode().unwrap_or_default(),
+            mtime: header.mtime().unwrap_or_default(),
+            owner: name_or_id(header.username().ok().flatten(), header.uid()),
+            group: name_or_id(header.groupname().ok().flatten(), header.gid()),
+            link_target: entry
+                .link_name()?
+                .map(|target| target.display().to_string()),
+        }))
+    }
+
+    /// Download can be stopped, requested path was seen
+    pub fn stop_after_path_found(&self) -> bool {
+        self.report.stop_after_path_found
+    }
+
+    pub fn finish(self) -> ListReport {
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.txt", "a.txt"));
+        assert!(glob_match("*.txt", "./a.txt"));
+        assert!(!glob_match("*.txt", "dir/a.txt"));
+        assert!(glob_match("dir/*.txt", "dir/a.txt"));
+        assert!(glob_match("**/*.txt", "a.txt"));
+        assert!(glob_match("**/*.txt", "dir/sub/a.txt"));
+        assert!(glob_match("dir/**", "dir/sub/a.txt"));
+        assert!(!glob_match("dir/**", "other/a.txt"));
+        assert!(glob_match("file?.bin", "file1.bin"));
+        assert!(!glob_match("file?.bin", "file10.bin"));
+        assert!(glob_match("file[0-4].bin", "file3.bin"));
+        assert!(!glob_match("file[0-4].bin", "file7.bin"));
+        assert!(glob_match("file[!0-4].bin", "file7.bin"));
+        assert!(glob_match("dir", "./dir/"));
+        assert!(!glob_match("a[b", "ab"));
+        assert!(glob_match("a[b", "a[b"));
+    }
+}
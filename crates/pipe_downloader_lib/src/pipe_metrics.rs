@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::pipe_progress::InternalProgress;
+
+const METRIC_PREFIX: &str = "pipe_downloader";
+
+struct MetricFamily {
+    help: &'static str,
+    kind: &'static str,
+    samples: Vec<String>,
+}
+
+/// Metrics in Prometheus text exposition format.
+/// Samples of many downloads can be added, every metric family is written once.
+#[derive(Default)]
+pub struct PrometheusMetrics {
+    families: BTreeMap<&'static str, MetricFamily>,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{labels}}}")
+}
+
+impl PrometheusMetrics {
+    fn add(
+        &mut self,
+        name: &'static str,
+        kind: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.families
+            .entry(name)
+            .or_insert_with(|| MetricFamily {
+                help,
+                kind,
+                samples: Vec::new(),
+            })
+            .samples
+            .push(format!("{}{} {}", name, format_labels(labels), value));
+    }
+
+    fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add(name, "gauge", help, labels, value);
+    }
+
+    fn counter(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) {
+        self.add(name, "counter", help, labels, value);
+    }
+
+    /// Text to serve at /metrics endpoint
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, family) in &self.families {
+            let _ = writeln!(out, "# HELP {}_{} {}", METRIC_PREFIX, name, family.help);
+            let _ = writeln!(out, "# TYPE {}_{} {}", METRIC_PREFIX, name, family.kind);
+            for sample in &family.samples {
+                let _ = writeln!(out, "{METRIC_PREFIX}_{sample}");
+            }
+        }
+        out
+    }
+
+    pub(crate) fn add_progress(&mut self, pc: &InternalProgress, labels: &[(&str, &str)]) {
+        let progress = pc.progress();
+        let with = |extra: (&'static str, &'static str)| {
+            let mut labels = labels.to_vec();
+            labels.push(extra);
+            labels
+        };
+        self.counter(
+            "downloaded_bytes_total",
+            "Bytes downloaded",
+            labels,
+            progress.downloaded as f64,
+        );
+        self.counter(
+            "unpacked_bytes_total",
+            "Bytes decompressed",
+            labels,
+            progress.unpacked as f64,
+        );
+        if let Some(total_download_size) = progress.total_download_size {
+            self.gauge(
+                "download_size_bytes",
+                "Size of the downloaded file",
+                labels,
+                total_download_size as f64,
+            );
+        }
+        if let Some(total_unpack_size) = progress.total_unpack_size {
+            self.gauge(
+                "unpack_size_bytes",
+                "Size of the decompressed data, may be estimated",
+                labels,
+                total_unpack_size as f64,
+            );
+        }
+        self.gauge(
+            "download_speed_bytes_per_second",
+            "Current download speed",
+            labels,
+            progress.current_download_speed as f64,
+        );
+        self.gauge(
+            "unpack_speed_bytes_per_second",
+            "Current decompression speed",
+            labels,
+            progress.current_unpack_speed as f64,
+        );
+        if let Some(max_download_speed) = progress.max_download_speed {
+            self.gauge(
+                "speed_limit_bytes_per_second",
+                "Download speed limit per thread",
+                labels,
+                max_download_speed as f64,
+            );
+        }
+        self.gauge(
+            "chunks_total",
+            "Number of chunks of the download",
+            labels,
+            progress.chunks_total as f64,
+        );
+        self.gauge(
+            "chunks_left",
+            "Chunks not downloaded yet",
+            labels,
+            progress.chunks_left as f64,
+        );
+        self.gauge(
+            "chunks_in_flight",
+            "Chunks being downloaded",
+            labels,
+            progress
+                .thread_stats
+                .iter()
+                .filter(|stats| stats.current_chunk.is_some())
+                .count() as f64,
+        );
+        let (requests, reconnects, retries) = progress.thread_stats.iter().fold(
+            (0, 0, 0),
+            |(requests, reconnects, retries), stats| {
+                (
+                    requests + stats.requests,
+                    reconnects + stats.reconnects,
+                    retries + stats.retries,
+                )
+            },
+        );
+        self.counter(
+            "requests_total",
+            "HTTP requests made by download threads",
+            labels,
+            requests as f64,
+        );
+        self.counter(
+            "reconnects_total",
+            "Requests made to resume after an error",
+            labels,
+            reconnects as f64,
+        );
+        self.counter(
+            "retries_total",
+            "Chunk downloads retried after an error",
+            labels,
+            retries as f64,
+        );
+        self.counter(
+            "files_extracted_total",
+            "Files extracted from the archive",
+            labels,
+            progress.unpacked_files as f64,
+        );
+        let blocked = &progress.stage_blocked;
+        for (stage, waiting_for, seconds) in [
+            ("download", "output", blocked.download_output_sec),
+            ("decode", "input", blocked.decode_input_sec),
+            ("decode", "output", blocked.decode_output_sec),
+            ("unpack", "input", blocked.unpack_input_sec),
+        ] {
+            let mut labels = with(("stage", stage));
+            labels.push(("waiting_for", waiting_for));
+            self.counter(
+                "stage_blocked_seconds_total",
+                "Time the stage spent waiting for other stages",
+                &labels,
+                seconds,
+            );
+        }
+        self.gauge(
+            "paused",
+            "Download is paused",
+            labels,
+            if progress.paused { 1.0 } else { 0.0 },
+        );
+        self.gauge(
+            "paused_low_disk_space",
+            "Download is paused because of low free disk space",
+            labels,
+            if progress.paused_low_disk_space {
+                1.0
+            } else {
+                0.0
+            },
+        );
+        self.gauge(
+            "elapsed_seconds",
+            "Time since start of the download",
+            labels,
+            progress.elapsed_time_sec,
+        );
+        let outcome = match &pc.outcome {
+            None => "running",
+            Some(Ok(_)) => "success",
+            Some(Err(err)) => err.kind(),
+        };
+        self.gauge(
+            "outcome",
+            "Result of the download: running, success or error kind",
+            &with(("outcome", outcome)),
+            1.0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_families_written_once_with_escaped_labels() {
+        let pc = InternalProgress::default();
+        let mut metrics = PrometheusMetrics::default();
+        metrics.add_progress(&pc, &[("url", "http://a/\"x\".tar.gz")]);
+        metrics.add_progress(&pc, &[("url", "http://b/y.tar.gz")]);
+        let text = metrics.render();
+        assert_eq!(
+            text.matches("# TYPE pipe_downloader_downloaded_bytes_total counter")
+                .count(),
+            1
+        );
+        assert!(text.contains(
+            "pipe_downloader_downloaded_bytes_total{url=\"http://a/\\\"x\\\".tar.gz\"} 0\n"
+        ));
+        assert!(text.contains(
+            "pipe_downloader_outcome{url=\"http://b/y.tar.gz\",outcome=\"running\"} 1\n"
+        ));
+    }
+}
@@ -1,8 +1,16 @@
+use crate::pipe_control::{ControlLog, ControlLogEntry};
+use crate::pipe_error::{DownloadError, DownloadSummary};
+use crate::pipe_events::{EventSender, PipeDownloaderEvent};
+use crate::pipe_format::{ArchiveInfo, UnpackSizeSource};
+use crate::pipe_history::{HistorySample, RunHistory};
+use crate::pipe_list::ListReport;
+use crate::pipe_verify::VerifyReport;
 use crate::tsutils::TimePair;
 use chrono::Utc;
 #[cfg(feature = "serde")]
 use serde::Serialize;
 use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
 use std::time;
 use std::time::Instant;
 
@@ -107,6 +115,51 @@ pub struct UnpackedFileInfo {
     pub finished: bool,
 }
 
+/// Statistics of single download thread (connection)
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Default)]
+pub struct DownloadThreadStats {
+    pub thread_no: usize,
+    pub current_chunk: Option<usize>,
+    pub chunks_downloaded: usize,
+    /// Bytes received by the thread, including data of failed attempts
+    pub bytes_downloaded: usize,
+    pub current_speed: usize,
+    /// Time to response headers of the last request
+    pub time_to_first_byte_ms: Option<u64>,
+    pub requests: usize,
+    /// Requests made to resume after an error
+    pub reconnects: usize,
+    pub retries: usize,
+    pub last_error: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub speed_buckets: ProgressHistory,
+}
+
+impl DownloadThreadStats {
+    pub fn new(thread_no: usize) -> Self {
+        Self {
+            thread_no,
+            speed_buckets: ProgressHistory::new(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Time the pipeline stages spent waiting for each other, shows which stage is the bottleneck
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Default)]
+pub struct StageBlockedTime {
+    /// Download threads waiting until decoder accepts the chunks (summed over threads)
+    pub download_output_sec: f64,
+    /// Decoder waiting for downloaded data
+    pub decode_input_sec: f64,
+    /// Decoder waiting until unpacker accepts decoded data
+    pub decode_output_sec: f64,
+    /// Unpacker waiting for decoded data
+    pub unpack_input_sec: f64,
+}
+
 #[derive(Debug, Clone)]
 pub struct InternalProgress {
     pub start_time: TimePair,
@@ -118,22 +171,53 @@ pub struct InternalProgress {
     pub total_downloaded: usize,
     pub total_download_size: Option<usize>,
     pub chunk_downloaded: Vec<usize>,
+    pub thread_stats: Vec<DownloadThreadStats>,
+    pub stage_blocked: StageBlockedTime,
     pub total_unpacked: usize,
     pub total_unpack_size: Option<usize>,
+    pub total_unpack_size_source: Option<UnpackSizeSource>,
+    pub total_unpack_size_estimated: bool,
+    /// Compressed bytes already consumed by the decoder
+    pub decoder_consumed: usize,
     pub stop_requested: bool,
+    /// Stop after the file currently being extracted is finished
+    pub graceful_stop_requested: bool,
     pub paused: bool,
+    /// Limit per download thread, can be changed while downloading
+    pub max_download_speed: Option<usize>,
+    pub control_log: ControlLog,
+    /// Paused by disk space watchdog, not by user
+    pub paused_low_disk_space: bool,
+    pub free_disk_space: Option<u64>,
     pub progress_buckets_download: ProgressHistory,
     pub progress_buckets_unpack: ProgressHistory,
+    /// Downsampled history of the whole run
+    pub history: RunHistory,
     pub finish_time: Option<TimePair>,
+    /// Time spent on syncing data to disk (see [crate::DurabilityMode])
+    pub sync_time: time::Duration,
+    pub syncing: bool,
     pub error_time: Option<time::Instant>,
     pub error_message_download: Option<String>,
     pub error_message_unpack: Option<String>,
     pub error_message: Option<String>,
+    /// First error of the pipeline, errors of other stages caused by stopping are not recorded
+    pub error: Option<DownloadError>,
+    pub stopped_by_user: bool,
+    /// Set when the pipeline finished, successfully or not
+    pub outcome: Option<Result<DownloadSummary, DownloadError>>,
     pub download_url: Option<String>,
+    pub target_path: Option<PathBuf>,
+    pub archive: Option<ArchiveInfo>,
     pub download_threads: usize,
     pub server_chunk_support: bool,
     pub unpacked_files: usize,
     pub last_unpacked_files: VecDeque<UnpackedFileInfo>,
+    pub verify_report: Option<VerifyReport>,
+    pub list_report: Option<ListReport>,
+    /// Sha256 of the downloaded file, set after the whole file was read by the decoder
+    pub download_sha256: Option<String>,
+    pub events: EventSender,
 }
 
 impl Default for InternalProgress {
@@ -148,22 +232,44 @@ impl Default for InternalProgress {
             total_download_size: None,
             total_downloaded: 0,
             chunk_downloaded: vec![],
+            thread_stats: vec![],
+            stage_blocked: StageBlockedTime::default(),
             total_unpacked: 0,
             total_unpack_size: None,
+            total_unpack_size_source: None,
+            total_unpack_size_estimated: false,
+            decoder_consumed: 0,
             stop_requested: false,
+            graceful_stop_requested: false,
             paused: false,
+            max_download_speed: None,
+            control_log: ControlLog::default(),
+            paused_low_disk_space: false,
+            free_disk_space: None,
             progress_buckets_download: ProgressHistory::new(),
             progress_buckets_unpack: ProgressHistory::new(),
+            history: RunHistory::default(),
             finish_time: None,
+            sync_time: time::Duration::ZERO,
+            syncing: false,
             error_time: None,
             error_message: None,
             error_message_download: None,
             error_message_unpack: None,
+            error: None,
+            stopped_by_user: false,
+            outcome: None,
             download_url: None,
+            target_path: None,
+            archive: None,
             download_threads: 0,
             server_chunk_support: false,
             last_unpacked_files: VecDeque::new(),
             unpacked_files: 0,
+            verify_report: None,
+            list_report: None,
+            download_sha256: None,
+            events: EventSender::default(),
         }
     }
 }
@@ -179,24 +285,40 @@ pub struct PipeDownloaderProgress {
     pub downloaded: usize,
     pub unpacked: usize,
     pub stop_requested: bool,
+    pub graceful_stop_requested: bool,
     pub paused: bool,
+    pub paused_low_disk_space: bool,
+    pub max_download_speed: Option<usize>,
+    /// Recent actions requested by the user
+    pub control_log: Vec<ControlLogEntry>,
+    pub free_disk_space: Option<u64>,
     pub elapsed_time_sec: f64,
     pub eta_sec: Option<u64>,
     pub finish_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub sync_time_sec: f64,
+    pub syncing: bool,
     pub current_download_speed: usize,
     pub current_unpack_speed: usize,
     pub error_message: Option<String>,
     pub error_message_download: Option<String>,
     pub error_message_unpack: Option<String>,
+    pub error: Option<DownloadError>,
     pub total_unpack_size: Option<usize>,
+    pub total_unpack_size_source: Option<UnpackSizeSource>,
+    pub total_unpack_size_estimated: bool,
     pub total_download_size: Option<usize>,
     pub download_url: Option<String>,
+    pub archive: Option<ArchiveInfo>,
     pub chunks_downloading: usize,
     pub chunks_total: usize,
     pub chunks_left: usize,
     pub current_chunks: BTreeMap<usize, DownloadChunkProgress>,
+    pub thread_stats: Vec<DownloadThreadStats>,
+    pub stage_blocked: StageBlockedTime,
     pub unpacked_files: usize,
     pub last_unpacked_files: VecDeque<UnpackedFileInfo>,
+    pub verify_report: Option<VerifyReport>,
+    pub list_report: Option<ListReport>,
     //pub unpack_chunks: BTreeMap<usize, UnpackChunkProgress>,
     //pub progress_buckets_download: ProgressHistory,
     //pub progress_buckets_unpack: ProgressHistory,
@@ -212,31 +334,98 @@ impl InternalProgress {
             downloaded: self.total_downloaded + self.chunk_downloaded.iter().sum::<usize>(),
             unpacked: self.total_unpacked,
             stop_requested: self.stop_requested,
+            graceful_stop_requested: self.graceful_stop_requested,
             paused: self.paused,
+            paused_low_disk_space: self.paused_low_disk_space,
+            max_download_speed: self.max_download_speed,
+            control_log: self.control_log.entries().to_vec(),
+            free_disk_space: self.free_disk_space,
             elapsed_time_sec: self.get_elapsed().as_secs_f64(),
             eta_sec: self.get_time_left_sec(),
             finish_time: self.finish_time.as_ref().and_then(|ts| ts.to_utc().ok()),
+            sync_time_sec: self.sync_time.as_secs_f64(),
+            syncing: self.syncing,
             current_download_speed: self.progress_buckets_download.get_speed(),
             current_unpack_speed: self.progress_buckets_unpack.get_speed(),
             error_message: self.error_message.clone(),
             error_message_download: self.error_message_download.clone(),
             error_message_unpack: self.error_message_unpack.clone(),
+            error: match &self.outcome {
+                Some(Err(err)) => Some(err.clone()),
+                _ => self.error.clone(),
+            },
             total_unpack_size: self.total_unpack_size,
+            total_unpack_size_source: self.total_unpack_size_source,
+            total_unpack_size_estimated: self.total_unpack_size_estimated,
             total_download_size: self.total_download_size,
             download_url: self.download_url.clone(),
+            archive: self.archive.clone(),
             chunks_downloading: self.chunk_downloaded.len(),
             chunks_total: self.total_chunks,
             chunks_left: self.unfinished_chunks.len(),
             //progress_buckets_download: self.progress_buckets_download.clone(),
             //progress_buckets_unpack: self.progress_buckets_unpack.clone(),
             current_chunks: self.current_chunks.clone(),
+            thread_stats: self
+                .thread_stats
+                .iter()
+                .map(|stats| DownloadThreadStats {
+                    current_speed: stats.speed_buckets.get_speed(),
+                    ..stats.clone()
+                })
+                .collect(),
+            stage_blocked: self.stage_blocked.clone(),
             server_chunk_support: self.server_chunk_support,
             unpacked_files: self.unpacked_files,
             last_unpacked_files: self.last_unpacked_files.clone(),
+            verify_report: self.verify_report.clone(),
+            list_report: self.list_report.clone(),
             //unpack_chunks: self.unpack_chunks.clone(),
         }
     }
 
+    pub fn summary(&self) -> DownloadSummary {
+        DownloadSummary {
+            download_url: self.download_url.clone(),
+            archive: self.archive.clone(),
+            target_path: self
+                .target_path
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            downloaded: self.total_downloaded + self.chunk_downloaded.iter().sum::<usize>(),
+            unpacked: self.total_unpacked,
+            unpacked_files: self.unpacked_files,
+            elapsed_time_sec: self.get_elapsed().as_secs_f64(),
+            sync_time_sec: self.sync_time.as_secs_f64(),
+            verify_report: self.verify_report.clone(),
+            list_report: self.list_report.clone(),
+            sha256: self.download_sha256.clone(),
+        }
+    }
+
+    pub fn record_history(&mut self, force: bool) {
+        let sample = HistorySample {
+            time_sec: self.get_elapsed().as_secs_f64(),
+            downloaded: self.total_downloaded + self.chunk_downloaded.iter().sum::<usize>(),
+            unpacked: self.total_unpacked,
+            unpacked_files: self.unpacked_files,
+        };
+        self.history.add_sample(sample, force);
+    }
+
+    /// Change paused state, returns event to emit (after releasing the lock) if the state changed
+    pub fn set_paused(&mut self, paused: bool) -> Option<PipeDownloaderEvent> {
+        if self.paused == paused {
+            return None;
+        }
+        self.paused = paused;
+        Some(if paused {
+            PipeDownloaderEvent::Paused
+        } else {
+            PipeDownloaderEvent::Resumed
+        })
+    }
+
     pub fn get_elapsed(&self) -> time::Duration {
         self.finish_time
             .as_ref()
@@ -249,6 +438,15 @@ impl InternalProgress {
         if self.finish_time.is_some() {
             return Some(0);
         }
+        let download_left = self.get_download_time_left_sec();
+        let unpack_left = self.get_unpack_time_left_sec();
+        match (download_left, unpack_left) {
+            (Some(download_left), Some(unpack_left)) => Some(download_left.max(unpack_left)),
+            (download_left, unpack_left) => download_left.or(unpack_left),
+        }
+    }
+
+    fn get_download_time_left_sec(&self) -> Option<u64> {
         let download_speed = self.get_download_speed();
         if download_speed < 100 {
             return None;
@@ -263,6 +461,57 @@ impl InternalProgress {
         None
     }
 
+    fn get_unpack_time_left_sec(&self) -> Option<u64> {
+        let unpack_speed = self.get_unpack_speed();
+        if unpack_speed < 100 {
+            return None;
+        }
+        self.total_unpack_size.map(|total_unpack_size| {
+            (total_unpack_size.saturating_sub(self.total_unpacked) / unpack_speed) as u64
+        })
+    }
+
+    /// Extrapolate unpack size from observed compression ratio,
+    /// when it's not known exactly from the format metadata.
+    pub fn update_unpack_size_estimate(&mut self) {
+        if let Some(total_unpack_size) = self.total_unpack_size {
+            if !self.total_unpack_size_estimated {
+                if self.total_unpacked <= total_unpack_size {
+                    return;
+                }
+                log::warn!(
+                    "Unpacked more than expected from {:?}, switching to estimation",
+                    self.total_unpack_size_source
+                );
+                self.total_unpack_size_source = Some(UnpackSizeSource::Estimated);
+                self.total_unpack_size_estimated = true;
+            }
+        }
+        let total_download_size = match self.total_download_size {
+            Some(total_download_size) => total_download_size,
+            None => return,
+        };
+        if self.decoder_consumed == 0 {
+            return;
+        }
+        let ratio = self.total_unpacked as f64 / self.decoder_consumed as f64;
+        let estimate = ((total_download_size as f64 * ratio) as usize).max(self.total_unpacked);
+        if self.total_unpack_size_source == Some(UnpackSizeSource::GzipTrailer) {
+            // ISIZE is exact modulo 2^32, pick matching value closest to the estimate
+            let wrap = 1u64 << 32;
+            let isize = self.total_unpack_size.unwrap_or(0) as u64 % wrap;
+            let mut candidate = isize;
+            while candidate + wrap / 2 < estimate as u64 || candidate < self.total_unpacked as u64 {
+                candidate += wrap;
+            }
+            self.total_unpack_size = Some(candidate as usize);
+            return;
+        }
+        self.total_unpack_size = Some(estimate);
+        self.total_unpack_size_source = Some(UnpackSizeSource::Estimated);
+        self.total_unpack_size_estimated = true;
+    }
+
     pub fn get_download_speed(&self) -> usize {
         if self.finish_time.is_some() {
             return 0;
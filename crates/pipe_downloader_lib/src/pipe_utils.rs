@@ -1,4 +1,5 @@
 use humansize::{FormatSizeOptions, SizeFormatter, DECIMAL};
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -9,21 +10,112 @@ pub fn bytes_to_human(bytes: usize) -> SizeFormatter<usize, FormatSizeOptions> {
     SizeFormatter::new(bytes, DECIMAL)
 }
 
-pub fn resolve_url(
-    download_url: String,
-    pc: Arc<Mutex<InternalProgress>>,
-) -> anyhow::Result<String> {
-    if download_url.ends_with(".link") {
-        loop {
-            if let Some(url) = pc.lock().unwrap().download_url.clone() {
-                break Ok(url);
+/// Free space on the filesystem where path is (or will be) located
+pub fn available_disk_space(path: &Path) -> std::io::Result<u64> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or_else(|| Path::new("."));
+    fs2::available_space(existing)
+}
+
+/// Pauses download when free space drops below threshold and resumes it when space is available again.
+/// Runs until stop is requested or the pipeline finished (also with error).
+pub fn disk_space_watchdog(path: PathBuf, threshold: u64, pc: Arc<Mutex<InternalProgress>>) {
+    let finished = |pc: &InternalProgress| pc.stop_requested || pc.outcome.is_some();
+    loop {
+        match available_disk_space(&path) {
+            Ok(available) => {
+                let mut pc = pc.lock().unwrap();
+                if finished(&pc) {
+                    break;
+                }
+                pc.free_disk_space = Some(available);
+                let mut event = None;
+                if available < threshold && !pc.paused {
+                    log::warn!(
+                        "Free disk space {} below {}, pausing download",
+                        bytes_to_human(available as usize),
+                        bytes_to_human(threshold as usize)
+                    );
+                    event = pc.set_paused(true);
+                    pc.paused_low_disk_space = true;
+                } else if available >= threshold && pc.paused_low_disk_space {
+                    log::info!(
+                        "Free disk space {} available again, resuming download",
+                        bytes_to_human(available as usize)
+                    );
+                    event = pc.set_paused(false);
+                    pc.paused_low_disk_space = false;
+                }
+                let events = pc.events.clone();
+                drop(pc);
+                if let Some(event) = event {
+                    events.emit(event);
+                }
             }
-            if pc.lock().unwrap().stop_requested {
-                return Err(anyhow::anyhow!("Stop requested"));
+            Err(err) => {
+                log::warn!("Failed to check free disk space: {:?}", err);
+                if finished(&pc.lock().unwrap()) {
+                    break;
+                }
             }
-            thread::sleep(Duration::from_secs(1));
         }
-    } else {
-        Ok(download_url)
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Resolve path of the tar entry inside dst the same way tar::Entry::unpack_in does.
+/// Returns None for entries that would be skipped when unpacking.
+pub fn entry_target_path(dst: &Path, entry_path: &Path) -> Option<PathBuf> {
+    let mut target = dst.to_path_buf();
+    for part in entry_path.components() {
+        match part {
+            Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+            Component::ParentDir => return None,
+            Component::Normal(part) => target.push(part),
+        }
+    }
+    if target == dst {
+        return None;
+    }
+    Some(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DownloadError;
+    use std::time::Instant;
+
+    #[test]
+    fn test_disk_space_watchdog() {
+        let pc = Arc::new(Mutex::new(InternalProgress::default()));
+        let watchdog = {
+            let pc = pc.clone();
+            thread::spawn(move || disk_space_watchdog(std::env::temp_dir(), u64::MAX, pc))
+        };
+        let start = Instant::now();
+        while !pc.lock().unwrap().paused_low_disk_space {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "download not paused"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(pc.lock().unwrap().paused);
+
+        // failed pipeline doesn't always set stop_requested
+        pc.lock().unwrap().outcome = Some(Err(DownloadError::Other {
+            message: "failed".to_string(),
+        }));
+        let start = Instant::now();
+        while !watchdog.is_finished() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "watchdog still running"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
     }
 }
@@ -0,0 +1,236 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::pipe_utils::entry_target_path;
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyDifferenceKind {
+    /// Entry type differs (e.g. file in archive, directory on disk)
+    Type,
+    Size,
+    Mode,
+    Content,
+    LinkTarget,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone)]
+pub struct VerifyDifference {
+    pub path: String,
+    pub kind: VerifyDifferenceKind,
+    pub expected: String,
+    pub found: String,
+}
+
+/// Result of comparing archive entries with the contents of the output directory
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "camelCase"))]
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub matching: usize,
+    /// Present in archive, not found on disk
+    pub missing: Vec<String>,
+    /// Found on disk, not present in archive
+    pub extra: Vec<String>,
+    pub different: Vec<VerifyDifference>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.different.is_empty()
+    }
+}
+
+pub struct Verifier {
+    dst: PathBuf,
+    seen: HashSet<PathBuf>,
+    report: VerifyReport,
+}
+
+fn sha256_of<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+impl Verifier {
+    pub fn new(dst: &Path) -> Self {
+        Self {
+            dst: dst.to_path_buf(),
+            seen: HashSet::new(),
+            report: VerifyReport::default(),
+        }
+    }
+
+    fn add_difference(
+        &mut self,
+        path: &Path,
+        kind: VerifyDifferenceKind,
+        expected: String,
+        found: String,
+    ) {
+        self.report.different.push(VerifyDifference {
+            path: path.display().to_string(),
+            kind,
+            expected,
+            found,
+        });
+    }
+
+    /// Compare single tar entry with the file on disk, entry data is consumed
+    pub fn check_entry<R: Read>(&mut self, entry: &mut tar::Entry<R>) -> std::io::Result<()> {
+        let entry_path = entry.path()?.to_path_buf();
+        let target = match entry_target_path(&self.dst, &entry_path) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let rel_path = target
+            .strip_prefix(&self.dst)
+            .unwrap_or(&target)
+            .to_path_buf();
+        // parents of every entry are created on unpack, so they are not extra
+        for ancestor in rel_path.ancestors() {
+            if !self.seen.insert(ancestor.to_path_buf()) {
+                break;
+            }
+        }
+
+        let metadata = match target.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                self.report.missing.push(rel_path.display().to_string());
+                return Ok(());
+            }
+        };
+        let entry_type = entry.header().entry_type();
+        let found_type = if metadata.is_dir() {
+            "directory"
+        } else if metadata.file_type().is_symlink() {
+            "symlink"
+        } else {
+            "file"
+        };
+        let expected_type = if entry_type.is_dir() {
+            "directory"
+        } else if entry_type.is_symlink() {
+            "symlink"
+        } else {
+            "file"
+        };
+        if found_type != expected_type {
+            self.add_difference(
+                &rel_path,
+                VerifyDifferenceKind::Type,
+                expected_type.to_string(),
+                found_type.to_string(),
+            );
+            return Ok(());
+        }
+
+        if entry_type.is_hard_link() {
+            // hard link entries carry no data, existence is all we can check
+            self.report.matching += 1;
+            return Ok(());
+        }
+        if entry_type.is_symlink() {
+            let expected_link = entry.link_name()?.map(|l| l.to_path_buf());
+            let found_link = fs::read_link(&target).ok();
+            if expected_link != found_link {
+                self.add_difference(
+                    &rel_path,
+                    VerifyDifferenceKind::LinkTarget,
+                    format!("{expected_link:?}"),
+                    format!("{found_link:?}"),
+                );
+            } else {
+                self.report.matching += 1;
+            }
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // tar crate does not preserve special bits when unpacking
+            let expected_mode = entry.header().mode()? & 0o777;
+            let found_mode = metadata.permissions().mode() & 0o777;
+            if expected_mode != found_mode {
+                self.add_difference(
+                    &rel_path,
+                    VerifyDifferenceKind::Mode,
+                    format!("{expected_mode:o}"),
+                    format!("{found_mode:o}"),
+                );
+                return Ok(());
+            }
+        }
+
+        if entry_type.is_file() {
+            let expected_size = entry.header().size()?;
+            if expected_size != metadata.len() {
+                self.add_difference(
+                    &rel_path,
+                    VerifyDifferenceKind::Size,
+                    expected_size.to_string(),
+                    metadata.len().to_string(),
+                );
+                return Ok(());
+            }
+            let expected_hash = sha256_of(entry)?;
+            let found_hash = sha256_of(&mut fs::File::open(&target)?)?;
+            if expected_hash != found_hash {
+                self.add_difference(
+                    &rel_path,
+                    VerifyDifferenceKind::Content,
+                    hex_string(&expected_hash),
+                    hex_string(&found_hash),
+                );
+                return Ok(());
+            }
+        }
+        self.report.matching += 1;
+        Ok(())
+    }
+
+    fn collect_extra(&mut self, dir: &Path) -> std::io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let rel_path = path.strip_prefix(&self.dst).unwrap_or(&path).to_path_buf();
+            if !self.seen.contains(&rel_path) {
+                self.report.extra.push(rel_path.display().to_string());
+                continue;
+            }
+            // do not follow symlinks to directories
+            if entry.file_type()?.is_dir() {
+                self.collect_extra(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk output directory to find files not present in the archive
+    pub fn finish(mut self) -> std::io::Result<VerifyReport> {
+        let dst = self.dst.clone();
+        self.collect_extra(&dst)?;
+        Ok(self.report)
+    }
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
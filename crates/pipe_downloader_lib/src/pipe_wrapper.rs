@@ -1,6 +1,8 @@
 use crate::pipe_progress::InternalProgress;
+use sha2::{Digest, Sha256};
 use std::io::{ErrorKind, Read};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 pub struct DataChunk {
     pub chunk_no: usize,
@@ -18,6 +20,7 @@ pub struct MpscReaderFromReceiver {
     debug: bool,
     is_unpack: bool,
     progress_context: Arc<Mutex<InternalProgress>>,
+    hasher: Option<Sha256>,
 }
 
 impl MpscReaderFromReceiver {
@@ -37,8 +40,26 @@ impl MpscReaderFromReceiver {
             debug,
             progress_context,
             is_unpack,
+            hasher: None,
         }
     }
+
+    /// Compute sha256 of all data read, see [MpscReaderFromReceiver::finish_sha256]
+    pub fn with_sha256(mut self) -> Self {
+        self.hasher = Some(Sha256::new());
+        self
+    }
+
+    /// Hex encoded sha256 of the data read so far
+    pub fn finish_sha256(&mut self) -> Option<String> {
+        self.hasher.take().map(|hasher| {
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()
+        })
+    }
 }
 
 impl Read for MpscReaderFromReceiver {
@@ -61,12 +82,33 @@ impl Read for MpscReaderFromReceiver {
                     Some(dt)
                 } else {
                     loop {
-                        let new_chunk = self.receiver.recv().map_err(|err| {
-                            std::io::Error::new(
-                                ErrorKind::InvalidData,
-                                format!("Receive error {err:?}"),
-                            )
-                        })?;
+                        let recv_start = Instant::now();
+                        let received = self.receiver.recv();
+                        {
+                            let waited = recv_start.elapsed().as_secs_f64();
+                            let mut pc = self.progress_context.lock().unwrap();
+                            if self.is_unpack {
+                                pc.stage_blocked.decode_input_sec += waited;
+                            } else {
+                                pc.stage_blocked.unpack_input_sec += waited;
+                            }
+                        }
+                        let new_chunk = match received {
+                            Ok(new_chunk) => new_chunk,
+                            // all senders finished without error, it's the end of the stream
+                            Err(_)
+                                if self.chunk_waiting_list.is_empty()
+                                    && !self.progress_context.lock().unwrap().stop_requested =>
+                            {
+                                return Ok(0);
+                            }
+                            Err(err) => {
+                                return Err(std::io::Error::new(
+                                    ErrorKind::InvalidData,
+                                    format!("Receive error {err:?}"),
+                                ));
+                            }
+                        };
                         if new_chunk.range.start == self.pos {
                             if self.debug {
                                 log::warn!("Found compatible chunk {}", self.pos);
@@ -99,18 +141,21 @@ impl Read for MpscReaderFromReceiver {
                         .remove(&(self.current_chunk_no - chunk_history));
                 }
             }
-        } else {
         }
 
         let min_val = std::cmp::min(self.current_buf.len() - self.current_buf_pos, buf.len());
 
         let src_slice = &self.current_buf[self.current_buf_pos..(self.current_buf_pos + min_val)];
         buf[0..min_val].copy_from_slice(src_slice);
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(src_slice);
+        }
         self.current_buf_pos += min_val;
         self.pos += min_val;
 
         if self.is_unpack {
             let mut pc = self.progress_context.lock().unwrap();
+            pc.decoder_consumed = self.pos;
 
             if let Some(chunk) = pc.current_chunks.get_mut(&self.current_chunk_no) {
                 chunk.unpacked = self.current_buf_pos;
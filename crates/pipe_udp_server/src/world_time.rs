@@ -1,8 +1,7 @@
 use sntpc::{Error, NtpContext, NtpResult, NtpTimestampGenerator, NtpUdpSocket, Result};
-use std::mem::MaybeUninit;
 use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
 use std::ops::Add;
-use std::sync::{Arc, Mutex, Once};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
 
 #[derive(Copy, Clone, Default)]
@@ -70,24 +69,11 @@ pub fn world_time() -> WorldTimer {
     *world_time_wrapper().world_timer.lock().unwrap()
 }
 pub fn world_time_wrapper() -> &'static WorldTimerWrapper {
-    static mut WORLD_TIME_WRAPPER: MaybeUninit<WorldTimerWrapper> = MaybeUninit::uninit();
-    static ONCE: Once = Once::new();
+    static WORLD_TIME_WRAPPER: OnceLock<WorldTimerWrapper> = OnceLock::new();
 
-    let world_time = WorldTimerWrapper {
+    WORLD_TIME_WRAPPER.get_or_init(|| WorldTimerWrapper {
         world_timer: Arc::new(Mutex::new(WorldTimer::default())),
-    };
-
-    // SAFETY: This is simple singleton pattern
-    // it shouldn't cause any problems
-    unsafe {
-        ONCE.call_once(|| {
-            // SAFETY: This is safe because we only write to the singleton once.
-            WORLD_TIME_WRAPPER.write(world_time);
-        });
-
-        // SAFETY: This is safe because singleton is initialized inside ONCE call
-        WORLD_TIME_WRAPPER.assume_init_ref()
-    }
+    })
 }
 
 pub fn init_world_time() {
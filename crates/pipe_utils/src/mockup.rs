@@ -28,7 +28,7 @@ pub async fn build_random_file(path: &Path, size: usize) -> io::Result<()> {
 
     let mut bytes_left: i64 = size as i64;
     loop {
-        if bytes_left < str.as_bytes().len() as i64 {
+        if bytes_left < str.len() as i64 {
             bytes_left -= file.write(&str.as_bytes()[0..bytes_left as usize])? as i64;
         } else {
             bytes_left -= file.write(str.as_bytes())? as i64;
@@ -0,0 +1,111 @@
+import React, { useContext, useState } from "react";
+import { BackendSettingsContext } from "./BackendSettingsProvider";
+import { backendFetch, jobQuery } from "./common/BackendCall";
+import { DateTime } from "luxon";
+import prettyBytes from "pretty-bytes";
+import "./ControlPanel.css";
+
+export interface ControlLogEntry {
+    time: string;
+    action: "pause" | "resume" | "stop" | "setSpeedLimit";
+    graceful?: boolean;
+    maxDownloadSpeed?: number | null;
+}
+
+interface ControlPanelProps {
+    paused: boolean;
+    stopRequested: boolean;
+    gracefulStopRequested: boolean;
+    maxDownloadSpeed: number | null;
+    controlLog: ControlLogEntry[];
+    jobId?: string;
+}
+
+function describeAction(entry: ControlLogEntry): string {
+    switch (entry.action) {
+        case "pause":
+            return "Paused";
+        case "resume":
+            return "Resumed";
+        case "stop":
+            return entry.graceful ? "Stop after current file" : "Stopped immediately";
+        case "setSpeedLimit":
+            return entry.maxDownloadSpeed
+                ? `Speed limit set to ${prettyBytes(entry.maxDownloadSpeed)}/s per thread`
+                : "Speed limit removed";
+    }
+}
+
+const ControlPanel = (props: ControlPanelProps) => {
+    const { backendSettings } = useContext(BackendSettingsContext);
+    const [speedLimit, setSpeedLimit] = useState("");
+
+    const control = (action: string, body?: object) => {
+        backendFetch(backendSettings, `/control/${action}${jobQuery(props.jobId, "?")}`, {
+            method: "POST",
+            body: body ? JSON.stringify(body) : undefined,
+        }).catch((e) => console.log("Error sending control action", e));
+    };
+
+    const applySpeedLimit = () => {
+        // value is in MB/s, empty removes the limit
+        const limit = parseFloat(speedLimit);
+        control("speed-limit", {
+            maxDownloadSpeed: isNaN(limit) || limit <= 0 ? null : Math.round(limit * 1000000),
+        });
+    };
+
+    const stopping = props.stopRequested || props.gracefulStopRequested;
+
+    return (
+        <div className="control-panel">
+            <div className="control-panel-buttons">
+                {props.paused ? (
+                    <button disabled={stopping} onClick={() => control("resume")}>
+                        Resume
+                    </button>
+                ) : (
+                    <button disabled={stopping} onClick={() => control("pause")}>
+                        Pause
+                    </button>
+                )}
+                <button disabled={stopping} onClick={() => control("stop", { graceful: true })}>
+                    Stop after current file
+                </button>
+                <button disabled={props.stopRequested} onClick={() => control("stop", { graceful: false })}>
+                    Stop now
+                </button>
+            </div>
+            <div className="control-panel-speed">
+                <span>
+                    Speed limit:{" "}
+                    {props.maxDownloadSpeed ? `${prettyBytes(props.maxDownloadSpeed)}/s per thread` : "none"}
+                </span>
+                <input
+                    placeholder="MB/s"
+                    value={speedLimit}
+                    disabled={stopping}
+                    onChange={(e) => setSpeedLimit(e.target.value)}
+                />
+                <button disabled={stopping} onClick={applySpeedLimit}>
+                    Set
+                </button>
+            </div>
+            {props.controlLog.length > 0 && (
+                <ul className="control-panel-log">
+                    {props.controlLog
+                        .slice(-5)
+                        .reverse()
+                        .map((entry) => (
+                            <li key={entry.time + entry.action}>
+                                {DateTime.fromISO(entry.time).toLocaleString(DateTime.TIME_WITH_SECONDS)}{" "}
+                                {describeAction(entry)}
+                            </li>
+                        ))}
+                </ul>
+            )}
+        </div>
+    );
+};
+
+export default ControlPanel;
@@ -3,6 +3,8 @@ import "./Dashboard.css";
 import { useConfigResult } from "./ConfigProvider";
 import BackendSettingsPage from "./BackendSettingsPage";
 import ProgressPage from "./ProgressPage";
+import JobsPage from "./JobsPage";
+import { Link, Route, Routes } from "react-router-dom";
 
 const Dashboard = () => {
     const configResult = useConfigResult();
@@ -20,8 +22,19 @@ const Dashboard = () => {
     }
     return (
         <div>
+            <div className="top-header">
+                <div className="top-header-title">Pipe downloader</div>
+                <div className="top-header-navigation">
+                    <Link to="/">Progress</Link>
+                    <Link to="/jobs">Jobs</Link>
+                </div>
+            </div>
             <div>
-                <ProgressPage />
+                <Routes>
+                    <Route path="/" element={<ProgressPage />} />
+                    <Route path="/jobs" element={<JobsPage />} />
+                    <Route path="/jobs/:jobId" element={<ProgressPage />} />
+                </Routes>
             </div>
         </div>
     );
@@ -0,0 +1,69 @@
+import React, { useContext, useEffect, useState } from "react";
+import { BackendSettingsContext } from "./BackendSettingsProvider";
+import { backendFetch, jobQuery } from "./common/BackendCall";
+import prettyBytes from "pretty-bytes";
+import "./HistoryGraph.css";
+
+interface HistoryPoint {
+    timeSec: number;
+    downloadSpeed: number;
+    unpackSpeed: number;
+    filesPerSec: number;
+    downloaded: number;
+    unpacked: number;
+    unpackedFiles: number;
+}
+
+interface History {
+    startTime: string;
+    intervalSec: number;
+    points: HistoryPoint[];
+}
+
+interface HistoryGraphProps {
+    // changing the value triggers reload of the history
+    refreshKey: number;
+    jobId?: string;
+}
+
+const WIDTH = 400;
+const HEIGHT = 120;
+
+const HistoryGraph = (props: HistoryGraphProps) => {
+    const { backendSettings } = useContext(BackendSettingsContext);
+    const [history, setHistory] = useState<History | null>(null);
+
+    useEffect(() => {
+        backendFetch(backendSettings, `/history?maxPoints=${WIDTH / 2}${jobQuery(props.jobId, "&")}`)
+            .then((response) => response.json())
+            .then((response_json) => setHistory(response_json.history))
+            .catch((e) => console.log("Error fetching history", e));
+    }, [backendSettings, props.refreshKey, props.jobId]);
+
+    if (history == null || history.points.length < 2) {
+        return null;
+    }
+    const points = history.points;
+    const maxTime = points[points.length - 1].timeSec;
+    const maxSpeed = Math.max(1, ...points.map((p) => Math.max(p.downloadSpeed, p.unpackSpeed)));
+    const line = (speed: (p: HistoryPoint) => number) =>
+        points
+            .map((p) => `${((p.timeSec / maxTime) * WIDTH).toFixed(1)},${(HEIGHT - (speed(p) / maxSpeed) * HEIGHT).toFixed(1)}`)
+            .join(" ");
+
+    return (
+        <div className="history-graph">
+            <div className="history-graph-header">
+                <span className="history-graph-download">Download</span>
+                <span className="history-graph-unpack">Unpack</span>
+                <span>max {prettyBytes(maxSpeed)}/s</span>
+            </div>
+            <svg viewBox={`0 0 ${WIDTH} ${HEIGHT}`} preserveAspectRatio="none">
+                <polyline className="history-graph-download-line" points={line((p) => p.downloadSpeed)} />
+                <polyline className="history-graph-unpack-line" points={line((p) => p.unpackSpeed)} />
+            </svg>
+        </div>
+    );
+};
+
+export default HistoryGraph;
@@ -0,0 +1,142 @@
+import React, { useCallback, useContext, useEffect, useState } from "react";
+import { Link } from "react-router-dom";
+import { BackendSettingsContext } from "./BackendSettingsProvider";
+import { backendFetch } from "./common/BackendCall";
+import { DateTime } from "luxon";
+import prettyBytes from "pretty-bytes";
+import "./JobsPage.css";
+
+type JobState = "queued" | "starting" | "running" | "succeeded" | "failed" | "cancelled";
+
+interface JobProgress {
+    downloaded: number;
+    totalDownloadSize: number | null;
+    currentDownloadSpeed: number;
+    paused: boolean;
+}
+
+interface JobInfo {
+    id: number;
+    url: string;
+    outputDir: string | null;
+    state: JobState;
+    createdAt: string;
+    startedAt: string | null;
+    finishedAt: string | null;
+    error: string | null;
+    progress: JobProgress | null;
+}
+
+function describeProgress(progress: JobProgress | null): string {
+    if (progress == null) {
+        return "";
+    }
+    const total = progress.totalDownloadSize ? ` / ${prettyBytes(progress.totalDownloadSize)}` : "";
+    const speed = progress.paused ? "paused" : `${prettyBytes(progress.currentDownloadSpeed)}/s`;
+    return `${prettyBytes(progress.downloaded)}${total} (${speed})`;
+}
+
+const JobsPage = () => {
+    const { backendSettings } = useContext(BackendSettingsContext);
+    const [jobs, setJobs] = useState<JobInfo[]>([]);
+    const [url, setUrl] = useState("");
+    const [outputDir, setOutputDir] = useState("");
+    const [submitError, setSubmitError] = useState<string | null>(null);
+    const [nextRefresh, setNextRefresh] = useState(0);
+
+    const loadJobs = useCallback(async () => {
+        try {
+            const response = await backendFetch(backendSettings, "/jobs");
+            const response_json = await response.json();
+            setJobs(response_json.jobs);
+        } catch (e) {
+            console.log("Error fetching jobs", e);
+        }
+    }, [backendSettings]);
+
+    useEffect(() => {
+        loadJobs().then(() => {
+            setTimeout(() => setNextRefresh(nextRefresh + 1), 1000);
+        });
+    }, [nextRefresh]);
+
+    const submitJob = async () => {
+        setSubmitError(null);
+        try {
+            const response = await backendFetch(backendSettings, "/jobs", {
+                method: "POST",
+                body: JSON.stringify({ url: url, outputDir: outputDir || undefined }),
+            });
+            const response_json = await response.json();
+            if (!response.ok) {
+                setSubmitError(response_json.error);
+                return;
+            }
+            setUrl("");
+            await loadJobs();
+        } catch (e) {
+            setSubmitError(`${e}`);
+        }
+    };
+
+    const jobAction = (jobId: number, action: string) => {
+        backendFetch(backendSettings, `/jobs/${jobId}/${action}`, { method: "POST" })
+            .then(() => loadJobs())
+            .catch((e) => console.log("Error sending job action", e));
+    };
+
+    const isActive = (job: JobInfo) => job.state == "queued" || job.state == "starting" || job.state == "running";
+
+    return (
+        <div className="jobs-page">
+            <div className="jobs-page-submit">
+                <input placeholder="Url of archive" value={url} onChange={(e) => setUrl(e.target.value)} />
+                <input
+                    placeholder="Output directory"
+                    value={outputDir}
+                    onChange={(e) => setOutputDir(e.target.value)}
+                />
+                <button disabled={url.length == 0} onClick={() => submitJob()}>
+                    Add job
+                </button>
+            </div>
+            {submitError && <div className="jobs-page-error">{submitError}</div>}
+            <table className="jobs-page-table">
+                <thead>
+                    <tr>
+                        <th>Id</th>
+                        <th>Url</th>
+                        <th>State</th>
+                        <th>Created</th>
+                        <th>Progress</th>
+                        <th></th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {jobs.map((job) => (
+                        <tr key={job.id}>
+                            <td>
+                                <Link to={`/jobs/${job.id}`}>{job.id}</Link>
+                            </td>
+                            <td className="jobs-page-url">{job.url}</td>
+                            <td title={job.error ?? undefined}>{job.state}</td>
+                            <td>{DateTime.fromISO(job.createdAt).toLocaleString(DateTime.TIME_WITH_SECONDS)}</td>
+                            <td>{describeProgress(job.progress)}</td>
+                            <td className="jobs-page-actions">
+                                {job.state == "running" &&
+                                    (job.progress?.paused ? (
+                                        <button onClick={() => jobAction(job.id, "resume")}>Resume</button>
+                                    ) : (
+                                        <button onClick={() => jobAction(job.id, "pause")}>Pause</button>
+                                    ))}
+                                {isActive(job) && <button onClick={() => jobAction(job.id, "cancel")}>Cancel</button>}
+                            </td>
+                        </tr>
+                    ))}
+                </tbody>
+            </table>
+        </div>
+    );
+};
+
+export default JobsPage;
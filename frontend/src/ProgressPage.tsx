@@ -1,14 +1,17 @@
-import React, { useCallback, useContext, useState } from "react";
+import React, { useCallback, useContext, useRef, useState } from "react";
 import { BackendSettingsContext } from "./BackendSettingsProvider";
 import { useBackendConfig } from "./ConfigProvider";
 import "./ProgressPage.css";
-import { backendFetch } from "./common/BackendCall";
+import { backendFetch, jobQuery } from "./common/BackendCall";
 import ProgressBar from "react-bootstrap/ProgressBar";
 import "bootstrap/dist/css/bootstrap.min.css";
 import { DateTime } from "luxon";
 import prettyBytes from "pretty-bytes";
 import DateBox from "./DateBox";
+import HistoryGraph from "./HistoryGraph";
+import ControlPanel, { ControlLogEntry } from "./ControlPanel";
 import { ImWarning } from "react-icons/all";
+import { useParams } from "react-router-dom";
 
 interface ProgressFileInfo {
     fileNo: number;
@@ -43,6 +46,27 @@ class ProgressChunkWrapper {
     }
 }
 
+interface DownloadThreadStats {
+    threadNo: number;
+    currentChunk: number | null;
+    chunksDownloaded: number;
+    bytesDownloaded: number;
+    currentSpeed: number;
+    timeToFirstByteMs: number | null;
+    requests: number;
+    reconnects: number;
+    retries: number;
+    lastError: string | null;
+}
+
+interface DownloadError {
+    kind: string;
+    message?: string;
+    status?: number;
+    url?: string;
+    path?: string;
+}
+
 interface Progress {
     chunkSize: number;
     chunksDownloading: number;
@@ -51,6 +75,7 @@ interface Progress {
     chunksLeft: number;
     chunksTotal: number;
     currentChunks: { [key: number]: ProgressChunk };
+    threadStats: DownloadThreadStats[];
     currentDownloadSpeed: number;
     currentUnpackSpeed: number;
     downloadUrl: string;
@@ -60,14 +85,23 @@ interface Progress {
     errorMessage: string | null;
     errorMessageDownload: string | null;
     errorMessageUnpack: string | null;
+    error: DownloadError | null;
     etaSec: number;
     finishTime: string | null;
+    syncTimeSec: number;
+    syncing: boolean;
     paused: boolean;
+    pausedLowDiskSpace: boolean;
+    freeDiskSpace: number | null;
     startTime: string;
     currentTime: string;
     stopRequested: boolean;
+    gracefulStopRequested: boolean;
+    maxDownloadSpeed: number | null;
+    controlLog: ControlLogEntry[];
     totalDownloadSize: number;
-    totalUnpackSize: number;
+    totalUnpackSize: number | null;
+    totalUnpackSizeEstimated: boolean;
     unpacked: number;
     unpackedFiles: number;
     lastUnpackedFiles: ProgressFileInfo[];
@@ -123,6 +157,48 @@ const ProgressChunk = (props: ProgressChunkProps) => {
     );
 };
 
+const ThreadStatsTable = (props: { threadStats: DownloadThreadStats[] }) => {
+    return (
+        <table className="thread-stats">
+            <thead>
+                <tr>
+                    <th>Thread</th>
+                    <th>Chunk</th>
+                    <th>Done</th>
+                    <th>Downloaded</th>
+                    <th>Speed</th>
+                    <th>TTFB</th>
+                    <th>Retries</th>
+                    <th>Reconnects</th>
+                    <th>Last error</th>
+                </tr>
+            </thead>
+            <tbody>
+                {props.threadStats.map((stats) => (
+                    <tr key={stats.threadNo}>
+                        <td>{stats.threadNo}</td>
+                        <td>{stats.currentChunk ?? "-"}</td>
+                        <td>{stats.chunksDownloaded}</td>
+                        <td>
+                            <HumanBytes bytes={stats.bytesDownloaded} />
+                        </td>
+                        <td>
+                            <HumanBytes bytes={stats.currentSpeed} />
+                            /s
+                        </td>
+                        <td>{stats.timeToFirstByteMs != null ? `${stats.timeToFirstByteMs}ms` : "-"}</td>
+                        <td>{stats.retries}</td>
+                        <td>{stats.reconnects}</td>
+                        <td className="thread-stats-error" title={stats.lastError ?? ""}>
+                            {stats.lastError ?? ""}
+                        </td>
+                    </tr>
+                ))}
+            </tbody>
+        </table>
+    );
+};
+
 interface LastFileEntryProps {
     lastFile: ProgressFileInfo;
 }
@@ -149,12 +225,42 @@ const LastFileEntry = (props: LastFileEntryProps) => {
 
 const ProgressPage = () => {
     const { backendSettings } = useContext(BackendSettingsContext);
+    // without job id the server picks the latest started job
+    const { jobId } = useParams();
     const config = useBackendConfig();
     const [progress, setProgress] = useState<Progress | null>(null);
 
     const [nextRefresh, setNextRefresh] = useState(0);
     const [chunkInfos, setChunkInfos] = useState<{ [key: number]: ProgressChunkWrapper }>({});
     const [finished, setFinished] = useState(false);
+    // latest snapshot pushed by the server, polling is used when the stream is not connected
+    const streamedProgress = useRef<Progress | null>(null);
+
+    React.useEffect(() => {
+        // EventSource cannot send the Authorization header, token is passed in the query instead
+        const params = new URLSearchParams();
+        if (backendSettings.enableBearerToken) {
+            params.set("accessToken", backendSettings.bearerToken);
+        }
+        if (jobId) {
+            params.set("job", jobId);
+        }
+        const source = new EventSource(`${backendSettings.backendUrl}/progress/stream?${params}`);
+        source.addEventListener("progress", (e) => {
+            streamedProgress.current = JSON.parse((e as MessageEvent).data);
+        });
+        source.addEventListener("end", () => {
+            source.close();
+        });
+        source.onerror = () => {
+            // browser reconnects with Last-Event-ID, poll in the meantime
+            streamedProgress.current = null;
+        };
+        return () => {
+            source.close();
+            streamedProgress.current = null;
+        };
+    }, [backendSettings, jobId]);
 
     const updateChunks = useCallback(
         (progress: Progress) => {
@@ -189,12 +295,17 @@ const ProgressPage = () => {
 
     const loadProgress = useCallback(async () => {
         try {
-            const response = await backendFetch(backendSettings, `/progress`);
-            const response_json = await response.json();
-            const progress: Progress = response_json.progress;
-            setProgress(response_json.progress);
+            let progress: Progress;
+            if (streamedProgress.current) {
+                progress = streamedProgress.current;
+            } else {
+                const response = await backendFetch(backendSettings, `/progress${jobQuery(jobId, "?")}`);
+                const response_json = await response.json();
+                progress = response_json.progress;
+            }
+            setProgress(progress);
 
-            updateChunks(response_json.progress);
+            updateChunks(progress);
 
             const finishTime = progress.finishTime ? DateTime.fromISO(progress.finishTime) : null;
             if (finishTime) {
@@ -213,7 +324,7 @@ const ProgressPage = () => {
             console.log(e);
             setProgress(null);
         }
-    }, [setProgress, chunkInfos, setChunkInfos, setFinished]);
+    }, [setProgress, chunkInfos, setChunkInfos, setFinished, jobId]);
 
     React.useEffect(() => {
         //timeout
@@ -253,6 +364,7 @@ const ProgressPage = () => {
         );
     }
     const progressPercent = (progress.downloaded / progress.totalDownloadSize) * 100;
+    const unpackPercent = progress.totalUnpackSize ? (progress.unpacked / progress.totalUnpackSize) * 100 : null;
     const serverTime = DateTime.fromISO(progress.currentTime);
     const etaSec = progress.etaSec;
     const eta = serverTime.plus({ seconds: etaSec });
@@ -274,11 +386,45 @@ const ProgressPage = () => {
                     )}
                 </div>
 
+                {progress.error && (
+                    <div className="progress-chunk-not-possible">
+                        <ImWarning />
+                        <div className="progress-chunk-not-possible-label">
+                            Download failed ({progress.error.kind}):{" "}
+                            {progress.errorMessage ??
+                                progress.errorMessageDownload ??
+                                progress.errorMessageUnpack ??
+                                progress.error.message}
+                        </div>
+                    </div>
+                )}
+
+                {progress.pausedLowDiskSpace && (
+                    <div className="progress-chunk-not-possible">
+                        <ImWarning />
+                        <div className="progress-chunk-not-possible-label">
+                            Download paused, low free disk space:{" "}
+                            <HumanBytes bytes={progress.freeDiskSpace ?? 0} />
+                        </div>
+                    </div>
+                )}
+
                 <div className={"download-url"}>
                     <div className={"header"}>Downloading file:</div>
                     <input readOnly={true} value={progress.downloadUrl} />
                 </div>
 
+                {!finishTime && !progress.error && (
+                    <ControlPanel
+                        paused={progress.paused}
+                        stopRequested={progress.stopRequested}
+                        gracefulStopRequested={progress.gracefulStopRequested}
+                        maxDownloadSpeed={progress.maxDownloadSpeed}
+                        controlLog={progress.controlLog}
+                        jobId={jobId}
+                    />
+                )}
+
                 {finishTime ? (
                     <div>
                         <table>
@@ -303,6 +449,12 @@ const ProgressPage = () => {
                                     <th>Elapsed time:</th>
                                     <td>{finishTime.diff(startTime).toFormat("hh:mm:ss")}</td>
                                 </tr>
+                                {progress.syncTimeSec > 0 && (
+                                    <tr>
+                                        <th>Time spent syncing to disk:</th>
+                                        <td>{progress.syncTimeSec.toFixed(2)}s</td>
+                                    </tr>
+                                )}
                             </tbody>
                         </table>
                     </div>
@@ -335,22 +487,46 @@ const ProgressPage = () => {
                                     <th>Unpacked:</th>
                                     <td>
                                         <HumanBytes bytes={progress.unpacked} />
+                                        {progress.totalUnpackSize && (
+                                            <>
+                                                /{progress.totalUnpackSizeEstimated && "~"}
+                                                <HumanBytes bytes={progress.totalUnpackSize} />
+                                            </>
+                                        )}
                                     </td>
                                 </tr>
                                 <tr>
                                     <th>Files unpacked:</th>
                                     <td>{progress.unpackedFiles}</td>
                                 </tr>
+                                {progress.syncing && (
+                                    <tr>
+                                        <th>Syncing to disk:</th>
+                                        <td>{progress.syncTimeSec.toFixed(2)}s</td>
+                                    </tr>
+                                )}
                                 <tr>
                                     <th>Percent downloaded:</th>
                                     <td>{progressPercent.toFixed(5)}%</td>
                                 </tr>
+                                {unpackPercent != null && (
+                                    <tr>
+                                        <th>Percent unpacked:</th>
+                                        <td>
+                                            {progress.totalUnpackSizeEstimated && "~"}
+                                            {unpackPercent.toFixed(5)}%
+                                        </td>
+                                    </tr>
+                                )}
                             </tbody>
                         </table>
                     </div>
                 )}
 
                 <ProgressBar striped variant="success" now={progressPercent} />
+                {unpackPercent != null && <ProgressBar striped variant="info" now={unpackPercent} />}
+
+                <HistoryGraph refreshKey={Math.floor(nextRefresh / 5)} jobId={jobId} />
 
                 {progress.lastUnpackedFiles
                     .slice(0)
@@ -372,6 +548,7 @@ const ProgressPage = () => {
                     <div>Active: {Object.keys(chunkInfos).length}</div>
                     <div>Download threads: {progress.downloadThreads}</div>
                 </div>
+                {progress.threadStats.length > 0 && <ThreadStatsTable threadStats={progress.threadStats} />}
                 {Object.entries(chunkInfos)
                     .reverse()
                     .map(([key, chunk]) => row(parseInt(key), chunk))}
@@ -6,6 +6,11 @@ interface BackendFetch {
     headers?: Headers;
 }
 
+// selects the job in server mode, empty when the latest started job is wanted
+export function jobQuery(jobId: string | undefined, separator: "?" | "&"): string {
+    return jobId ? `${separator}job=${encodeURIComponent(jobId)}` : "";
+}
+
 export function backendFetch(backendSettings: BackendSettings, uri: string, params?: BackendFetch): Promise<Response> {
     const headers = params?.headers ?? new Headers();
     const method = params?.method ?? "GET";
@@ -0,0 +1,278 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::error::InternalError;
+use actix_web::http::Method;
+use actix_web::{web, HttpResponse};
+use anyhow::bail;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+
+use crate::options::CliOptions;
+
+/// The only routes accepting the token in the query, EventSource and browser WebSocket
+/// can't set headers. Query strings end up in access logs, so other routes require
+/// the Authorization header.
+const QUERY_TOKEN_PATHS: &[&str] = &["/api/progress/stream", "/api/progress/ws"];
+
+/// Access granted by a token, control includes read access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiAccess {
+    Read,
+    Control,
+}
+
+/// Tokens required by the `/api` scope, when none is configured the API is open
+#[derive(Debug, Clone, Default)]
+pub struct ApiTokens {
+    control: Option<String>,
+    read: Option<String>,
+}
+
+fn load_token(
+    token: &Option<String>,
+    token_file: &Option<std::path::PathBuf>,
+    name: &str,
+) -> anyhow::Result<Option<String>> {
+    let token = match (token, token_file) {
+        (Some(_), Some(_)) => bail!("Use either --{name} or --{name}-file, not both"),
+        (Some(token), None) => token.trim().to_string(),
+        (None, Some(path)) => read_token_file(path)?,
+        (None, None) => return Ok(None),
+    };
+    if token.is_empty() {
+        bail!("Empty --{name} given");
+    }
+    Ok(Some(token))
+}
+
+fn read_token_file(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path)
+        .map(|token| token.trim().to_string())
+        .map_err(|err| anyhow::anyhow!("Cannot read token file {}: {}", path.display(), err))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+/// Compare without early exit, so the token can't be guessed from response time
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+impl ApiTokens {
+    pub fn from_options(opt: &CliOptions) -> anyhow::Result<Self> {
+        Ok(Self {
+            control: load_token(&opt.api_token, &opt.api_token_file, "api-token")?,
+            read: load_token(
+                &opt.api_read_token,
+                &opt.api_read_token_file,
+                "api-read-token",
+            )?,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.control.is_some() || self.read.is_some()
+    }
+
+    fn access(&self, token: &str) -> Option<ApiAccess> {
+        if let Some(control) = &self.control {
+            if token_matches(control, token) {
+                return Some(ApiAccess::Control);
+            }
+        }
+        if let Some(read) = &self.read {
+            if token_matches(read, token) {
+                return Some(ApiAccess::Read);
+            }
+        }
+        None
+    }
+
+    /// Check token from Authorization header, or `accessToken` query parameter
+    /// of the Server-Sent Events and WebSocket streams
+    pub fn authorize(&self, req: &ServiceRequest) -> Result<(), actix_web::Error> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        // everything changing state (control actions, submitting jobs) is a POST
+        let required = if req.method() != Method::GET && req.method() != Method::HEAD {
+            ApiAccess::Control
+        } else {
+            ApiAccess::Read
+        };
+        let header_token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let token = header_token.or_else(|| {
+            if !QUERY_TOKEN_PATHS.contains(&req.path()) {
+                return None;
+            }
+            web::Query::<TokenQuery>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.into_inner().access_token)
+        });
+        let (mut response, message) = match token.and_then(|token| self.access(&token)) {
+            Some(access) if access >= required => return Ok(()),
+            Some(_) => (
+                HttpResponse::Forbidden(),
+                "Token does not allow control actions",
+            ),
+            None => (
+                HttpResponse::Unauthorized(),
+                "Missing or invalid bearer token",
+            ),
+        };
+        let response = response
+            .insert_header(("WWW-Authenticate", "Bearer"))
+            .json(json!({ "error": message }));
+        Err(InternalError::from_response(message, response).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    fn tokens() -> ApiTokens {
+        ApiTokens {
+            control: Some("control-token".to_string()),
+            read: Some("read-token".to_string()),
+        }
+    }
+
+    async fn status(tokens: ApiTokens, req: test::TestRequest) -> StatusCode {
+        let metrics_tokens = tokens.clone();
+        // same wrapping as in main
+        let app = test::init_service(
+            App::new()
+                .service(
+                    web::resource("/metrics")
+                        .wrap_fn(move |req, srv| {
+                            let authorized = metrics_tokens.authorize(&req);
+                            let response = authorized.map(|_| srv.call(req));
+                            async move { response?.await }
+                        })
+                        .route(web::get().to(HttpResponse::Ok)),
+                )
+                .service(
+                    web::scope("/api")
+                        .wrap_fn(move |req, srv| {
+                            let authorized = tokens.authorize(&req);
+                            let response = authorized.map(|_| srv.call(req));
+                            async move { response?.await }
+                        })
+                        .route("/progress", web::get().to(HttpResponse::Ok))
+                        .route("/progress/stream", web::get().to(HttpResponse::Ok))
+                        .route("/progress/ws", web::get().to(HttpResponse::Ok))
+                        .route("/control/pause", web::post().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+        match app.call(req.to_request()).await {
+            Ok(response) => response.status(),
+            Err(err) => err.as_response_error().status_code(),
+        }
+    }
+
+    fn bearer(req: test::TestRequest, token: &str) -> test::TestRequest {
+        req.insert_header(("Authorization", format!("Bearer {token}")))
+    }
+
+    #[actix_web::test]
+    async fn test_missing_and_wrong_token() {
+        let progress = || test::TestRequest::get().uri("/api/progress");
+        assert_eq!(status(tokens(), progress()).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(tokens(), bearer(progress(), "wrong-token")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(tokens(), bearer(progress(), "control-token")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(ApiTokens::default(), progress()).await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_read_token_scope() {
+        let pause = || test::TestRequest::post().uri("/api/control/pause");
+        assert_eq!(
+            status(
+                tokens(),
+                bearer(test::TestRequest::get().uri("/api/progress"), "read-token")
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(tokens(), bearer(pause(), "read-token")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(tokens(), bearer(pause(), "control-token")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_token() {
+        let metrics = || test::TestRequest::get().uri("/metrics");
+        assert_eq!(status(tokens(), metrics()).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(tokens(), bearer(metrics(), "read-token")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(ApiTokens::default(), metrics()).await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_query_token_only_for_streams() {
+        let with_query =
+            |path: &str| test::TestRequest::get().uri(&format!("{path}?accessToken=read-token"));
+        assert_eq!(
+            status(tokens(), with_query("/api/progress/stream")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(tokens(), with_query("/api/progress/ws")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                tokens(),
+                test::TestRequest::get().uri("/api/progress/ws?accessToken=wrong-token")
+            )
+            .await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(tokens(), with_query("/api/progress")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(tokens(), with_query("/metrics")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::Context;
+use humansize::{SizeFormatter, DECIMAL};
+use pipe_downloader_lib::DownloadError;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::jobs::{JobInfo, JobManager, JobRequest, JobState};
+use crate::signals::{self, SignalAction, Signals};
+
+/// Archives listed in manifest file, either a list of entries or `archives:` with the list.
+/// YAML parser is used for JSON manifests as well.
+pub fn load_manifest(path: &Path) -> anyhow::Result<Vec<JobRequest>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read manifest {}", path.display()))?;
+    let manifest: serde_yaml::Value = serde_yaml::from_str(&text)
+        .with_context(|| format!("Invalid manifest {}", path.display()))?;
+    let entries = match manifest {
+        serde_yaml::Value::Sequence(entries) => entries,
+        serde_yaml::Value::Mapping(mut manifest) => match manifest.remove("archives") {
+            Some(serde_yaml::Value::Sequence(entries)) if manifest.is_empty() => entries,
+            _ => anyhow::bail!(
+                "Manifest {} has to be a list of archives or contain only `archives` list",
+                path.display()
+            ),
+        },
+        _ => anyhow::bail!("Manifest {} does not list any archives", path.display()),
+    };
+    if entries.is_empty() {
+        anyhow::bail!("Manifest {} does not list any archives", path.display());
+    }
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            serde_yaml::from_value(entry).with_context(|| {
+                format!("Invalid entry {} of manifest {}", idx + 1, path.display())
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchJobProgress {
+    pub id: u64,
+    pub url: String,
+    pub output_dir: Option<String>,
+    pub state: JobState,
+    pub downloaded: usize,
+    pub total_download_size: Option<usize>,
+    pub current_download_speed: usize,
+    pub error: Option<DownloadError>,
+}
+
+/// Combined progress of all archives of the manifest
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchProgress {
+    pub total: usize,
+    pub queued: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub downloaded: usize,
+    /// Known only when sizes of all archives are known
+    pub total_download_size: Option<usize>,
+    pub current_download_speed: usize,
+    pub current_unpack_speed: usize,
+    pub elapsed_time_sec: f64,
+    pub jobs: Vec<BatchJobProgress>,
+}
+
+impl BatchProgress {
+    fn new(jobs: &[JobInfo], elapsed: Duration) -> Self {
+        let count = |state: JobState| jobs.iter().filter(|job| job.state == state).count();
+        let progress = || jobs.iter().filter_map(|job| job.progress.as_ref());
+        Self {
+            total: jobs.len(),
+            queued: count(JobState::Queued),
+            running: count(JobState::Starting) + count(JobState::Running),
+            succeeded: count(JobState::Succeeded),
+            failed: count(JobState::Failed),
+            cancelled: count(JobState::Cancelled),
+            downloaded: progress().map(|progress| progress.downloaded).sum(),
+            total_download_size: jobs
+                .iter()
+                .map(|job| job.progress.as_ref()?.total_download_size)
+                .sum(),
+            current_download_speed: progress()
+                .map(|progress| progress.current_download_speed)
+                .sum(),
+            current_unpack_speed: progress()
+                .map(|progress| progress.current_unpack_speed)
+                .sum(),
+            elapsed_time_sec: elapsed.as_secs_f64(),
+            jobs: jobs
+                .iter()
+                .map(|job| BatchJobProgress {
+                    id: job.id,
+                    url: job.url.clone(),
+                    output_dir: job.output_dir.clone(),
+                    state: job.state,
+                    downloaded: job.progress.as_ref().map_or(0, |p| p.downloaded),
+                    total_download_size: job.progress.as_ref().and_then(|p| p.total_download_size),
+                    current_download_speed: job
+                        .progress
+                        .as_ref()
+                        .map_or(0, |p| p.current_download_speed),
+                    error: job.error.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn human_line(&self) -> String {
+        let size = match self.total_download_size {
+            Some(total) => format!(
+                "{} / {}",
+                SizeFormatter::new(self.downloaded, DECIMAL),
+                SizeFormatter::new(total, DECIMAL)
+            ),
+            None => format!("{}", SizeFormatter::new(self.downloaded, DECIMAL)),
+        };
+        format!(
+            "[{}/{} done] running {}, queued {}, failed {} | {} | download {}/s, unpack {}/s",
+            self.succeeded + self.failed + self.cancelled,
+            self.total,
+            self.running,
+            self.queued,
+            self.failed,
+            size,
+            SizeFormatter::new(self.current_download_speed, DECIMAL),
+            SizeFormatter::new(self.current_unpack_speed, DECIMAL),
+        )
+    }
+}
+
+/// Print combined progress until all archives are finished. Fails with error of the first
+/// failed archive, so the exit status reflects it.
+pub async fn run_batch(
+    jobs: &JobManager,
+    signals: &mut Signals,
+    json: bool,
+) -> Result<(), DownloadError> {
+    let start = Instant::now();
+    loop {
+        let progress = BatchProgress::new(&jobs.list(), start.elapsed());
+        if json {
+            println!("{}", serde_json::to_string(&progress).unwrap());
+        } else {
+            println!("{}", progress.human_line());
+        }
+        if jobs.all_done() {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(1000)) => {},
+            action = signals.recv() => match action {
+                SignalAction::Stop => {
+                    println!("Kill signal received, cancelling jobs");
+                    jobs.cancel_all();
+                }
+                SignalAction::ForceExit => signals::force_exit(),
+                SignalAction::Pause => jobs.set_all_paused(true),
+                SignalAction::Resume => jobs.set_all_paused(false),
+            },
+        }
+    }
+
+    let finished = jobs.list();
+    if !json {
+        for job in &finished {
+            match &job.error {
+                Some(err) => println!("{:?} {}: {}", job.state, job.url, err),
+                None => println!("{:?} {}", job.state, job.url),
+            }
+        }
+    }
+    let failed = finished.iter().filter(|job| job.state == JobState::Failed);
+    if let Some(error) = failed.clone().find_map(|job| job.error.clone()) {
+        if !json {
+            println!("{} of {} archives failed", failed.count(), finished.len());
+        }
+        return Err(error);
+    }
+    if finished.iter().any(|job| job.state == JobState::Cancelled) {
+        return Err(DownloadError::StoppedByUser);
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use humansize::{SizeFormatter, DECIMAL};
+use pipe_downloader_lib::{BenchReport, BenchStage};
+use std::time::Duration;
+
+use crate::exit_code::UsageError;
+use crate::options::BenchOptions;
+
+fn speed(bytes_per_sec: f64) -> String {
+    format!("{}/s", SizeFormatter::new(bytes_per_sec as usize, DECIMAL))
+}
+
+fn stage_name(stage: BenchStage) -> &'static str {
+    match stage {
+        BenchStage::Network => "network",
+        BenchStage::Decode => "decode",
+        BenchStage::Write => "write",
+    }
+}
+
+fn print_report(report: &BenchReport) {
+    let row = |name: &str, value: String| println!("{:<18}{}", format!("{name}:"), value);
+    row("Url", report.url.clone());
+    row(
+        "Size",
+        report
+            .total_download_size
+            .map(|size| SizeFormatter::new(size, DECIMAL).to_string())
+            .unwrap_or_else(|| "unknown".to_string()),
+    );
+    if let Some(format) = report.format {
+        row(
+            "Compression",
+            match report.compression_ratio {
+                Some(ratio) => format!("{format:?}, ratio {ratio:.2}"),
+                None => format!("{format:?}"),
+            }
+            .to_lowercase(),
+        );
+    }
+    if !report.network.is_empty() {
+        println!("Network:");
+        for network in &report.network {
+            println!(
+                "  {:>3} connections  {:>12}  ({} in {:.1}s)",
+                network.threads,
+                speed(network.bytes_per_sec),
+                SizeFormatter::new(network.bytes, DECIMAL),
+                network.elapsed_time_sec
+            );
+        }
+    }
+    if !report.decode.is_empty() {
+        println!("Decode:");
+        for decode in &report.decode {
+            println!(
+                "  {:<8} {:>12} compressed, {:>12} decoded{}",
+                format!("{:?}", decode.format).to_lowercase(),
+                speed(decode.compressed_bytes_per_sec),
+                speed(decode.decoded_bytes_per_sec),
+                if decode.archive_codec {
+                    " (archive)"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+    if let Some(write) = &report.write {
+        row(
+            "Write",
+            format!(
+                "{} to {} ({} files)",
+                speed(write.bytes_per_sec),
+                write.path,
+                write.files
+            ),
+        );
+    }
+    println!("Stage throughput in downloaded bytes:");
+    for (stage, value) in [
+        (BenchStage::Network, report.network_bytes_per_sec),
+        (BenchStage::Decode, report.decode_bytes_per_sec),
+        (BenchStage::Write, report.write_bytes_per_sec),
+    ] {
+        if let Some(value) = value {
+            println!("  {:<8} {:>12}", stage_name(stage), speed(value));
+        }
+    }
+    if let (Some(bottleneck), Some(expected)) = (report.bottleneck, report.expected_bytes_per_sec) {
+        row(
+            "Bottleneck",
+            format!(
+                "{}, expected speed {}",
+                stage_name(bottleneck),
+                speed(expected)
+            ),
+        );
+    }
+    let recommendation = &report.recommendation;
+    row(
+        "Recommended",
+        format!(
+            "--download-threads {} --download-buffer {} --unpack-buffer {}",
+            recommendation.download_threads,
+            recommendation.chunk_size_downloader,
+            recommendation.chunk_size_decoder
+        ),
+    );
+    if !report.warnings.is_empty() {
+        println!("Warnings:");
+        for warning in &report.warnings {
+            println!("  - {warning}");
+        }
+    }
+}
+
+/// `bench` subcommand
+pub async fn run_bench(opt: &BenchOptions) -> anyhow::Result<()> {
+    if !opt.duration_sec.is_finite() || opt.duration_sec <= 0.0 {
+        return Err(UsageError("--duration-sec has to be positive".to_string()).into());
+    }
+    let url = opt.url.clone();
+    let options = pipe_downloader_lib::BenchOptions {
+        max_time: Duration::from_secs_f64(opt.duration_sec),
+        max_bytes: opt.max_bytes,
+        thread_counts: opt.threads.clone(),
+        sample_size: opt.sample_size,
+        all_codecs: opt.all_codecs,
+        target_dir: opt.output_dir.clone(),
+        ..Default::default()
+    };
+    // blocking http client can't be used from async context
+    let report =
+        tokio::task::spawn_blocking(move || pipe_downloader_lib::bench(&url, &options)).await??;
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
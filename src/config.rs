@@ -0,0 +1,528 @@
+use anyhow::{bail, Context};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::clap::ArgMatches;
+use structopt::StructOpt;
+
+use crate::options::CliOptions;
+
+const ENV_PREFIX: &str = "PIPE_DOWNLOADER_";
+const CONFIG_FILE_NAME: &str = "pipe_downloader.toml";
+const PROGRAM_NAME: &str = "pipe_downloader";
+/// Tokens are not shown by print-config
+const SECRET_OPTIONS: &[&str] = &["api-token", "api-read-token"];
+
+/// Options which can't be used together (`conflicts_with` of [CliOptions]), the one set
+/// in the source with higher precedence resets the other one
+const CONFLICTS: &[(&str, &str)] = &[("json", "ndjson"), ("verify", "list")];
+
+/// Where the effective value of the option comes from, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingSource {
+    CommandLine,
+    Environment,
+    ConfigFile,
+    Default,
+}
+
+impl SettingSource {
+    fn name(self) -> &'static str {
+        match self {
+            SettingSource::CommandLine => "command line",
+            SettingSource::Environment => "environment",
+            SettingSource::ConfigFile => "config file",
+            SettingSource::Default => "default",
+        }
+    }
+}
+
+/// Where the merged options come from, printed by `print-config`
+pub struct OptionSources {
+    pub config_file: Option<PathBuf>,
+    pub sources: BTreeMap<String, SettingSource>,
+}
+
+/// `PIPE_DOWNLOADER_DOWNLOAD_THREADS` for `download-threads`
+pub fn env_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('-', "_").to_uppercase())
+}
+
+/// Environment variables of the process, not unicode ones are skipped
+pub fn env_vars() -> BTreeMap<String, String> {
+    std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .collect()
+}
+
+fn options_to_map(options: &CliOptions) -> anyhow::Result<serde_json::Map<String, Value>> {
+    match serde_json::to_value(options)? {
+        Value::Object(options) => Ok(options),
+        _ => bail!("Options are not serialized as object"),
+    }
+}
+
+/// Options that can be set in config file with their default values,
+/// a boolean default means the option is a flag
+fn known_options() -> anyhow::Result<serde_json::Map<String, Value>> {
+    options_to_map(&CliOptions::from_iter_safe([PROGRAM_NAME])?)
+}
+
+/// `--config` (or `PIPE_DOWNLOADER_CONFIG`) or first existing of the default locations
+fn find_config_file(cli: &CliOptions, env: &BTreeMap<String, String>) -> Option<PathBuf> {
+    if let Some(path) = &cli.config {
+        return Some(path.clone());
+    }
+    default_config_files(env)
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// `./pipe_downloader.toml`, `$XDG_CONFIG_HOME/pipe_downloader/config.toml`
+/// (or `~/.config/...`) and `/etc/pipe_downloader/config.toml`
+pub fn default_config_files(env: &BTreeMap<String, String>) -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    let config_home = match env.get("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env
+            .get("HOME")
+            .map(|home| PathBuf::from(home).join(".config")),
+    };
+    if let Some(config_home) = config_home {
+        files.push(config_home.join("pipe_downloader/config.toml"));
+    }
+    files.push(PathBuf::from("/etc/pipe_downloader/config.toml"));
+    files
+}
+
+fn toml_to_args(key: &str, value: &toml::Value, is_flag: bool) -> anyhow::Result<Vec<String>> {
+    let flag = format!("--{key}");
+    Ok(match value {
+        toml::Value::Boolean(enabled) if is_flag => {
+            if *enabled {
+                vec![flag]
+            } else {
+                Vec::new()
+            }
+        }
+        _ if is_flag => bail!("Option {key} is a flag, expected true or false"),
+        toml::Value::String(s) => vec![flag, s.clone()],
+        toml::Value::Integer(i) => vec![flag, i.to_string()],
+        toml::Value::Float(f) => vec![flag, f.to_string()],
+        toml::Value::Array(values) => {
+            let mut args = Vec::new();
+            for value in values {
+                args.extend(toml_to_args(key, value, false)?);
+            }
+            args
+        }
+        _ => bail!("Unsupported value of option {key}"),
+    })
+}
+
+fn env_to_args(
+    key: &str,
+    value: &str,
+    is_flag: bool,
+    is_list: bool,
+) -> anyhow::Result<Vec<String>> {
+    let flag = format!("--{key}");
+    if is_flag {
+        return match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(vec![flag]),
+            "" | "0" | "false" | "no" | "off" => Ok(Vec::new()),
+            _ => bail!("{} is a flag, expected true or false", env_name(key)),
+        };
+    }
+    if is_list {
+        // comma separated, like PIPE_DOWNLOADER_CORS_ORIGIN=http://a,http://b
+        return Ok(value
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .flat_map(|value| [flag.clone(), value.to_string()])
+            .collect());
+    }
+    Ok(vec![flag, value.to_string()])
+}
+
+/// Parses arguments built from one source alone, so the values are checked the same way
+/// as on the command line, returns typed values of the given options
+fn parse_source(
+    args: Vec<String>,
+    keys: &[String],
+) -> anyhow::Result<serde_json::Map<String, Value>> {
+    let options = CliOptions::from_iter_safe(std::iter::once(PROGRAM_NAME.to_string()).chain(args))
+        .map_err(|err| anyhow::anyhow!(err.message))?;
+    let mut values = options_to_map(&options)?;
+    values.retain(|key, _| keys.contains(key));
+    Ok(values)
+}
+
+/// Flag turned on, or option with a value
+fn is_set(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => false,
+        Value::Array(values) => !values.is_empty(),
+        _ => true,
+    }
+}
+
+/// Merge options with precedence: command line, `PIPE_DOWNLOADER_*` environment variables,
+/// config file, defaults. Every source is parsed on its own and the typed values are merged,
+/// an option set in a source with higher precedence also resets options conflicting with it.
+pub fn merge_options(
+    matches: &ArgMatches,
+    env: &BTreeMap<String, String>,
+) -> anyhow::Result<(CliOptions, OptionSources)> {
+    let cli = CliOptions::from_clap(matches);
+    let known = known_options()?;
+    let config_file = find_config_file(&cli, env);
+    let config: toml::Table = match &config_file {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Cannot read config file {}", path.display()))?;
+            toml::from_str(&text)
+                .with_context(|| format!("Invalid config file {}", path.display()))?
+        }
+        None => toml::Table::new(),
+    };
+
+    let mut config_args = Vec::new();
+    let mut config_keys = Vec::new();
+    for (key, value) in &config {
+        // snake_case keys are accepted too
+        let key = key.replace('_', "-");
+        let Some(default) = known.get(&key) else {
+            bail!(
+                "Unknown option {} in config file {}",
+                key,
+                config_file.as_ref().unwrap().display()
+            );
+        };
+        config_args.extend(
+            toml_to_args(&key, value, default.is_boolean())
+                .with_context(|| format!("Invalid value in config file for {key}"))?,
+        );
+        config_keys.push(key);
+    }
+    let config_values = parse_source(config_args, &config_keys).with_context(|| {
+        format!(
+            "Invalid config file {}",
+            config_file.as_ref().unwrap().display()
+        )
+    })?;
+
+    let mut env_args = Vec::new();
+    let mut env_keys = Vec::new();
+    for (key, default) in &known {
+        if let Some(value) = env.get(&env_name(key)) {
+            env_args.extend(env_to_args(
+                key,
+                value,
+                default.is_boolean(),
+                default.is_array(),
+            )?);
+            env_keys.push(key.clone());
+        }
+    }
+    let env_values = parse_source(env_args, &env_keys)
+        .context("Invalid value of PIPE_DOWNLOADER_* environment variable")?;
+
+    // subcommand options are in the matches of the subcommand, they are not counted here
+    let cli_values = {
+        let mut values = options_to_map(&cli)?;
+        values.retain(|key, _| matches.occurrences_of(key) > 0);
+        values
+    };
+
+    let mut merged = known.clone();
+    let mut sources: BTreeMap<String, SettingSource> = known
+        .keys()
+        .map(|key| (key.clone(), SettingSource::Default))
+        .collect();
+    for (values, source) in [
+        (config_values, SettingSource::ConfigFile),
+        (env_values, SettingSource::Environment),
+        (cli_values, SettingSource::CommandLine),
+    ] {
+        for (key, value) in values {
+            if is_set(&value) {
+                for (a, b) in CONFLICTS {
+                    let other = match key.as_str() {
+                        k if k == *a => b,
+                        k if k == *b => a,
+                        _ => continue,
+                    };
+                    merged.insert(other.to_string(), known[*other].clone());
+                    sources.insert(other.to_string(), SettingSource::Default);
+                }
+            }
+            merged.insert(key.clone(), value);
+            sources.insert(key, source);
+        }
+    }
+
+    let mut options: CliOptions = serde_json::from_value(Value::Object(merged))?;
+    // not merged, they are taken from the command line only
+    options.command = cli.command;
+    options.url = cli.url;
+    options.config = cli.config;
+    Ok((
+        options,
+        OptionSources {
+            config_file,
+            sources,
+        },
+    ))
+}
+
+/// `print-config` subcommand, prints effective settings as TOML usable as config file
+pub fn print_config(
+    opt: &CliOptions,
+    merged: &OptionSources,
+    env: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    match &merged.config_file {
+        Some(path) if opt.config.is_some() => println!("# Config file: {}", path.display()),
+        Some(path) => println!("# Config file (default location): {}", path.display()),
+        None => println!(
+            "# No config file found, searched: {}",
+            default_config_files(env)
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+    println!(
+        "# Precedence: command line, {ENV_PREFIX}* environment variables, config file, defaults"
+    );
+    for (key, value) in options_to_map(opt)? {
+        let source = merged
+            .sources
+            .get(&key)
+            .copied()
+            .unwrap_or(SettingSource::Default);
+        let value = match value {
+            // not set, there is no null in TOML
+            Value::Null => {
+                println!("# {key} is not set");
+                continue;
+            }
+            _ if SECRET_OPTIONS.contains(&key.as_str()) => "\"<redacted>\"".to_string(),
+            Value::String(s) => toml::Value::String(s).to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::Array(values) => toml::Value::Array(
+                values
+                    .into_iter()
+                    .filter_map(|value| value.as_str().map(|s| toml::Value::String(s.to_string())))
+                    .collect(),
+            )
+            .to_string(),
+            Value::Object(_) => continue,
+        };
+        println!("{key} = {value}  # {}", source.name());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_file(text: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, text.as_bytes()).unwrap();
+        file
+    }
+
+    /// Explicit config file, so files in default locations don't affect the tests
+    fn merge_with_env(
+        config: &tempfile::NamedTempFile,
+        args: &[&str],
+        env: &[(&str, &str)],
+    ) -> anyhow::Result<(CliOptions, OptionSources)> {
+        let mut all = vec![PROGRAM_NAME, "--config", config.path().to_str().unwrap()];
+        all.extend_from_slice(args);
+        let matches = CliOptions::clap().get_matches_from_safe(all).unwrap();
+        let env = env
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        merge_options(&matches, &env)
+    }
+
+    fn merge(
+        config: &tempfile::NamedTempFile,
+        args: &[&str],
+    ) -> anyhow::Result<(CliOptions, OptionSources)> {
+        merge_with_env(config, args, &[])
+    }
+
+    #[test]
+    fn test_defaults() {
+        let (opt, merged) = merge(&config_file(""), &[]).unwrap();
+        assert_eq!(opt.download_threads, 4);
+        assert_eq!(merged.sources["download-threads"], SettingSource::Default);
+    }
+
+    #[test]
+    fn test_config_file() {
+        let config = config_file(
+            "download_threads = 7\nforce = true\ncors-origin = [\"http://a\", \"http://b\"]\ndurability = \"fsync\"\n",
+        );
+        let (opt, merged) = merge(&config, &["http://127.0.0.1/archive.tar.gz"]).unwrap();
+        assert_eq!(opt.download_threads, 7);
+        assert!(opt.force);
+        assert_eq!(opt.cors_origins, vec!["http://a", "http://b"]);
+        assert_eq!(
+            opt.durability,
+            pipe_downloader_lib::DurabilityMode::FsyncFiles
+        );
+        assert_eq!(opt.url.as_deref(), Some("http://127.0.0.1/archive.tar.gz"));
+        assert_eq!(
+            merged.sources["download-threads"],
+            SettingSource::ConfigFile
+        );
+
+        // command line wins over the config file
+        let (opt, merged) = merge(&config, &["-t", "9", "--cors-origin", "http://c"]).unwrap();
+        assert_eq!(opt.download_threads, 9);
+        assert_eq!(opt.cors_origins, vec!["http://c"]);
+        assert_eq!(
+            merged.sources["download-threads"],
+            SettingSource::CommandLine
+        );
+        assert_eq!(merged.sources["cors-origin"], SettingSource::CommandLine);
+    }
+
+    #[test]
+    fn test_environment() {
+        let config = config_file("limit-speed = 100\n");
+        let env = [(env_name("limit-speed"), "200"), (env_name("force"), "yes")];
+        let env: Vec<(&str, &str)> = env.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        let (opt, merged) = merge_with_env(&config, &[], &env).unwrap();
+        assert_eq!(opt.limit_speed, Some(200));
+        assert!(opt.force);
+        assert_eq!(merged.sources["limit-speed"], SettingSource::Environment);
+
+        let (opt, merged) = merge_with_env(&config, &["--limit-speed=300"], &env).unwrap();
+        assert_eq!(opt.limit_speed, Some(300));
+        assert_eq!(merged.sources["limit-speed"], SettingSource::CommandLine);
+
+        let err = merge_with_env(&config, &[], &[("PIPE_DOWNLOADER_LIMIT_SPEED", "fast")])
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("environment variable"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn test_conflicting_sources() {
+        let config = config_file("json = true\nlist = true\n");
+        let (opt, merged) = merge_with_env(
+            &config,
+            &["--ndjson"],
+            &[("PIPE_DOWNLOADER_VERIFY", "true")],
+        )
+        .unwrap();
+        assert!(opt.ndjson && !opt.json);
+        assert_eq!(merged.sources["json"], SettingSource::Default);
+        assert!(opt.verify && !opt.list);
+        assert_eq!(merged.sources["verify"], SettingSource::Environment);
+
+        // flag turned off doesn't reset the other one
+        let (opt, _) =
+            merge_with_env(&config, &[], &[("PIPE_DOWNLOADER_NDJSON", "false")]).unwrap();
+        assert!(opt.json && !opt.ndjson);
+    }
+
+    #[test]
+    fn test_conflicts_match_cli_options() {
+        for (a, b) in CONFLICTS {
+            let err =
+                CliOptions::from_iter_safe([PROGRAM_NAME, &format!("--{a}"), &format!("--{b}")])
+                    .err()
+                    .unwrap();
+            assert_eq!(err.kind, structopt::clap::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn test_option_names_match_arguments() {
+        // options given on the command line are found by the config key
+        for (key, default) in known_options().unwrap() {
+            let mut args = vec![PROGRAM_NAME.to_string(), format!("--{key}")];
+            match default {
+                Value::Bool(_) => {}
+                Value::String(s) => args.push(s),
+                Value::Number(n) => args.push(n.to_string()),
+                _ => args.push("1".to_string()),
+            }
+            if key.starts_with("list-") {
+                args.push("--list".to_string());
+            }
+            let matches = CliOptions::clap().get_matches_from_safe(args).unwrap();
+            assert_eq!(matches.occurrences_of(&key), 1, "{key}");
+        }
+    }
+
+    #[test]
+    fn test_subcommand_options() {
+        let config = config_file("output-dir = \"from-config\"\n");
+        let (opt, merged) = merge(
+            &config,
+            &[
+                "bench",
+                "-o",
+                "bench-dir",
+                "http://127.0.0.1/archive.tar.gz",
+            ],
+        )
+        .unwrap();
+        assert_eq!(opt.output_dir, Some(PathBuf::from("from-config")));
+        assert_eq!(merged.sources["output-dir"], SettingSource::ConfigFile);
+    }
+
+    #[test]
+    fn test_malformed_config_file() {
+        let err = merge(&config_file("download-threads = \n"), &[])
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("Invalid config file"),
+            "{err:#}"
+        );
+        let err = merge(&config_file("download-threads = \"many\"\n"), &[])
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("Invalid config file"),
+            "{err:#}"
+        );
+        let err = merge(&config_file("no-such-option = 1\n"), &[])
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("Unknown option no-such-option"),
+            "{err:#}"
+        );
+        let err = merge(&config_file("force = \"yes\"\n"), &[]).err().unwrap();
+        assert!(format!("{err:#}").contains("is a flag"), "{err:#}");
+        let missing = config_file("");
+        let path = missing.path().to_path_buf();
+        drop(missing);
+        let matches = CliOptions::clap()
+            .get_matches_from_safe([PROGRAM_NAME, "--config", path.to_str().unwrap()])
+            .unwrap();
+        let err = merge_options(&matches, &BTreeMap::new()).err().unwrap();
+        assert!(
+            format!("{err:#}").contains("Cannot read config file"),
+            "{err:#}"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use pipe_downloader_lib::DownloadError;
+use std::fmt::{Display, Formatter};
+
+/// Process exit codes, documented in README and `--help`
+pub const SUCCESS: u8 = 0;
+/// Unexpected error not covered by other codes
+pub const OTHER: u8 = 1;
+/// Invalid command line arguments, config file or combination of options
+pub const USAGE: u8 = 2;
+/// Connection failed, unexpected HTTP status or remote file changed
+pub const NETWORK: u8 = 3;
+pub const CHECKSUM_MISMATCH: u8 = 4;
+/// Compressed stream is corrupted
+pub const DECODE: u8 = 5;
+/// Archive is corrupted or unsupported, or extracted files can't be written
+pub const ARCHIVE: u8 = 6;
+pub const DISK_FULL: u8 = 7;
+/// Stopped by Ctrl+C, 128 + SIGINT like shells report it.
+/// Stopped by other signal the code is 128 + its number, see [crate::signals]
+pub const INTERRUPTED: u8 = 130;
+
+pub const HELP: &str = "EXIT CODES:
+    0    success
+    1    other error
+    2    invalid usage (arguments, config file)
+    3    network or HTTP error
+    4    checksum mismatch
+    5    decode error (corrupted compressed stream)
+    6    archive or extraction error
+    7    disk full
+    130  interrupted (128 + signal number, 143 for SIGTERM, 129 for SIGHUP)";
+
+/// Error in arguments or configuration, reported with [USAGE] exit code
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Mark error of parsing arguments or configuration as [UsageError]
+pub fn usage(err: anyhow::Error) -> anyhow::Error {
+    UsageError(format!("{err:#}")).into()
+}
+
+pub fn for_download_error(err: &DownloadError) -> u8 {
+    match err {
+        DownloadError::Network { .. }
+        | DownloadError::HttpStatus { .. }
+        | DownloadError::SourceChanged { .. } => NETWORK,
+        DownloadError::ChecksumMismatch { .. } => CHECKSUM_MISMATCH,
+        DownloadError::Decode { .. } => DECODE,
+        DownloadError::UnsupportedArchive { .. }
+        | DownloadError::Archive { .. }
+        | DownloadError::Io { .. } => ARCHIVE,
+        DownloadError::InsufficientDiskSpace { .. } | DownloadError::DiskFull { .. } => DISK_FULL,
+        // fixed by --force or different --output-dir
+        DownloadError::OutputExists { .. } => USAGE,
+        DownloadError::StoppedByUser => {
+            crate::signals::interrupted_exit_code().unwrap_or(INTERRUPTED)
+        }
+        DownloadError::Other { .. } => OTHER,
+    }
+}
+
+/// Exit code and one line summary of the error printed to stderr
+pub fn summary(err: &anyhow::Error) -> (u8, String) {
+    if let Some(err) = err.downcast_ref::<DownloadError>() {
+        let code = for_download_error(err);
+        return (
+            code,
+            format!("Error ({}, exit code {code}): {err}", err.kind()),
+        );
+    }
+    let code = if err.is::<UsageError>() { USAGE } else { OTHER };
+    // causes joined with ": ", so the summary stays on one line
+    let message = format!("{err:#}").replace('\n', " ");
+    (code, format!("Error (exit code {code}): {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> String {
+        "error".to_string()
+    }
+
+    #[test]
+    fn test_download_error_codes() {
+        let cases = [
+            (DownloadError::Network { message: message() }, NETWORK),
+            (
+                DownloadError::HttpStatus {
+                    status: 404,
+                    url: "http://example.com".to_string(),
+                },
+                NETWORK,
+            ),
+            (DownloadError::SourceChanged { message: message() }, NETWORK),
+            (
+                DownloadError::ChecksumMismatch {
+                    expected: "a".to_string(),
+                    actual: "b".to_string(),
+                },
+                CHECKSUM_MISMATCH,
+            ),
+            (DownloadError::Decode { message: message() }, DECODE),
+            (
+                DownloadError::UnsupportedArchive { message: message() },
+                ARCHIVE,
+            ),
+            (DownloadError::Archive { message: message() }, ARCHIVE),
+            (DownloadError::Io { message: message() }, ARCHIVE),
+            (
+                DownloadError::InsufficientDiskSpace {
+                    path: "/".to_string(),
+                    required: 2,
+                    available: 1,
+                },
+                DISK_FULL,
+            ),
+            (DownloadError::DiskFull { message: message() }, DISK_FULL),
+            (
+                DownloadError::OutputExists {
+                    path: "out".to_string(),
+                },
+                USAGE,
+            ),
+            (DownloadError::StoppedByUser, INTERRUPTED),
+            (DownloadError::Other { message: message() }, OTHER),
+        ];
+        for (err, code) in cases {
+            assert_eq!(for_download_error(&err), code, "{err:?}");
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let (code, line) = summary(&DownloadError::Decode { message: message() }.into());
+        assert_eq!(code, DECODE);
+        assert!(line.starts_with("Error (decode, exit code 5)"), "{line}");
+
+        let err = usage(anyhow::anyhow!("first line\nsecond line"));
+        assert_eq!(
+            summary(&err),
+            (
+                USAGE,
+                "Error (exit code 2): first line second line".to_string()
+            )
+        );
+
+        let err = anyhow::anyhow!("inner").context("outer");
+        assert_eq!(
+            summary(&err),
+            (OTHER, "Error (exit code 1): outer: inner".to_string())
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use humansize::{SizeFormatter, DECIMAL};
+use pipe_downloader_lib::{InspectReport, UnpackSizeSource};
+
+use crate::options::InspectOptions;
+
+fn size(bytes: usize) -> String {
+    SizeFormatter::new(bytes, DECIMAL).to_string()
+}
+
+fn duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+fn print_report(report: &InspectReport) {
+    let row = |name: &str, value: String| println!("{:<18}{}", format!("{name}:"), value);
+    row("Url", report.url.clone());
+    if report.download_url != report.url {
+        row("Link points to", report.download_url.clone());
+    }
+    if report.final_url != report.download_url {
+        row("Redirected to", report.final_url.clone());
+    }
+    row(
+        "Size",
+        report
+            .total_download_size
+            .map(size)
+            .unwrap_or_else(|| "unknown".to_string()),
+    );
+    row(
+        "Range requests",
+        if report.partial_content_support {
+            "supported"
+        } else {
+            "not supported"
+        }
+        .to_string(),
+    );
+    for (name, value) in [
+        ("ETag", &report.etag),
+        ("Last-Modified", &report.last_modified),
+        ("Content-Type", &report.content_type),
+    ] {
+        if let Some(value) = value {
+            row(name, value.clone());
+        }
+    }
+    row(
+        "Compression",
+        match (&report.detected_compression, &report.archive) {
+            (Some(detected), Some(archive)) => format!(
+                "{detected:?} (file name {}: {:?}{})",
+                archive.file_name,
+                archive.format,
+                if archive.is_tar { " tar" } else { "" }
+            ),
+            (Some(detected), None) => format!("{detected:?}"),
+            (None, _) => "unknown".to_string(),
+        }
+        .to_lowercase(),
+    );
+    if let Some(unpack_size) = report.total_unpack_size {
+        let source = match report.total_unpack_size_source {
+            Some(UnpackSizeSource::ZstdFrameHeader) => "zstd frame header",
+            Some(UnpackSizeSource::Lz4FrameHeader) => "lz4 frame header",
+            Some(UnpackSizeSource::XzIndex) => "xz index",
+            Some(UnpackSizeSource::GzipTrailer) => "gzip trailer",
+            Some(UnpackSizeSource::Estimated) | None => "estimate",
+        };
+        row(
+            "Unpacked size",
+            format!(
+                "{}{} (from {source})",
+                if report.total_unpack_size_estimated {
+                    "~"
+                } else {
+                    ""
+                },
+                size(unpack_size)
+            ),
+        );
+    }
+    if let Some(sample) = &report.speed_sample {
+        row(
+            "Speed sample",
+            format!(
+                "{} in {:.2}s, {}/s, first byte after {} ms",
+                size(sample.bytes),
+                sample.elapsed_time_sec,
+                size(sample.bytes_per_sec as usize),
+                sample.time_to_first_byte_ms
+            ),
+        );
+    }
+    if let Some(estimate) = report.estimated_download_time_sec {
+        row(
+            "Estimated time",
+            format!("{} with one connection", duration(estimate)),
+        );
+    }
+    match report.is_tar {
+        Some(true) => {
+            println!("Tar entries:");
+            for entry in &report.tar_entries {
+                println!(
+                    "  {:>10}  {:<10} {}",
+                    size(entry.size as usize),
+                    entry.entry_type,
+                    entry.path
+                );
+            }
+            if report.tar_entries_truncated {
+                println!("  ...");
+            }
+        }
+        Some(false) => row("Content", "single compressed file, not tar".to_string()),
+        None => {}
+    }
+    if !report.warnings.is_empty() {
+        println!("Warnings:");
+        for warning in &report.warnings {
+            println!("  - {warning}");
+        }
+    }
+}
+
+/// `inspect` subcommand
+pub async fn run_inspect(opt: &InspectOptions) -> anyhow::Result<()> {
+    let url = opt.url.clone();
+    let options = pipe_downloader_lib::InspectOptions {
+        sample_size: opt.sample_size,
+        max_entries: opt.entries,
+    };
+    // blocking http client can't be used from async context
+    let report =
+        tokio::task::spawn_blocking(move || pipe_downloader_lib::inspect(&url, &options)).await??;
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
@@ -0,0 +1,882 @@
+use actix_web::web::Data;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use pipe_downloader_lib::{
+    DownloadError, DurabilityMode, OutputMode, PipeDownloader, PipeDownloaderOptions,
+    PipeDownloaderProgress,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::run_history::{RunHistory, RunRecord};
+use crate::streaming::StreamHub;
+use crate::ServerData;
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Download submitted with POST /api/jobs or listed in manifest,
+/// options not given are taken from the command line
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct JobRequest {
+    pub url: String,
+    pub output_dir: Option<PathBuf>,
+    /// Expected sha256 of the downloaded file
+    pub sha256: Option<String>,
+    pub download_threads: Option<usize>,
+    pub limit_speed: Option<usize>,
+    pub force: Option<bool>,
+    pub ignore_symlinks: Option<bool>,
+    pub verify: Option<bool>,
+    /// none, fsync or syncfs
+    pub durability: Option<String>,
+}
+
+impl JobRequest {
+    fn to_options(&self, base: &PipeDownloaderOptions) -> anyhow::Result<PipeDownloaderOptions> {
+        if self.url.trim().is_empty() {
+            anyhow::bail!("Job url is empty");
+        }
+        let mut options = base.clone();
+        if let Some(download_threads) = self.download_threads {
+            if download_threads == 0 {
+                anyhow::bail!("downloadThreads has to be at least 1");
+            }
+            options.download_threads = download_threads;
+        }
+        if let Some(limit_speed) = self.limit_speed {
+            options.max_download_speed = Some(limit_speed);
+        }
+        if let Some(force) = self.force {
+            options.ignore_directory_exists = force;
+        }
+        if let Some(ignore_symlinks) = self.ignore_symlinks {
+            options.ignore_symlinks = ignore_symlinks;
+        }
+        if let Some(verify) = self.verify {
+            options.output_mode = if verify {
+                OutputMode::Verify
+            } else {
+                OutputMode::Unpack
+            };
+        }
+        if let Some(durability) = &self.durability {
+            options.durability = DurabilityMode::from_str(durability)?;
+        }
+        options.expected_sha256 = self.sha256.clone();
+        Ok(options)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Queued,
+    /// Download is being initialized (checking url, disk space)
+    Starting,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    fn is_active(self) -> bool {
+        matches!(self, JobState::Starting | JobState::Running)
+    }
+
+    fn is_done(self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// Started download of the job
+#[derive(Clone)]
+pub struct JobHandle {
+    pub id: u64,
+    pub pipe_downloader: Arc<Mutex<PipeDownloader>>,
+    pub stream_hub: Arc<StreamHub>,
+}
+
+struct Job {
+    id: u64,
+    request: JobRequest,
+    options: PipeDownloaderOptions,
+    state: JobState,
+    cancel_requested: bool,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    error: Option<DownloadError>,
+    handle: Option<JobHandle>,
+    /// Limit per thread requested by the user (job request or control API),
+    /// share of --max-total-speed is never above it
+    speed_limit: Option<usize>,
+    /// Share of --max-total-speed set to the running download
+    applied_speed_limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    pub id: u64,
+    pub url: String,
+    pub output_dir: Option<String>,
+    pub state: JobState,
+    /// Connections used by the download, lowered to --max-connections when taken from the command line
+    pub download_threads: usize,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<DownloadError>,
+    pub progress: Option<PipeDownloaderProgress>,
+}
+
+impl Job {
+    /// Info without progress, the download is locked by [with_progress] after the jobs lock
+    /// is released
+    fn info(&self) -> (JobInfo, Option<Arc<Mutex<PipeDownloader>>>) {
+        let info = JobInfo {
+            id: self.id,
+            url: self.request.url.clone(),
+            output_dir: self
+                .request
+                .output_dir
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            state: self.state,
+            download_threads: self.options.download_threads,
+            created_at: self.created_at,
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            error: self.error.clone(),
+            progress: None,
+        };
+        let pipe_downloader = self
+            .handle
+            .as_ref()
+            .map(|handle| handle.pipe_downloader.clone());
+        (info, pipe_downloader)
+    }
+}
+
+fn with_progress(
+    (mut info, pipe_downloader): (JobInfo, Option<Arc<Mutex<PipeDownloader>>>),
+) -> JobInfo {
+    info.progress = pipe_downloader.map(|pd| pd.lock().unwrap().get_progress());
+    info
+}
+
+#[derive(Debug)]
+pub enum JobActionError {
+    NotFound,
+    /// Action is not possible in the current state of the job
+    InvalidState(JobState),
+}
+
+struct JobsState {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+}
+
+/// Resources shared by all jobs
+#[derive(Debug, Clone, Default)]
+pub struct JobLimits {
+    /// Jobs running at the same time
+    pub max_concurrent_jobs: usize,
+    /// Download connections of all running jobs together
+    pub max_connections: Option<usize>,
+    /// Bytes per second of all running jobs together
+    pub max_total_speed: Option<usize>,
+    /// Finished jobs kept with their download and events, older ones are removed
+    pub max_finished_jobs: Option<usize>,
+}
+
+/// Keeps submitted downloads and starts queued ones while running jobs are below the limits
+pub struct JobManager {
+    state: Mutex<JobsState>,
+    base_options: PipeDownloaderOptions,
+    limits: JobLimits,
+    run_history: Option<Arc<RunHistory>>,
+}
+
+/// Error of the download start (setup errors are not [DownloadError])
+pub fn into_download_error(err: anyhow::Error) -> DownloadError {
+    match err.downcast::<DownloadError>() {
+        Ok(err) => err,
+        Err(err) => DownloadError::Other {
+            message: format!("{err:#}"),
+        },
+    }
+}
+
+impl JobManager {
+    pub fn new(
+        base_options: PipeDownloaderOptions,
+        limits: JobLimits,
+        run_history: Option<Arc<RunHistory>>,
+    ) -> Self {
+        Self {
+            state: Mutex::new(JobsState {
+                next_id: 1,
+                jobs: BTreeMap::new(),
+            }),
+            base_options,
+            limits: JobLimits {
+                max_concurrent_jobs: limits.max_concurrent_jobs.max(1),
+                max_connections: limits.max_connections.map(|max| max.max(1)),
+                ..limits
+            },
+            run_history,
+        }
+    }
+
+    fn new_job(state: &mut JobsState, request: JobRequest, options: PipeDownloaderOptions) -> u64 {
+        let id = state.next_id;
+        state.next_id += 1;
+        state.jobs.insert(
+            id,
+            Job {
+                id,
+                request,
+                state: JobState::Queued,
+                cancel_requested: false,
+                created_at: Utc::now(),
+                started_at: None,
+                finished_at: None,
+                error: None,
+                handle: None,
+                speed_limit: options.max_download_speed,
+                applied_speed_limit: None,
+                options,
+            },
+        );
+        id
+    }
+
+    /// Add job to the queue, it's started by [JobManager::run_scheduler].
+    /// Job asking for more connections than --max-connections is rejected, it could never start.
+    /// Threads taken from the command line are lowered to the limit, [JobInfo] shows the used value.
+    pub fn submit(&self, request: JobRequest) -> anyhow::Result<JobInfo> {
+        let mut options = request.to_options(&self.base_options)?;
+        if let Some(max_connections) = self.limits.max_connections {
+            if options.download_threads > max_connections {
+                if let Some(download_threads) = request.download_threads {
+                    anyhow::bail!(
+                        "downloadThreads {download_threads} is more than --max-connections {max_connections}"
+                    );
+                }
+                log::info!(
+                    "Download threads lowered from {} to --max-connections {}",
+                    options.download_threads,
+                    max_connections
+                );
+                options.download_threads = max_connections;
+            }
+        }
+        let info = {
+            let mut state = self.state.lock().unwrap();
+            let id = Self::new_job(&mut state, request, options);
+            log::info!("Job {} queued", id);
+            state.jobs[&id].info()
+        };
+        Ok(with_progress(info))
+    }
+
+    /// Register download started outside of the queue (single download mode)
+    pub fn add_started(
+        &self,
+        request: JobRequest,
+        pipe_downloader: PipeDownloader,
+        stream_hub: Arc<StreamHub>,
+    ) -> JobHandle {
+        let mut state = self.state.lock().unwrap();
+        let id = Self::new_job(&mut state, request, self.base_options.clone());
+        let handle = JobHandle {
+            id,
+            pipe_downloader: Arc::new(Mutex::new(pipe_downloader)),
+            stream_hub,
+        };
+        let job = state.jobs.get_mut(&id).unwrap();
+        job.state = JobState::Running;
+        job.started_at = Some(Utc::now());
+        job.handle = Some(handle.clone());
+        handle
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let infos: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .map(|job| job.info())
+            .collect();
+        infos.into_iter().map(with_progress).collect()
+    }
+
+    pub fn info(&self, id: u64) -> Option<JobInfo> {
+        let info = self
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .get(&id)
+            .map(|job| job.info());
+        info.map(with_progress)
+    }
+
+    /// Started downloads, used for metrics
+    pub fn handles(&self) -> Vec<JobHandle> {
+        self.state
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .filter_map(|job| job.handle.clone())
+            .collect()
+    }
+
+    /// Given job or the most recently started one when id is not given
+    pub fn handle(&self, id: Option<u64>) -> Option<JobHandle> {
+        let state = self.state.lock().unwrap();
+        match id {
+            Some(id) => state.jobs.get(&id).and_then(|job| job.handle.clone()),
+            None => state.jobs.values().rev().find_map(|job| job.handle.clone()),
+        }
+    }
+
+    pub fn set_paused(&self, id: u64, paused: bool) -> Result<JobInfo, JobActionError> {
+        let handle = {
+            let state = self.state.lock().unwrap();
+            let job = state.jobs.get(&id).ok_or(JobActionError::NotFound)?;
+            match (&job.handle, job.state) {
+                (Some(handle), JobState::Running) => handle.clone(),
+                (_, state) => return Err(JobActionError::InvalidState(state)),
+            }
+        };
+        {
+            let pd = handle.pipe_downloader.lock().unwrap();
+            if paused {
+                pd.pause_download();
+            } else {
+                pd.resume_download();
+            }
+        }
+        self.info(id).ok_or(JobActionError::NotFound)
+    }
+
+    /// Change speed limit of the started job, with --max-total-speed it caps the job's share
+    pub fn set_speed_limit(
+        &self,
+        id: u64,
+        max_download_speed: Option<usize>,
+    ) -> Result<(), JobActionError> {
+        let handle = {
+            let mut state = self.state.lock().unwrap();
+            let job = state.jobs.get_mut(&id).ok_or(JobActionError::NotFound)?;
+            let handle = job
+                .handle
+                .clone()
+                .ok_or(JobActionError::InvalidState(job.state))?;
+            job.speed_limit = max_download_speed.filter(|speed| *speed > 0);
+            job.applied_speed_limit = None;
+            handle
+        };
+        if self.limits.max_total_speed.is_some() {
+            self.balance_speed();
+        } else {
+            handle
+                .pipe_downloader
+                .lock()
+                .unwrap()
+                .set_max_download_speed(max_download_speed);
+        }
+        Ok(())
+    }
+
+    /// Remove queued job from the queue or stop the running one
+    pub fn cancel(&self, id: u64) -> Result<JobInfo, JobActionError> {
+        let handle = {
+            let mut state = self.state.lock().unwrap();
+            let job = state.jobs.get_mut(&id).ok_or(JobActionError::NotFound)?;
+            if job.state.is_done() {
+                return Err(JobActionError::InvalidState(job.state));
+            }
+            job.cancel_requested = true;
+            if job.state == JobState::Queued {
+                job.state = JobState::Cancelled;
+                job.finished_at = Some(Utc::now());
+            }
+            // job still starting is stopped as soon as the download is started
+            job.handle.clone()
+        };
+        if let Some(handle) = handle {
+            handle.pipe_downloader.lock().unwrap().signal_stop();
+        }
+        self.info(id).ok_or(JobActionError::NotFound)
+    }
+
+    pub fn cancel_all(&self) {
+        let ids: Vec<u64> = self.state.lock().unwrap().jobs.keys().copied().collect();
+        for id in ids {
+            let _ = self.cancel(id);
+        }
+    }
+
+    /// Pause or resume all running jobs
+    pub fn set_all_paused(&self, paused: bool) {
+        let ids: Vec<u64> = self.state.lock().unwrap().jobs.keys().copied().collect();
+        for id in ids {
+            let _ = self.set_paused(id, paused);
+        }
+    }
+
+    /// No job is queued, starting or running
+    pub fn all_done(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .all(|job| job.state.is_done())
+    }
+
+    fn record_run(&self, records: Vec<RunRecord>) {
+        if let Some(run_history) = &self.run_history {
+            for record in &records {
+                run_history.record(record);
+            }
+        }
+    }
+
+    /// Update state of finished downloads and record them in the run history
+    pub fn update_outcomes(&self) {
+        // downloads are not locked while holding the jobs lock
+        let running: Vec<(u64, Arc<Mutex<PipeDownloader>>)> = self
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .values()
+            .filter(|job| job.state == JobState::Running)
+            .filter_map(|job| Some((job.id, job.handle.as_ref()?.pipe_downloader.clone())))
+            .collect();
+        let finished: Vec<_> = running
+            .into_iter()
+            .filter_map(|(id, pipe_downloader)| {
+                let pd = pipe_downloader.lock().unwrap();
+                Some((id, pd.outcome()?, pd.get_progress()))
+            })
+            .collect();
+        let mut records = Vec::new();
+        let mut state = self.state.lock().unwrap();
+        for (id, outcome, progress) in finished {
+            let job = match state.jobs.get_mut(&id) {
+                Some(job) if job.state == JobState::Running => job,
+                _ => continue,
+            };
+            records.push(RunRecord::new(
+                &job.request.url,
+                job.request.output_dir.as_deref(),
+                &job.options,
+                job.started_at.unwrap_or(job.created_at),
+                Some(&progress),
+                &outcome,
+            ));
+            job.finished_at = Some(Utc::now());
+            job.state = match outcome {
+                Ok(_) => JobState::Succeeded,
+                Err(DownloadError::StoppedByUser) if job.cancel_requested => JobState::Cancelled,
+                Err(err) => {
+                    job.error = Some(err);
+                    JobState::Failed
+                }
+            };
+            log::info!("Job {} finished: {:?}", job.id, job.state);
+        }
+        drop(state);
+        self.record_run(records);
+    }
+
+    /// Remove the oldest finished jobs over --max-finished-jobs, together with their download,
+    /// progress history and buffer of stream events
+    fn evict_finished(&self) {
+        let max_finished_jobs = match self.limits.max_finished_jobs {
+            Some(max_finished_jobs) => max_finished_jobs,
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut finished: Vec<(DateTime<Utc>, u64)> = state
+            .jobs
+            .values()
+            .filter(|job| job.state.is_done())
+            .map(|job| (job.finished_at.unwrap_or(job.created_at), job.id))
+            .collect();
+        if finished.len() <= max_finished_jobs {
+            return;
+        }
+        finished.sort();
+        for (_, id) in &finished[..finished.len() - max_finished_jobs] {
+            state.jobs.remove(id);
+            log::debug!("Finished job {} removed", id);
+        }
+    }
+
+    /// Split --max-total-speed equally between download threads of running jobs,
+    /// limit set by the user is kept when it's lower
+    fn balance_speed(&self) {
+        let max_total_speed = match self.limits.max_total_speed {
+            Some(max_total_speed) => max_total_speed,
+            None => return,
+        };
+        let mut changed = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            let running_threads: usize = state
+                .jobs
+                .values()
+                .filter(|job| job.state == JobState::Running)
+                .map(|job| job.options.download_threads)
+                .sum();
+            if running_threads == 0 {
+                return;
+            }
+            let per_thread = (max_total_speed / running_threads).max(1);
+            for job in state
+                .jobs
+                .values_mut()
+                .filter(|job| job.state == JobState::Running)
+            {
+                let limit = job
+                    .speed_limit
+                    .map_or(per_thread, |own| own.min(per_thread));
+                if job.applied_speed_limit != Some(limit) {
+                    job.applied_speed_limit = Some(limit);
+                    changed.extend(job.handle.clone().map(|handle| (handle, limit)));
+                }
+            }
+        }
+        for (handle, limit) in changed {
+            log::debug!("Job {} speed limit per thread: {}", handle.id, limit);
+            handle
+                .pipe_downloader
+                .lock()
+                .unwrap()
+                .set_max_download_speed(Some(limit));
+        }
+    }
+
+    /// Pick queued jobs to start, in order of submission. The queue is strictly FIFO: a job waiting
+    /// for free connections blocks smaller jobs submitted after it, so big downloads are not starved.
+    fn schedule(&self) -> Vec<(u64, JobRequest, PipeDownloaderOptions)> {
+        self.update_outcomes();
+        self.evict_finished();
+        self.balance_speed();
+        let mut state = self.state.lock().unwrap();
+        let (active, mut connections) = state
+            .jobs
+            .values()
+            .filter(|job| job.state.is_active())
+            .fold((0, 0), |(count, connections), job| {
+                (count + 1, connections + job.options.download_threads)
+            });
+        let free_slots = self.limits.max_concurrent_jobs.saturating_sub(active);
+        let mut to_start = Vec::new();
+        for job in state.jobs.values_mut() {
+            if job.state != JobState::Queued {
+                continue;
+            }
+            if to_start.len() >= free_slots {
+                break;
+            }
+            if let Some(max_connections) = self.limits.max_connections {
+                if connections + job.options.download_threads > max_connections {
+                    break;
+                }
+            }
+            connections += job.options.download_threads;
+            job.state = JobState::Starting;
+            job.started_at = Some(Utc::now());
+            to_start.push((job.id, job.request.clone(), job.options.clone()));
+        }
+        to_start
+    }
+
+    async fn start_job(
+        self: Arc<Self>,
+        id: u64,
+        request: JobRequest,
+        options: PipeDownloaderOptions,
+    ) {
+        log::info!("Starting job {}: {}", id, request.url);
+        let stream_hub = Arc::new(StreamHub::default());
+        let result = options
+            .start_download_with_observer(
+                &request.url,
+                request.output_dir.clone(),
+                stream_hub.clone(),
+            )
+            .await;
+        let mut records = Vec::new();
+        let mut state = self.state.lock().unwrap();
+        let job = match state.jobs.get_mut(&id) {
+            Some(job) => job,
+            None => return,
+        };
+        match result {
+            Ok(pipe_downloader) => {
+                if job.cancel_requested {
+                    pipe_downloader.signal_stop();
+                }
+                job.state = JobState::Running;
+                job.handle = Some(JobHandle {
+                    id,
+                    pipe_downloader: Arc::new(Mutex::new(pipe_downloader)),
+                    stream_hub,
+                });
+            }
+            Err(err) => {
+                log::error!("Job {} failed to start: {:?}", id, err);
+                job.state = JobState::Failed;
+                job.finished_at = Some(Utc::now());
+                let err = into_download_error(err);
+                records.push(RunRecord::new(
+                    &request.url,
+                    request.output_dir.as_deref(),
+                    &job.options,
+                    job.started_at.unwrap_or(job.created_at),
+                    None,
+                    &Err(err.clone()),
+                ));
+                job.error = Some(err);
+            }
+        }
+        drop(state);
+        self.record_run(records);
+    }
+
+    /// Runs for the lifetime of the server
+    pub async fn run_scheduler(self: Arc<Self>) {
+        loop {
+            for (id, request, options) in self.schedule() {
+                actix_web::rt::spawn(self.clone().start_job(id, request, options));
+            }
+            tokio::time::sleep(SCHEDULER_INTERVAL).await;
+        }
+    }
+}
+
+fn job_action_response(result: Result<JobInfo, JobActionError>) -> HttpResponse {
+    match result {
+        Ok(job) => HttpResponse::Ok().json(json!({ "job": job })),
+        Err(JobActionError::NotFound) => {
+            HttpResponse::NotFound().json(json!({ "error": "Job not found" }))
+        }
+        Err(JobActionError::InvalidState(state)) => HttpResponse::Conflict()
+            .json(json!({ "error": format!("Action not possible, job is {state:?}") })),
+    }
+}
+
+pub async fn list_jobs_endpoint(server_data: Data<Box<ServerData>>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "jobs": server_data.jobs.list() }))
+}
+
+pub async fn submit_job_endpoint(
+    request: web::Json<JobRequest>,
+    server_data: Data<Box<ServerData>>,
+) -> HttpResponse {
+    match server_data.jobs.submit(request.into_inner()) {
+        Ok(job) => HttpResponse::Created().json(json!({ "job": job })),
+        Err(err) => HttpResponse::BadRequest().json(json!({ "error": err.to_string() })),
+    }
+}
+
+pub async fn job_endpoint(id: web::Path<u64>, server_data: Data<Box<ServerData>>) -> HttpResponse {
+    job_action_response(server_data.jobs.info(*id).ok_or(JobActionError::NotFound))
+}
+
+pub async fn pause_job_endpoint(
+    id: web::Path<u64>,
+    server_data: Data<Box<ServerData>>,
+) -> HttpResponse {
+    job_action_response(server_data.jobs.set_paused(*id, true))
+}
+
+pub async fn resume_job_endpoint(
+    id: web::Path<u64>,
+    server_data: Data<Box<ServerData>>,
+) -> HttpResponse {
+    job_action_response(server_data.jobs.set_paused(*id, false))
+}
+
+pub async fn cancel_job_endpoint(
+    id: web::Path<u64>,
+    server_data: Data<Box<ServerData>>,
+) -> HttpResponse {
+    job_action_response(server_data.jobs.cancel(*id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_manager(limits: JobLimits) -> JobManager {
+        let base_options = PipeDownloaderOptions {
+            download_threads: 4,
+            ..Default::default()
+        };
+        JobManager::new(base_options, limits, None)
+    }
+
+    fn request(download_threads: Option<usize>) -> JobRequest {
+        JobRequest {
+            url: "http://127.0.0.1:1/archive.tar.gz".to_string(),
+            download_threads,
+            ..Default::default()
+        }
+    }
+
+    fn started(manager: &JobManager) -> Vec<u64> {
+        manager
+            .schedule()
+            .into_iter()
+            .map(|(id, _, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn test_max_concurrent_jobs() {
+        let manager = job_manager(JobLimits {
+            max_concurrent_jobs: 2,
+            ..Default::default()
+        });
+        for _ in 0..3 {
+            manager.submit(request(None)).unwrap();
+        }
+        assert_eq!(started(&manager), vec![1, 2]);
+        // running jobs take the slots
+        assert!(started(&manager).is_empty());
+        assert_eq!(manager.info(3).unwrap().state, JobState::Queued);
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let manager = job_manager(JobLimits {
+            max_concurrent_jobs: 10,
+            max_connections: Some(6),
+            ..Default::default()
+        });
+        // more threads than the limit can never start
+        assert!(manager.submit(request(Some(8))).is_err());
+        // default threads from the command line are lowered to the limit
+        let manager_low = job_manager(JobLimits {
+            max_connections: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(
+            manager_low.submit(request(None)).unwrap().download_threads,
+            2
+        );
+
+        manager.submit(request(Some(4))).unwrap();
+        manager.submit(request(Some(4))).unwrap();
+        manager.submit(request(Some(1))).unwrap();
+        // FIFO, the small job waits behind the one needing free connections
+        assert_eq!(started(&manager), vec![1]);
+        assert!(started(&manager).is_empty());
+        assert_eq!(manager.info(3).unwrap().state, JobState::Queued);
+    }
+
+    #[test]
+    fn test_max_finished_jobs() {
+        let manager = job_manager(JobLimits {
+            max_finished_jobs: Some(2),
+            ..Default::default()
+        });
+        for id in 1..=4 {
+            manager.submit(request(None)).unwrap();
+            manager.cancel(id).unwrap();
+        }
+        manager.submit(request(None)).unwrap();
+        started(&manager);
+        let ids: Vec<u64> = manager.list().iter().map(|job| job.id).collect();
+        // queued and running jobs are not removed
+        assert_eq!(ids, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn test_speed_limit_caps_share() {
+        let manager = job_manager(JobLimits {
+            max_concurrent_jobs: 2,
+            max_total_speed: Some(8000),
+            ..Default::default()
+        });
+        manager.submit(request(None)).unwrap();
+        manager
+            .submit(JobRequest {
+                limit_speed: Some(500),
+                ..request(None)
+            })
+            .unwrap();
+        started(&manager);
+        {
+            let mut state = manager.state.lock().unwrap();
+            for job in state.jobs.values_mut() {
+                job.state = JobState::Running;
+            }
+        }
+        manager.balance_speed();
+        let applied = |id: u64| manager.state.lock().unwrap().jobs[&id].applied_speed_limit;
+        // 8 threads share the total speed, user's limit is kept when lower
+        assert_eq!(applied(1), Some(1000));
+        assert_eq!(applied(2), Some(500));
+
+        // limit changed at runtime stays a cap after rebalancing
+        manager
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .get_mut(&1)
+            .unwrap()
+            .speed_limit = Some(300);
+        manager.balance_speed();
+        assert_eq!(applied(1), Some(300));
+        manager
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .get_mut(&2)
+            .unwrap()
+            .state = JobState::Succeeded;
+        manager.balance_speed();
+        assert_eq!(applied(1), Some(300));
+    }
+
+    #[test]
+    fn test_cancel_queued() {
+        let manager = job_manager(JobLimits::default());
+        manager.submit(request(None)).unwrap();
+        manager.submit(request(None)).unwrap();
+        assert_eq!(started(&manager), vec![1]);
+        assert_eq!(manager.cancel(2).unwrap().state, JobState::Cancelled);
+        assert!(matches!(
+            manager.cancel(2),
+            Err(JobActionError::InvalidState(JobState::Cancelled))
+        ));
+        assert!(matches!(manager.cancel(5), Err(JobActionError::NotFound)));
+        assert!(started(&manager).is_empty());
+        assert!(!manager.all_done());
+    }
+}
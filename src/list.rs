@@ -0,0 +1,93 @@
+use chrono::{Local, TimeZone};
+use pipe_downloader_lib::{ListReport, ListedEntry, PipeDownloaderEvent, PipeDownloaderObserver};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::streaming::StreamHub;
+
+/// Prints listed entries as they are walked, other events are passed to the stream hub.
+/// Observer is used instead of a subscription, so no entry is dropped when stdout is slow.
+pub struct ListPrinter {
+    pub json: bool,
+    pub stream_hub: Arc<StreamHub>,
+}
+
+impl PipeDownloaderObserver for ListPrinter {
+    fn on_event(&self, event: &PipeDownloaderEvent) {
+        if let PipeDownloaderEvent::EntryListed { entry } = event {
+            let line = if self.json {
+                serde_json::to_string(entry).unwrap()
+            } else {
+                entry_line(entry)
+            };
+            let mut stdout = std::io::stdout().lock();
+            // consumer closed the pipe, nothing to do about it
+            let _ = writeln!(stdout, "{line}");
+        }
+        self.stream_hub.on_event(event);
+    }
+}
+
+/// `drwxr-xr-x` like `ls -l` and `tar -tv`
+fn mode_string(entry_type: &str, mode: u32) -> String {
+    let type_char = match entry_type {
+        "directory" => 'd',
+        "symlink" => 'l',
+        "link" => 'h',
+        "char" => 'c',
+        "block" => 'b',
+        "fifo" => 'p',
+        _ => '-',
+    };
+    let mut result = String::from(type_char);
+    for (bit, c) in [
+        (0o400, 'r'),
+        (0o200, 'w'),
+        (0o100, 'x'),
+        (0o040, 'r'),
+        (0o020, 'w'),
+        (0o010, 'x'),
+        (0o004, 'r'),
+        (0o002, 'w'),
+        (0o001, 'x'),
+    ] {
+        result.push(if mode & bit != 0 { c } else { '-' });
+    }
+    result
+}
+
+/// One line of `tar -tv` output
+fn entry_line(entry: &ListedEntry) -> String {
+    let mtime = Local
+        .timestamp_opt(entry.mtime as i64, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    let link = match (&entry.link_target, entry.entry_type.as_str()) {
+        (Some(target), "link") => format!(" link to {target}"),
+        (Some(target), _) => format!(" -> {target}"),
+        (None, _) => String::new(),
+    };
+    format!(
+        "{} {}/{} {:>12} {} {}{}",
+        mode_string(&entry.entry_type, entry.mode),
+        entry.owner,
+        entry.group,
+        entry.size,
+        mtime,
+        entry.path,
+        link
+    )
+}
+
+/// Summary goes to stderr, stdout has only the listing
+pub fn print_report(report: &ListReport) {
+    eprintln!("Listed {} of {} entries", report.listed, report.entries);
+    if let Some(path) = &report.stop_after_path {
+        if report.stop_after_path_found {
+            eprintln!("Found {path}, download stopped");
+        } else {
+            eprintln!("Not found in archive: {path}");
+        }
+    }
+}
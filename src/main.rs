@@ -1,26 +1,73 @@
+mod auth;
+mod batch;
+mod bench;
+mod config;
+mod exit_code;
 mod frontend;
+mod inspect;
+mod jobs;
+mod list;
+mod ndjson;
 mod options;
+mod run_history;
+mod signals;
+mod streaming;
+mod tui;
 
 use actix_web::web::Data;
-use actix_web::{web, App, HttpRequest, HttpServer, Responder};
-use std::sync::{Arc, Mutex};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use std::sync::Arc;
 
-use crate::options::CliOptions;
-use pipe_downloader_lib::{PipeDownloader, PipeDownloaderOptions};
+use crate::auth::ApiTokens;
+use crate::exit_code::UsageError;
+use crate::options::{CliOptions, Command};
+use crate::run_history::{default_state_dir, print_history, runs_endpoint, RunHistory, RunRecord};
+use pipe_downloader_lib::{
+    DownloadError, DownloadSummary, OutputMode, PipeDownloaderObserver, PipeDownloaderOptions,
+    PrometheusMetrics, VerifyReport,
+};
 
 use crate::frontend::frontend_serve;
 use crate::frontend::redirect_to_frontend;
+use crate::jobs::{
+    cancel_job_endpoint, into_download_error, job_endpoint, list_jobs_endpoint, pause_job_endpoint,
+    resume_job_endpoint, submit_job_endpoint, JobHandle, JobLimits, JobManager, JobRequest,
+};
+use crate::list::ListPrinter;
+use crate::signals::{SignalAction, Signals};
+use crate::streaming::{progress_sse_endpoint, progress_ws_endpoint, StreamHub};
+use serde::Deserialize;
 use serde_json::json;
 
+use std::process::ExitCode;
 use std::time::Duration;
 
-use actix_web::dev::ServerHandle;
+use actix_web::dev::{ServerHandle, Service};
 use structopt::StructOpt;
-use tokio::signal;
 
 #[derive(Clone)]
 pub struct ServerData {
-    pub pipe_downloader: Arc<Mutex<PipeDownloader>>,
+    pub jobs: Arc<JobManager>,
+    /// Default interval of progress snapshots in live streams
+    pub stream_interval: Duration,
+    /// Value of job_name label of the metrics
+    pub metrics_job: Option<String>,
+    /// Finished runs of this and previous invocations
+    pub run_history: Option<Arc<RunHistory>>,
+}
+
+#[derive(Deserialize)]
+pub struct JobQuery {
+    /// Job id, the most recently started job is used when not given
+    pub job: Option<u64>,
+}
+
+impl ServerData {
+    pub fn selected_job(&self, job: Option<u64>) -> actix_web::Result<JobHandle> {
+        self.jobs
+            .handle(job)
+            .ok_or_else(|| actix_web::error::ErrorNotFound("No started download"))
+    }
 }
 
 pub async fn config(_req: HttpRequest, _server_data: Data<Box<ServerData>>) -> impl Responder {
@@ -28,11 +75,123 @@ pub async fn config(_req: HttpRequest, _server_data: Data<Box<ServerData>>) -> i
     web::Json(json!({"config": {"version": VERSION}}))
 }
 async fn progress_endpoint(
-    _req: HttpRequest,
+    job: web::Query<JobQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<impl Responder> {
+    let job = server_data.selected_job(job.job)?;
+    let pd = job.pipe_downloader.lock().unwrap();
+    Ok(web::Json(json!({ "progress": pd.get_progress() })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryQuery {
+    /// Seconds since start of the download
+    from: Option<f64>,
+    to: Option<f64>,
+    max_points: Option<usize>,
+}
+
+async fn history_endpoint(
+    query: web::Query<HistoryQuery>,
+    job: web::Query<JobQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<impl Responder> {
+    let job = server_data.selected_job(job.job)?;
+    let pd = job.pipe_downloader.lock().unwrap();
+    Ok(web::Json(
+        json!({ "history": pd.get_history(query.from, query.to, query.max_points) }),
+    ))
+}
+
+async fn pause_endpoint(
+    job: web::Query<JobQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<impl Responder> {
+    let job = server_data.selected_job(job.job)?;
+    let pd = job.pipe_downloader.lock().unwrap();
+    pd.pause_download();
+    Ok(web::Json(json!({ "progress": pd.get_progress() })))
+}
+
+async fn resume_endpoint(
+    job: web::Query<JobQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<impl Responder> {
+    let job = server_data.selected_job(job.job)?;
+    let pd = job.pipe_downloader.lock().unwrap();
+    pd.resume_download();
+    Ok(web::Json(json!({ "progress": pd.get_progress() })))
+}
+
+#[derive(Deserialize)]
+struct StopRequest {
+    /// Finish the file currently being extracted before stopping
+    #[serde(default)]
+    graceful: bool,
+}
+
+async fn stop_endpoint(
+    body: Option<web::Json<StopRequest>>,
+    job: web::Query<JobQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<impl Responder> {
+    let job = server_data.selected_job(job.job)?;
+    let pd = job.pipe_downloader.lock().unwrap();
+    if body.map(|body| body.graceful).unwrap_or(false) {
+        pd.signal_graceful_stop();
+    } else {
+        pd.signal_stop();
+    }
+    Ok(web::Json(json!({ "progress": pd.get_progress() })))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpeedLimitRequest {
+    /// Bytes per second per download thread, null removes the limit
+    max_download_speed: Option<usize>,
+}
+
+async fn speed_limit_endpoint(
+    body: web::Json<SpeedLimitRequest>,
+    job: web::Query<JobQuery>,
     server_data: Data<Box<ServerData>>,
-) -> impl Responder {
-    let pd = server_data.pipe_downloader.lock().unwrap();
-    web::Json(json!({ "progress": pd.get_progress() }))
+) -> actix_web::Result<impl Responder> {
+    let job = server_data.selected_job(job.job)?;
+    // kept by the job manager, so it's not overwritten by share of --max-total-speed
+    server_data
+        .jobs
+        .set_speed_limit(job.id, body.max_download_speed)
+        .map_err(|_| actix_web::error::ErrorNotFound("No started download"))?;
+    let pd = job.pipe_downloader.lock().unwrap();
+    Ok(web::Json(json!({ "progress": pd.get_progress() })))
+}
+
+fn is_loopback(listen_addr: &str) -> bool {
+    listen_addr == "localhost"
+        || listen_addr
+            .parse::<std::net::IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+}
+
+async fn metrics_endpoint(server_data: Data<Box<ServerData>>) -> impl Responder {
+    let mut metrics = PrometheusMetrics::default();
+    for job in server_data.jobs.handles() {
+        let job_id = job.id.to_string();
+        let mut labels = vec![("job_id", job_id.as_str())];
+        if let Some(metrics_job) = &server_data.metrics_job {
+            labels.push(("job_name", metrics_job));
+        }
+        job.pipe_downloader
+            .lock()
+            .unwrap()
+            .add_metrics(&mut metrics, &labels);
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
 }
 
 struct StopHandle {
@@ -53,12 +212,102 @@ impl StopHandle {
     }
 }
 
+fn print_verify_report(report: &VerifyReport) {
+    for path in &report.missing {
+        println!("Missing: {path}");
+    }
+    for path in &report.extra {
+        println!("Extra: {path}");
+    }
+    for diff in &report.different {
+        println!(
+            "Different: {} ({:?} expected: {} found: {})",
+            diff.path, diff.kind, diff.expected, diff.found
+        );
+    }
+    println!(
+        "Verify summary: {} matching, {} missing, {} extra, {} different",
+        report.matching,
+        report.missing.len(),
+        report.extra.len(),
+        report.different.len()
+    );
+}
+
+/// Server mode runs until killed, then running jobs are cancelled.
+/// SIGUSR1 and SIGUSR2 pause and resume all running jobs.
+async fn serve_until_killed(jobs: &JobManager, signals: &mut Signals) {
+    loop {
+        match signals.recv().await {
+            SignalAction::Stop => break,
+            SignalAction::ForceExit => signals::force_exit(),
+            SignalAction::Pause => jobs.set_all_paused(true),
+            SignalAction::Resume => jobs.set_all_paused(false),
+        }
+    }
+    println!("Kill signal received, cancelling jobs");
+    jobs.cancel_all();
+    while !jobs.all_done() {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(200)) => {},
+            action = signals.recv() => {
+                if let SignalAction::ForceExit = action {
+                    signals::force_exit();
+                }
+            },
+        }
+    }
+}
+
 #[actix_web::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     env_logger::init();
-    let opt: CliOptions = CliOptions::from_args();
+    match run().await {
+        Ok(()) => ExitCode::from(exit_code::SUCCESS),
+        Err(err) => {
+            let (code, summary) = exit_code::summary(&err);
+            eprintln!("{summary}");
+            ExitCode::from(code)
+        }
+    }
+}
 
-    let pd = PipeDownloaderOptions {
+async fn run() -> anyhow::Result<()> {
+    let matches = match CliOptions::clap().get_matches_safe() {
+        Ok(matches) => matches,
+        // --help and --version
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => {
+            eprintln!("{}", err.message);
+            return Err(UsageError("Invalid command line arguments".to_string()).into());
+        }
+    };
+    let env = config::env_vars();
+    let (opt, merged) = config::merge_options(&matches, &env).map_err(exit_code::usage)?;
+    if let Some(Command::PrintConfig) = &opt.command {
+        return config::print_config(&opt, &merged, &env);
+    }
+    if let Some(Command::Inspect(inspect_opt)) = &opt.command {
+        return inspect::run_inspect(inspect_opt).await;
+    }
+    if let Some(Command::Bench(bench_opt)) = &opt.command {
+        return bench::run_bench(bench_opt).await;
+    }
+    let state_dir = opt.state_dir.clone().or_else(default_state_dir);
+    if let Some(Command::History(history_opt)) = &opt.command {
+        let state_dir = state_dir
+            .ok_or_else(|| anyhow::anyhow!("Cannot determine state directory, use --state-dir"))?;
+        return print_history(&RunHistory::new(&state_dir), history_opt);
+    }
+    // installed before the download is started, so a signal during url probing doesn't kill the process
+    let mut signals = Signals::new()?;
+    let run_history = match &state_dir {
+        Some(state_dir) if !opt.no_run_history => Some(Arc::new(RunHistory::new(state_dir))),
+        _ => None,
+    };
+    let api_tokens = ApiTokens::from_options(&opt).map_err(exit_code::usage)?;
+
+    let base_options = PipeDownloaderOptions {
         chunk_size_decoder: opt.unpack_buffer,
         chunk_size_downloader: opt.download_buffer,
         max_download_speed: opt.limit_speed,
@@ -66,42 +315,216 @@ async fn main() -> anyhow::Result<()> {
         download_threads: opt.download_threads,
         ignore_symlinks: opt.ignore_symlinks,
         ignore_directory_exists: opt.force,
+        output_mode: if opt.verify {
+            OutputMode::Verify
+        } else if opt.list {
+            OutputMode::List
+        } else if opt.raw {
+            OutputMode::StdoutRaw
+        } else if opt.output_to_stdout() {
+            OutputMode::Stdout
+        } else {
+            OutputMode::Unpack
+        },
+        check_disk_space: !opt.skip_disk_space_check,
+        min_free_disk_space: if opt.min_free_space > 0 {
+            Some(opt.min_free_space)
+        } else {
+            None
+        },
+        durability: opt.durability,
+        expected_sha256: opt.sha256.clone(),
+        list_filter: opt.list_filter.clone(),
+        list_stop_after_path: opt.list_stop_after.clone(),
+    };
+    let jobs = Arc::new(JobManager::new(
+        base_options.clone(),
+        JobLimits {
+            max_concurrent_jobs: opt.max_concurrent_jobs,
+            max_connections: opt.max_connections,
+            max_total_speed: opt.max_total_speed,
+            // manifest summary needs all jobs
+            max_finished_jobs: Some(opt.max_finished_jobs).filter(|_| opt.serve),
+        },
+        run_history.clone(),
+    ));
+    if opt.ndjson && (opt.manifest.is_some() || opt.serve) {
+        return Err(
+            UsageError("--ndjson is supported only for single download".to_string()).into(),
+        );
+    }
+    if opt.list && (opt.manifest.is_some() || opt.serve) {
+        return Err(UsageError("--list is supported only for single download".to_string()).into());
+    }
+    // with `-o -` stdout has the data, messages go to stderr
+    let to_stdout = opt.output_to_stdout();
+    if opt.raw && !to_stdout {
+        return Err(UsageError("--raw requires -o -".to_string()).into());
     }
-    .start_download(&opt.url, opt.output_dir)
-    .await?;
+    if to_stdout && (opt.manifest.is_some() || opt.serve || opt.ndjson || opt.list || opt.verify) {
+        return Err(UsageError(
+            "-o - can't be used with --manifest, --serve, --ndjson, --list or --verify".to_string(),
+        )
+        .into());
+    }
+    // started download in single download mode
+    let single_job = if let Some(manifest) = &opt.manifest {
+        if opt.url.is_some() || opt.serve {
+            return Err(
+                UsageError("--manifest can't be used with url or --serve".to_string()).into(),
+            );
+        }
+        // all entries are validated before anything is started
+        for request in batch::load_manifest(manifest).map_err(exit_code::usage)? {
+            jobs.submit(request).map_err(exit_code::usage)?;
+        }
+        actix_web::rt::spawn(jobs.clone().run_scheduler());
+        None
+    } else if opt.serve {
+        if let Some(url) = &opt.url {
+            jobs.submit(JobRequest {
+                url: url.clone(),
+                output_dir: opt.output_dir.clone(),
+                ..Default::default()
+            })
+            .map_err(exit_code::usage)?;
+        }
+        actix_web::rt::spawn(jobs.clone().run_scheduler());
+        None
+    } else {
+        let url = opt
+            .url
+            .clone()
+            .ok_or_else(|| UsageError("Url is required, unless --serve is used".to_string()))?;
+        let stream_hub = Arc::new(StreamHub::default());
+        let started_at = chrono::Utc::now();
+        let observer: Arc<dyn PipeDownloaderObserver> = if opt.list && !opt.ndjson {
+            Arc::new(ListPrinter {
+                json: opt.json,
+                stream_hub: stream_hub.clone(),
+            })
+        } else {
+            stream_hub.clone()
+        };
+        let start = base_options.clone().start_download_with_observer(
+            &url,
+            opt.output_dir.clone().filter(|_| !to_stdout),
+            observer,
+        );
+        // nothing is written before the download is started, no need to wait for it
+        let started = tokio::select! {
+            started = start => started,
+            _ = signals.recv_stop() => Err(DownloadError::StoppedByUser.into()),
+        };
+        let pd = match started {
+            Ok(pd) => pd,
+            Err(err) => {
+                let err = into_download_error(err);
+                if opt.ndjson {
+                    ndjson::print_start_failure(&stream_hub);
+                }
+                if let Some(run_history) = &run_history {
+                    run_history.record(&RunRecord::new(
+                        &url,
+                        opt.output_dir.as_deref(),
+                        &base_options,
+                        started_at,
+                        None,
+                        &Err(err.clone()),
+                    ));
+                }
+                return Err(err.into());
+            }
+        };
+        let request = JobRequest {
+            url,
+            output_dir: opt.output_dir.clone(),
+            ..Default::default()
+        };
+        Some(jobs.add_started(request, pd, stream_hub))
+    };
 
     let server_data = Data::new(Box::new(ServerData {
-        pipe_downloader: Arc::new(Mutex::new(pd)),
+        jobs: jobs.clone(),
+        stream_interval: Duration::from_millis(opt.stream_interval_ms),
+        metrics_job: opt.metrics_job.clone(),
+        run_history,
     }));
     let server_data_cloned = server_data.clone();
 
-    let (srv, stop_handle) = if !opt.frontend {
+    let (srv, stop_handle) = if !opt.frontend && !opt.metrics && !opt.serve {
         (None, None)
     } else {
+        if !api_tokens.is_enabled() && !is_loopback(&opt.listen_addr) {
+            log::warn!(
+                "API listening on {} without authentication, consider setting --api-token",
+                opt.listen_addr
+            );
+        }
+        let cors_origins = opt.cors_origins.clone();
+        let frontend = opt.frontend || opt.serve;
         let srv = HttpServer::new(move || {
-            let cors = if opt.add_cors {
-                actix_cors::Cors::default()
-                    .allow_any_origin()
-                    .allow_any_method()
-                    .allow_any_header()
-                    .max_age(3600)
-            } else {
+            let cors = cors_origins.iter().fold(
                 actix_cors::Cors::default()
-            };
+                    .allowed_methods(vec!["GET", "POST"])
+                    .allowed_headers(vec![
+                        actix_web::http::header::AUTHORIZATION,
+                        actix_web::http::header::CONTENT_TYPE,
+                        actix_web::http::header::HeaderName::from_static("last-event-id"),
+                    ])
+                    .max_age(3600),
+                |cors, origin| cors.allowed_origin(origin),
+            );
+            let metrics_tokens = api_tokens.clone();
+            let metrics = web::resource("/metrics")
+                .wrap_fn(move |req, srv| {
+                    let authorized = metrics_tokens.authorize(&req);
+                    let response = authorized.map(|_| srv.call(req));
+                    async move { response?.await }
+                })
+                .app_data(server_data_cloned.clone())
+                .route(web::get().to(metrics_endpoint));
+            let api_tokens = api_tokens.clone();
 
+            // cors is the outer middleware, so preflight requests don't need the token
             let api_scope = web::scope("/api")
+                .wrap_fn(move |req, srv| {
+                    let authorized = api_tokens.authorize(&req);
+                    let response = authorized.map(|_| srv.call(req));
+                    async move { response?.await }
+                })
                 .wrap(cors)
                 .app_data(server_data_cloned.clone())
                 .route("/progress", web::get().to(progress_endpoint))
-                .route("/config", web::get().to(config));
+                .route("/progress/stream", web::get().to(progress_sse_endpoint))
+                .route("/progress/ws", web::get().to(progress_ws_endpoint))
+                .route("/history", web::get().to(history_endpoint))
+                .route("/history/runs", web::get().to(runs_endpoint))
+                .route("/config", web::get().to(config))
+                .route("/jobs", web::get().to(list_jobs_endpoint))
+                .route("/jobs", web::post().to(submit_job_endpoint))
+                .route("/jobs/{id}", web::get().to(job_endpoint))
+                .route("/jobs/{id}/pause", web::post().to(pause_job_endpoint))
+                .route("/jobs/{id}/resume", web::post().to(resume_job_endpoint))
+                .route("/jobs/{id}/cancel", web::post().to(cancel_job_endpoint))
+                .route("/control/pause", web::post().to(pause_endpoint))
+                .route("/control/resume", web::post().to(resume_endpoint))
+                .route("/control/stop", web::post().to(stop_endpoint))
+                .route("/control/speed-limit", web::post().to(speed_limit_endpoint));
 
-            App::new()
-                .route("/", web::get().to(redirect_to_frontend))
-                .route("/frontend", web::get().to(redirect_to_frontend))
-                .route("/frontend/{_:.*}", web::get().to(frontend_serve))
-                .service(api_scope)
+            // only metrics are served when the frontend is not enabled
+            App::new().service(metrics).configure(|cfg| {
+                if frontend {
+                    cfg.route("/", web::get().to(redirect_to_frontend))
+                        .route("/frontend", web::get().to(redirect_to_frontend))
+                        .route("/frontend/{_:.*}", web::get().to(frontend_serve))
+                        .service(api_scope);
+                }
+            })
         })
         .workers(1)
+        // signals are handled below, the server is stopped after the download
+        .disable_signals()
         .bind((opt.listen_addr.clone(), opt.listen_port))
         .map_err(anyhow::Error::from)?
         .run();
@@ -109,70 +532,173 @@ async fn main() -> anyhow::Result<()> {
         (Some(srv), Some(st_handle))
     };
 
-    //let mut signals = Signals::new(&[SIGINT])?;
-
+    let mut show_tui = tui::is_enabled(&opt);
     let sp_thread = tokio::spawn(async move {
+        let single_job = match single_job {
+            Some(single_job) => single_job,
+            None if opt.manifest.is_some() => {
+                let result = batch::run_batch(&jobs, &mut signals, opt.json).await;
+                if let Some(stop_handle) = stop_handle {
+                    stop_handle.stop(true);
+                }
+                return result.map(|_| DownloadSummary::default());
+            }
+            None => {
+                serve_until_killed(&jobs, &mut signals).await;
+                if let Some(stop_handle) = stop_handle {
+                    stop_handle.stop(true);
+                }
+                return Ok(DownloadSummary::default());
+            }
+        };
+        let print_line = |line: String| {
+            if to_stdout {
+                eprintln!("{line}");
+            } else {
+                println!("{line}");
+            }
+        };
         let current_time = std::time::Instant::now();
         let mut requested_kill = false;
-        loop {
-            {
-                let pd = server_data.pipe_downloader.lock().unwrap();
-                if opt.json {
-                    println!(
-                        "{}",
-                        serde_json::to_string_pretty(&pd.get_progress()).unwrap()
-                    )
-                } else {
-                    println!("{}", pd.get_progress_human_line());
+        if opt.ndjson {
+            requested_kill = ndjson::run(
+                &single_job,
+                &mut signals,
+                Duration::from_millis(opt.stream_interval_ms),
+            )
+            .await;
+        } else if show_tui {
+            match tui::run(&single_job, &mut signals).await {
+                Ok(stop_requested) => requested_kill = stop_requested,
+                Err(err) => {
+                    log::warn!("Cannot show terminal UI, printing progress lines: {err:?}");
+                    show_tui = false;
                 }
-                if pd.is_finished() {
-                    break;
+            }
+        }
+        if !opt.ndjson && !show_tui {
+            loop {
+                {
+                    let pd = single_job.pipe_downloader.lock().unwrap();
+                    if opt.list {
+                        // stdout has only the listing
+                    } else if opt.json {
+                        print_line(serde_json::to_string_pretty(&pd.get_progress()).unwrap())
+                    } else {
+                        print_line(pd.get_progress_human_line());
+                    }
+                    if pd.is_finished() {
+                        break;
+                    }
                 }
+                // test pause
+                // if elapsed.as_secs() > 30 {
+                //     pd.pause_download();
+                //     break;
+                // }
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_millis(1000)) => {
+                    },
+                    action = signals.recv() => {
+                        let pd = single_job.pipe_downloader.lock().unwrap();
+                        match action {
+                            SignalAction::Stop => {
+                                print_line(
+                                    "Stop signal received, finishing current file (send again to exit now)"
+                                        .to_string(),
+                                );
+                                pd.signal_graceful_stop();
+                                requested_kill = true;
+                            }
+                            SignalAction::ForceExit => signals::force_exit(),
+                            SignalAction::Pause => pd.pause_download(),
+                            SignalAction::Resume => pd.resume_download(),
+                        }
+                    },
+                };
             }
-            // test pause
-            // if elapsed.as_secs() > 30 {
-            //     pd.pause_download();
-            //     break;
-            // }
-            tokio::select! {
-                _ = tokio::time::sleep(Duration::from_millis(1000)) => {
-                },
-                _ = signal::ctrl_c() => {
-                    println!("Kill signal received");
-                    let pd = server_data.pipe_downloader.lock().unwrap();
-                    pd.signal_stop();
-                    requested_kill = true;
-
-                },
-            };
         }
         let elapsed = current_time.elapsed();
-        println!("Unpack finished in: {elapsed:?}");
+        jobs.update_outcomes();
+        let outcome = single_job
+            .pipe_downloader
+            .lock()
+            .unwrap()
+            .outcome()
+            .unwrap_or(Err(DownloadError::Other {
+                message: "Download finished without outcome".to_string(),
+            }));
+        // with --ndjson the outcome is in the finished record
+        match &outcome {
+            _ if opt.ndjson => {}
+            Ok(summary) if opt.verify => {
+                if let Some(report) = &summary.verify_report {
+                    print_verify_report(report);
+                }
+                println!("Verify finished in: {elapsed:?}");
+            }
+            Ok(summary) if opt.list => {
+                if let Some(report) = &summary.list_report {
+                    list::print_report(report);
+                }
+                eprintln!("List finished in: {elapsed:?}");
+            }
+            Ok(_) if to_stdout => {
+                print_line(format!("Written to stdout in: {elapsed:?}"));
+            }
+            Ok(_) => {
+                println!("Unpack finished in: {elapsed:?}");
+            }
+            Err(err) => {
+                print_line(format!(
+                    "Download failed after {elapsed:?} ({}): {err}",
+                    err.kind()
+                ));
+            }
+        }
         if let Some(stop_handle) = stop_handle {
             if !requested_kill {
-                println!("Waiting after finish: {} sec", opt.wait_after_finish_sec);
+                if !opt.ndjson {
+                    print_line(format!(
+                        "Waiting after finish: {} sec",
+                        opt.wait_after_finish_sec
+                    ));
+                }
                 std::thread::sleep(Duration::from_secs(opt.wait_after_finish_sec));
                 stop_handle.stop(true);
             } else {
                 stop_handle.stop(false);
             }
         }
+        outcome
     });
 
     if let Some(srv) = srv {
-        println!(
-            "Frontend started at http://{}:{}",
-            opt.listen_addr, opt.listen_port
-        );
+        if opt.ndjson {
+            // stdout has only the records
+        } else if to_stdout {
+            eprintln!(
+                "Frontend started at http://{}:{}",
+                opt.listen_addr, opt.listen_port
+            );
+        } else {
+            println!(
+                "Frontend started at http://{}:{}",
+                opt.listen_addr, opt.listen_port
+            );
+        }
         //Await actix_web server if it was started
         srv.await.map_err(anyhow::Error::from)?;
     }
     match sp_thread.await {
-        Ok(_) => {
+        Ok(Ok(_)) => {
             log::info!("Finished");
         }
+        Ok(Err(err)) => {
+            return Err(err.into());
+        }
         Err(e) => {
-            log::error!("Error: {:?}", e);
+            return Err(anyhow::anyhow!("Download task failed: {e}"));
         }
     }
     Ok(())
@@ -0,0 +1,120 @@
+use pipe_downloader_lib::PipeDownloaderEvent;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::jobs::JobHandle;
+use crate::signals::{self, SignalAction, Signals};
+use crate::streaming::StreamHub;
+
+/// Version of the `--ndjson` records, increased on incompatible changes
+/// (removed or renamed fields, changed meaning). New fields and record types may be added
+/// without changing the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Emitted often, their effect is visible in progress records
+fn is_verbose(event: &PipeDownloaderEvent) -> bool {
+    matches!(
+        event,
+        PipeDownloaderEvent::ChunkStarted { .. }
+            | PipeDownloaderEvent::ChunkFinished { .. }
+            | PipeDownloaderEvent::EntryExtracted { .. }
+    )
+}
+
+/// One compact line: `{"schemaVersion":1,"type":"...","time":"...",...}`,
+/// fields of the record are placed next to type
+fn print_record(record_type: &str, record: impl Serialize) {
+    let mut line = json!({
+        "schemaVersion": SCHEMA_VERSION,
+        "type": record_type,
+        "time": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    });
+    if let (Value::Object(line), Ok(Value::Object(fields))) =
+        (&mut line, serde_json::to_value(record))
+    {
+        for (key, value) in fields {
+            line.entry(key).or_insert(value);
+        }
+    }
+    let mut stdout = std::io::stdout().lock();
+    // consumer closed the pipe, nothing to do about it
+    let _ = writeln!(stdout, "{line}");
+    let _ = stdout.flush();
+}
+
+fn print_event(event: &PipeDownloaderEvent) {
+    let record_type = match serde_json::to_value(event) {
+        Ok(Value::Object(fields)) => fields
+            .get("type")
+            .and_then(|t| t.as_str())
+            .unwrap_or("event")
+            .to_string(),
+        _ => "event".to_string(),
+    };
+    print_record(&record_type, event);
+}
+
+/// Finished event is preceded by the final progress, returns true for it
+fn handle_event(job: &JobHandle, event: &PipeDownloaderEvent) -> bool {
+    let finished = matches!(event, PipeDownloaderEvent::Finished { .. });
+    if finished {
+        let progress = job.pipe_downloader.lock().unwrap().get_progress();
+        print_record("progress", json!({ "progress": progress }));
+    }
+    if !is_verbose(event) {
+        print_event(event);
+    }
+    finished
+}
+
+/// Events of a download that failed to start, the last one is `finished` with the error
+pub fn print_start_failure(stream_hub: &StreamHub) {
+    let (_, replay) = stream_hub.subscribe_since(Some(0));
+    for stream_event in replay.iter().filter(|e| !is_verbose(&e.event)) {
+        print_event(&stream_event.event);
+    }
+}
+
+/// Print pipeline events as they happen and progress snapshots every interval, the last
+/// record is `finished` (with `summary` or `error`). Returns true when stopped by signal.
+pub async fn run(job: &JobHandle, signals: &mut Signals, interval: Duration) -> bool {
+    // events emitted while the download was being started are in the replay buffer
+    let (mut receiver, replay) = job.stream_hub.subscribe_since(Some(0));
+    let mut requested_kill = false;
+    let mut finished = false;
+    for stream_event in replay {
+        finished |= handle_event(job, &stream_event.event);
+    }
+    let mut ticker = tokio::time::interval(interval);
+    while !finished {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(stream_event) => finished = handle_event(job, &stream_event.event),
+                Err(RecvError::Lagged(count)) => {
+                    print_record("eventsDropped", json!({ "count": count }));
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = ticker.tick() => {
+                let progress = job.pipe_downloader.lock().unwrap().get_progress();
+                print_record("progress", json!({ "progress": progress }));
+            },
+            action = signals.recv() => {
+                let pd = job.pipe_downloader.lock().unwrap();
+                match action {
+                    SignalAction::Stop => {
+                        pd.signal_graceful_stop();
+                        requested_kill = true;
+                    }
+                    SignalAction::ForceExit => signals::force_exit(),
+                    SignalAction::Pause => pd.pause_download(),
+                    SignalAction::Resume => pd.resume_download(),
+                }
+            },
+        }
+    }
+    requested_kill
+}
@@ -1,16 +1,57 @@
-use std::path::PathBuf;
+use pipe_downloader_lib::DurabilityMode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-#[derive(Debug, StructOpt)]
+fn serialize_durability<S: Serializer>(
+    durability: &DurabilityMode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(durability.as_str())
+}
+
+fn deserialize_durability<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DurabilityMode, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Options are also read from `PIPE_DOWNLOADER_*` environment variables and config file,
+/// see [crate::config::merge_options]. Serialized names are the keys of the config file.
+#[derive(Debug, StructOpt, Serialize, Deserialize)]
 #[structopt(
     name = "Pipe downloader",
-    about = "Fast multithreaded downloader for tar.lz4, tar.gz, tar.bz2 files"
+    about = "Fast multithreaded downloader for tar.lz4, tar.gz, tar.bz2 files",
+    after_help = crate::exit_code::HELP
 )]
+#[serde(rename_all = "kebab-case")]
 pub struct CliOptions {
-    /// Url of tar.gz or tar.lz4 file
-    pub url: String,
+    #[structopt(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
+    /// Url of tar.gz or tar.lz4 file, optional with --serve
+    #[serde(skip)]
+    pub url: Option<String>,
 
-    /// Output directory
+    /// Config file (TOML with long option names as keys), defaults to the first existing of
+    /// ./pipe_downloader.toml, ~/.config/pipe_downloader/config.toml, /etc/pipe_downloader/config.toml.
+    /// Command line options take precedence over PIPE_DOWNLOADER_* environment variables,
+    /// which take precedence over the config file.
+    #[structopt(
+        long = "config",
+        env = "PIPE_DOWNLOADER_CONFIG",
+        global = true,
+        parse(from_os_str)
+    )]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+
+    /// Output directory (or output file for single compressed file), `-` writes the decoded
+    /// stream to stdout (progress goes to stderr then).
+    /// When not given, it is inferred from Content-Disposition header or url
     #[structopt(short = "o", long = "output-dir", parse(from_os_str))]
     pub output_dir: Option<PathBuf>,
 
@@ -44,6 +85,16 @@ pub struct CliOptions {
     #[structopt(long = "json")]
     pub json: bool,
 
+    /// Print newline delimited JSON for machine consumers: pipeline events and progress snapshots
+    /// every --stream-interval-ms, one compact object per line with schemaVersion and type fields
+    #[structopt(long = "ndjson", conflicts_with = "json")]
+    pub ndjson: bool,
+
+    /// Print progress as plain lines also when stdout is a terminal
+    /// (by default progress bars with keyboard controls are shown)
+    #[structopt(long = "no-tui")]
+    pub no_tui: bool,
+
     /// For server
     #[structopt(long = "wait-after-finish-sec", default_value = "10")]
     pub wait_after_finish_sec: u64,
@@ -64,10 +115,231 @@ pub struct CliOptions {
     #[structopt(long, default_value = "15100")]
     pub listen_port: u16,
 
-    #[structopt(long = "add-cors")]
-    pub add_cors: bool,
+    /// Sha256 of the downloaded file, download fails when it differs
+    #[structopt(long = "sha256")]
+    pub sha256: Option<String>,
+
+    /// Download archives listed in manifest file (JSON or YAML) instead of single url.
+    /// Entries have the same fields as POST /api/jobs: url, outputDir, sha256, downloadThreads, limitSpeed, ...
+    #[structopt(long = "manifest", parse(from_os_str))]
+    pub manifest: Option<PathBuf>,
+
+    /// Keep the server running and accept download jobs at /api/jobs,
+    /// url (if given) is submitted as the first job
+    #[structopt(long = "serve")]
+    pub serve: bool,
+
+    /// Number of jobs downloaded at the same time in --serve and --manifest mode, other jobs are queued
+    #[structopt(long = "max-concurrent-jobs", default_value = "1")]
+    pub max_concurrent_jobs: usize,
+
+    /// Limit of download connections of all jobs together, jobs wait in the queue
+    /// until enough connections are free
+    #[structopt(long = "max-connections")]
+    pub max_connections: Option<usize>,
+
+    /// Max bytes downloaded per second by all jobs together,
+    /// shared equally by download threads of running jobs
+    #[structopt(long = "max-total-speed")]
+    pub max_total_speed: Option<usize>,
+
+    /// Number of finished jobs kept in --serve mode, older ones are removed with their progress
+    #[structopt(long = "max-finished-jobs", default_value = "100")]
+    pub max_finished_jobs: usize,
+
+    /// Directory keeping the run history (runs.jsonl),
+    /// defaults to $XDG_STATE_HOME/pipe_downloader or ~/.local/state/pipe_downloader
+    #[structopt(
+        long = "state-dir",
+        env = "PIPE_DOWNLOADER_STATE_DIR",
+        global = true,
+        parse(from_os_str)
+    )]
+    pub state_dir: Option<PathBuf>,
+
+    /// Do not record the run in the run history
+    #[structopt(long = "no-run-history")]
+    pub no_run_history: bool,
+
+    /// Serve Prometheus metrics at /metrics, also when --frontend is not enabled
+    #[structopt(long = "metrics")]
+    pub metrics: bool,
+
+    /// Value of job_name label added to the metrics
+    #[structopt(long = "metrics-job")]
+    pub metrics_job: Option<String>,
+
+    /// Interval of progress snapshots sent over /api/progress/stream and /api/progress/ws
+    /// (clients can override it with intervalMs query parameter) and printed with --ndjson
+    #[structopt(long = "stream-interval-ms", default_value = "1000")]
+    pub stream_interval_ms: u64,
+
+    /// Allow cross-origin requests to the API from given origin (e.g. http://localhost:3000),
+    /// can be repeated
+    #[structopt(name = "cors-origin", long = "cors-origin")]
+    #[serde(rename = "cors-origin")]
+    pub cors_origins: Vec<String>,
+
+    /// Token required for the API (read and control access), sent as `Authorization: Bearer <token>`
+    #[structopt(
+        long = "api-token",
+        env = "PIPE_DOWNLOADER_API_TOKEN",
+        hide_env_values = true
+    )]
+    pub api_token: Option<String>,
+
+    /// Read --api-token from file
+    #[structopt(long = "api-token-file", parse(from_os_str))]
+    pub api_token_file: Option<PathBuf>,
+
+    /// Token allowing only read access to the API (progress, history, streams)
+    #[structopt(
+        long = "api-read-token",
+        env = "PIPE_DOWNLOADER_API_READ_TOKEN",
+        hide_env_values = true
+    )]
+    pub api_read_token: Option<String>,
+
+    /// Read --api-read-token from file
+    #[structopt(long = "api-read-token-file", parse(from_os_str))]
+    pub api_read_token_file: Option<PathBuf>,
 
     /// Ignore symlinks when un-taring
     #[structopt(long = "ignore-symlinks")]
     pub ignore_symlinks: bool,
+
+    /// Do not unpack, compare archive entries with files already present in output directory
+    /// and print a report of missing, extra and different files. Nothing is written to disk.
+    #[structopt(long = "verify")]
+    pub verify: bool,
+
+    /// Do not unpack, walk archive entries and print them like `tar -tv`
+    /// (mode, owner, size, mtime, path). Nothing is written to disk.
+    #[structopt(long = "list", conflicts_with = "verify")]
+    pub list: bool,
+
+    /// Print only entries matching the glob, `*` and `?` do not match `/`, `**` does
+    #[structopt(long = "list-filter", requires = "list")]
+    pub list_filter: Option<String>,
+
+    /// Stop the download once the entry with this path was listed
+    #[structopt(long = "list-stop-after", requires = "list")]
+    pub list_stop_after: Option<String>,
+
+    /// With `-o -` write the downloaded stream as it is, without decoding (any file, not only archives).
+    /// --sha256 is checked at the end, when the data is already written, mismatch is reported by exit code.
+    #[structopt(long = "raw")]
+    pub raw: bool,
+
+    /// Do not check if there is enough free disk space for the unpacked archive before starting
+    #[structopt(long = "skip-disk-space-check")]
+    pub skip_disk_space_check: bool,
+
+    /// Pause download when free disk space drops below this value (in bytes), 0 disables the watchdog
+    #[structopt(long = "min-free-space", default_value = "200000000")]
+    pub min_free_space: u64,
+
+    /// Durability of unpacked data: none (leave it to OS), fsync (each file after writing)
+    /// or syncfs (whole filesystem at the end), directories are synced in both latter modes.
+    /// Finish is reported after the data is synced.
+    #[structopt(long = "durability", default_value = "none")]
+    #[serde(
+        serialize_with = "serialize_durability",
+        deserialize_with = "deserialize_durability"
+    )]
+    pub durability: DurabilityMode,
+}
+
+impl CliOptions {
+    /// `-o -`, data goes to stdout
+    pub fn output_to_stdout(&self) -> bool {
+        self.output_dir.as_deref() == Some(Path::new("-"))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// List past runs recorded in the run history
+    History(HistoryOptions),
+    /// Print effective settings merged from command line, environment and config file
+    PrintConfig,
+    /// Probe url without downloading: size, range support, compression, first tar entries
+    /// and estimated download time
+    Inspect(InspectOptions),
+    /// Measure network, decode and write throughput and recommend --download-threads,
+    /// --download-buffer and --unpack-buffer
+    Bench(BenchOptions),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct InspectOptions {
+    /// Url of the file to inspect
+    pub url: String,
+
+    /// Bytes downloaded from the beginning of the file to measure speed and list tar entries
+    #[structopt(long = "sample-size", default_value = "4000000")]
+    pub sample_size: usize,
+
+    /// Number of tar entries listed
+    #[structopt(long = "entries", default_value = "10")]
+    pub entries: usize,
+
+    /// Print the report as json
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct BenchOptions {
+    /// Url or path of a local file, network is not measured for local files
+    pub url: String,
+
+    /// Directory on the filesystem where files would be unpacked, write throughput is measured
+    /// in a temporary directory created (and removed) inside it
+    #[structopt(
+        short = "o",
+        long = "output-dir",
+        default_value = ".",
+        parse(from_os_str)
+    )]
+    pub output_dir: PathBuf,
+
+    /// Numbers of connections measured
+    #[structopt(long = "threads", default_value = "1,2,4,8,16", use_delimiter = true)]
+    pub threads: Vec<usize>,
+
+    /// Time limit of each network measurement in seconds
+    #[structopt(long = "duration-sec", default_value = "5")]
+    pub duration_sec: f64,
+
+    /// Bytes limit of each network measurement
+    #[structopt(long = "max-bytes", default_value = "200000000")]
+    pub max_bytes: usize,
+
+    /// Compressed bytes from the beginning of the file used to measure decoding and writing
+    #[structopt(long = "sample-size", default_value = "32000000")]
+    pub sample_size: usize,
+
+    /// Also compare decoding speed of the sample compressed with every supported codec
+    #[structopt(long = "all-codecs")]
+    pub all_codecs: bool,
+
+    /// Print the report as json
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HistoryOptions {
+    /// Show only runs with url containing this text
+    #[structopt(long = "url")]
+    pub url: Option<String>,
+
+    /// Number of latest runs shown
+    #[structopt(long = "limit", default_value = "20")]
+    pub limit: usize,
+
+    /// Print runs as json
+    #[structopt(long = "json")]
+    pub json: bool,
 }
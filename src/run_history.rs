@@ -0,0 +1,340 @@
+use actix_web::web::Data;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use pipe_downloader_lib::{
+    DownloadError, DownloadSummary, OutputMode, PipeDownloaderOptions, PipeDownloaderProgress,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::options::HistoryOptions;
+use crate::ServerData;
+
+const RUNS_FILE_NAME: &str = "runs.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RunOutcome {
+    Success,
+    Stopped,
+    Failed,
+}
+
+/// Options of the run that affect its performance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunOptions {
+    pub download_threads: usize,
+    pub chunk_size: usize,
+    pub max_download_speed: Option<usize>,
+    pub force_no_chunks: bool,
+    pub verify: bool,
+    #[serde(default)]
+    pub list: bool,
+    pub durability: String,
+}
+
+impl RunOptions {
+    fn new(options: &PipeDownloaderOptions) -> Self {
+        Self {
+            download_threads: options.download_threads,
+            chunk_size: options.chunk_size_downloader,
+            max_download_speed: options.max_download_speed,
+            force_no_chunks: options.force_no_chunks,
+            verify: options.output_mode == OutputMode::Verify,
+            list: options.output_mode == OutputMode::List,
+            durability: options.durability.as_str().to_string(),
+        }
+    }
+}
+
+/// One finished download, stored as a line of `runs.jsonl` in the state directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunRecord {
+    pub url: String,
+    #[serde(default)]
+    pub output_dir: Option<String>,
+    pub options: RunOptions,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    #[serde(default)]
+    pub elapsed_time_sec: f64,
+    #[serde(default)]
+    pub total_download_size: Option<usize>,
+    #[serde(default)]
+    pub downloaded: usize,
+    #[serde(default)]
+    pub unpacked: usize,
+    #[serde(default)]
+    pub unpacked_files: usize,
+    /// Bytes per second over the whole run
+    #[serde(default)]
+    pub avg_download_speed: f64,
+    #[serde(default)]
+    pub avg_unpack_speed: f64,
+    #[serde(default)]
+    pub requests: usize,
+    #[serde(default)]
+    pub reconnects: usize,
+    #[serde(default)]
+    pub retries: usize,
+    pub outcome: RunOutcome,
+    #[serde(default)]
+    pub error_kind: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl RunRecord {
+    /// Progress is missing when the download failed before it was started
+    pub fn new(
+        url: &str,
+        output_dir: Option<&Path>,
+        options: &PipeDownloaderOptions,
+        started_at: DateTime<Utc>,
+        progress: Option<&PipeDownloaderProgress>,
+        outcome: &Result<DownloadSummary, DownloadError>,
+    ) -> Self {
+        let finished_at = Utc::now();
+        let elapsed_time_sec = progress
+            .map(|progress| progress.elapsed_time_sec)
+            .unwrap_or_else(|| {
+                (finished_at - started_at).num_milliseconds().max(0) as f64 / 1000.0
+            });
+        let per_second = |bytes: usize| {
+            if elapsed_time_sec > 0.0 {
+                bytes as f64 / elapsed_time_sec
+            } else {
+                0.0
+            }
+        };
+        let (requests, reconnects, retries) = progress
+            .map(|progress| {
+                progress.thread_stats.iter().fold((0, 0, 0), |acc, stats| {
+                    (
+                        acc.0 + stats.requests,
+                        acc.1 + stats.reconnects,
+                        acc.2 + stats.retries,
+                    )
+                })
+            })
+            .unwrap_or_default();
+        let (outcome, error_kind, error) = match outcome {
+            Ok(_) => (RunOutcome::Success, None, None),
+            Err(DownloadError::StoppedByUser) => (RunOutcome::Stopped, None, None),
+            Err(err) => (
+                RunOutcome::Failed,
+                Some(err.kind().to_string()),
+                Some(err.to_string()),
+            ),
+        };
+        let downloaded = progress.map(|p| p.downloaded).unwrap_or_default();
+        let unpacked = progress.map(|p| p.unpacked).unwrap_or_default();
+        Self {
+            url: url.to_string(),
+            output_dir: output_dir.map(|path| path.display().to_string()),
+            options: RunOptions::new(options),
+            started_at,
+            finished_at,
+            elapsed_time_sec,
+            total_download_size: progress.and_then(|p| p.total_download_size),
+            downloaded,
+            unpacked,
+            unpacked_files: progress.map(|p| p.unpacked_files).unwrap_or_default(),
+            avg_download_speed: per_second(downloaded),
+            avg_unpack_speed: per_second(unpacked),
+            requests,
+            reconnects,
+            retries,
+            outcome,
+            error_kind,
+            error,
+        }
+    }
+
+    /// Host of the url, runs are compared per mirror
+    pub fn host(&self) -> String {
+        reqwest::Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_string()))
+            .unwrap_or_else(|| self.url.clone())
+    }
+}
+
+/// `$XDG_STATE_HOME/pipe_downloader` or `~/.local/state/pipe_downloader`
+pub fn default_state_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("pipe_downloader"))
+}
+
+/// Append-only log of finished runs, shared by all invocations using the same state directory
+pub struct RunHistory {
+    path: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl RunHistory {
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            path: state_dir.join(RUNS_FILE_NAME),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, record: &RunRecord) -> anyhow::Result<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        // whole line in one write, so runs from other processes are not interleaved
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Failure to record a run is not a reason to fail the download
+    pub fn record(&self, record: &RunRecord) {
+        if let Err(err) = self.append(record) {
+            log::warn!(
+                "Cannot write run history to {}: {:?}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    /// Runs in the order they finished, lines that can't be parsed are skipped
+    pub fn load(&self) -> anyhow::Result<Vec<RunRecord>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut runs = Vec::new();
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(run) => runs.push(run),
+                Err(err) => log::warn!(
+                    "Skipping line {} of {}: {}",
+                    line_no + 1,
+                    self.path.display(),
+                    err
+                ),
+            }
+        }
+        Ok(runs)
+    }
+
+    /// Latest runs first, `url` filters by substring
+    pub fn query(&self, url: Option<&str>, limit: usize) -> anyhow::Result<Vec<RunRecord>> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .rev()
+            .filter(|run| url.is_none_or(|url| run.url.contains(url)))
+            .take(limit)
+            .collect())
+    }
+}
+
+fn format_speed(bytes_per_sec: f64) -> String {
+    format!("{:.2} MB/s", bytes_per_sec / 1_000_000.0)
+}
+
+/// `history` subcommand
+pub fn print_history(history: &RunHistory, opt: &HistoryOptions) -> anyhow::Result<()> {
+    let runs = history.query(opt.url.as_deref(), opt.limit)?;
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&runs)?);
+        return Ok(());
+    }
+    if runs.is_empty() {
+        println!("No runs recorded in {}", history.path().display());
+        return Ok(());
+    }
+    for run in runs.iter().rev() {
+        println!(
+            "{} {:>8} {:>12} {:>10.1} MB {:>8.1}s threads {:<3} retries {:<3} {}{}",
+            run.started_at.format("%Y-%m-%d %H:%M"),
+            format!("{:?}", run.outcome).to_lowercase(),
+            format_speed(run.avg_download_speed),
+            run.downloaded as f64 / 1_000_000.0,
+            run.elapsed_time_sec,
+            run.options.download_threads,
+            run.retries,
+            run.url,
+            run.error_kind
+                .as_ref()
+                .map(|kind| format!(" ({kind})"))
+                .unwrap_or_default(),
+        );
+    }
+    // successful runs only, failed ones would skew the speeds
+    let mut mirrors: BTreeMap<String, (usize, Vec<f64>)> = BTreeMap::new();
+    for run in &runs {
+        let mirror = mirrors.entry(run.host()).or_default();
+        mirror.0 += 1;
+        if run.outcome == RunOutcome::Success {
+            mirror.1.push(run.avg_download_speed);
+        }
+    }
+    println!();
+    println!("Per mirror:");
+    for (host, (count, speeds)) in mirrors {
+        let avg = speeds.iter().sum::<f64>() / speeds.len().max(1) as f64;
+        let best = speeds.iter().copied().fold(0.0, f64::max);
+        println!(
+            "{:<40} runs {:<4} ok {:<4} avg {:>12} best {:>12}",
+            host,
+            count,
+            speeds.len(),
+            format_speed(avg),
+            format_speed(best)
+        );
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunsQuery {
+    url: Option<String>,
+    limit: Option<usize>,
+}
+
+pub async fn runs_endpoint(
+    query: web::Query<RunsQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> HttpResponse {
+    let history = match &server_data.run_history {
+        Some(history) => history,
+        None => return HttpResponse::Ok().json(json!({ "runs": [] })),
+    };
+    match history.query(query.url.as_deref(), query.limit.unwrap_or(100)) {
+        Ok(runs) => HttpResponse::Ok().json(json!({ "runs": runs })),
+        Err(err) => HttpResponse::InternalServerError()
+            .json(json!({ "error": format!("Cannot read run history: {err}") })),
+    }
+}
@@ -0,0 +1,95 @@
+//! SIGINT, SIGTERM and SIGHUP stop the download after the file being extracted,
+//! the second one exits immediately. SIGUSR1 pauses and SIGUSR2 resumes the download.
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Number of the first stop signal, 0 when none was received
+static STOP_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+pub enum SignalAction {
+    /// First SIGINT, SIGTERM or SIGHUP, stop gracefully
+    Stop,
+    /// Stop signal received again, don't wait for the pipeline, see [force_exit]
+    ForceExit,
+    Pause,
+    Resume,
+}
+
+/// Exit code for the interrupted run, 128 + signal number like shells report it
+pub fn interrupted_exit_code() -> Option<u8> {
+    match STOP_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signo => Some((128 + signo) as u8),
+    }
+}
+
+pub fn force_exit() -> ! {
+    eprintln!("Second stop signal received, exiting immediately");
+    std::process::exit(interrupted_exit_code().unwrap_or(crate::exit_code::INTERRUPTED) as i32)
+}
+
+pub struct Signals {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    user1: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    user2: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    /// Installs the handlers, from now on the signals don't terminate the process
+    #[cfg(unix)]
+    pub fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Signals {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+            hangup: signal(SignalKind::hangup())?,
+            user1: signal(SignalKind::user_defined1())?,
+            user2: signal(SignalKind::user_defined2())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Signals {})
+    }
+
+    /// Wait for the next signal
+    pub async fn recv(&mut self) -> SignalAction {
+        #[cfg(unix)]
+        let signo = tokio::select! {
+            _ = self.interrupt.recv() => libc::SIGINT,
+            _ = self.terminate.recv() => libc::SIGTERM,
+            _ = self.hangup.recv() => libc::SIGHUP,
+            _ = self.user1.recv() => return SignalAction::Pause,
+            _ = self.user2.recv() => return SignalAction::Resume,
+        };
+        #[cfg(not(unix))]
+        let signo = match tokio::signal::ctrl_c().await {
+            // SIGINT
+            Ok(()) => 2,
+            Err(_) => std::future::pending().await,
+        };
+        match STOP_SIGNAL.compare_exchange(0, signo, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => SignalAction::Stop,
+            Err(_) => SignalAction::ForceExit,
+        }
+    }
+
+    /// Wait for a stop signal, pause and resume are ignored
+    pub async fn recv_stop(&mut self) {
+        loop {
+            match self.recv().await {
+                SignalAction::Stop => return,
+                SignalAction::ForceExit => force_exit(),
+                SignalAction::Pause | SignalAction::Resume => {}
+            }
+        }
+    }
+}
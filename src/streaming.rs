@@ -0,0 +1,324 @@
+use actix_web::web::{Bytes, Data};
+use actix_web::{web, HttpRequest, HttpResponse};
+use async_stream::stream;
+use futures_core::stream::Stream;
+use futures_util::StreamExt;
+use pipe_downloader_lib::{
+    PipeDownloader, PipeDownloaderEvent, PipeDownloaderObserver, PipeDownloaderProgress,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::ServerData;
+
+/// Number of events kept for clients reconnecting with last event id
+const REPLAY_BUFFER_SIZE: usize = 1000;
+const MIN_STREAM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Pipeline event with sequential id used for resuming the stream
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamEvent {
+    pub id: u64,
+    pub event: PipeDownloaderEvent,
+}
+
+struct HubState {
+    next_id: u64,
+    recent: VecDeque<StreamEvent>,
+}
+
+/// Numbers pipeline events and distributes them to connected stream clients
+pub struct StreamHub {
+    state: Mutex<HubState>,
+    sender: broadcast::Sender<StreamEvent>,
+}
+
+impl Default for StreamHub {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(REPLAY_BUFFER_SIZE);
+        Self {
+            state: Mutex::new(HubState {
+                next_id: 1,
+                recent: VecDeque::new(),
+            }),
+            sender,
+        }
+    }
+}
+
+impl PipeDownloaderObserver for StreamHub {
+    fn on_event(&self, event: &PipeDownloaderEvent) {
+        let mut state = self.state.lock().unwrap();
+        let stream_event = StreamEvent {
+            id: state.next_id,
+            event: event.clone(),
+        };
+        state.next_id += 1;
+        state.recent.push_back(stream_event.clone());
+        if state.recent.len() > REPLAY_BUFFER_SIZE {
+            state.recent.pop_front();
+        }
+        // sent under the lock, so subscribe_since never misses or duplicates an event
+        let _ = self.sender.send(stream_event);
+    }
+}
+
+impl StreamHub {
+    /// Subscribe to new events, returns also events after last_event_id still kept in the buffer
+    pub fn subscribe_since(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (broadcast::Receiver<StreamEvent>, Vec<StreamEvent>) {
+        let state = self.state.lock().unwrap();
+        let replay = match last_event_id {
+            Some(last_event_id) => state
+                .recent
+                .iter()
+                .filter(|e| e.id > last_event_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (self.sender.subscribe(), replay)
+    }
+}
+
+enum StreamMessage {
+    Event(StreamEvent),
+    Progress(Box<PipeDownloaderProgress>),
+    /// Client was too slow and the given number of events was dropped
+    Lagged(u64),
+    /// Download finished, no more messages will be sent
+    End,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamQuery {
+    /// Interval of progress snapshots
+    interval_ms: Option<u64>,
+    /// Alternative to Last-Event-ID header
+    last_event_id: Option<u64>,
+    /// Job id, latest started job when not given
+    job: Option<u64>,
+}
+
+impl StreamQuery {
+    fn interval(&self, server_data: &ServerData) -> Duration {
+        self.interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(server_data.stream_interval)
+            .max(MIN_STREAM_INTERVAL)
+    }
+}
+
+/// Current progress and whether the download finished
+fn progress_snapshot(
+    pipe_downloader: Arc<Mutex<PipeDownloader>>,
+) -> impl Fn() -> (PipeDownloaderProgress, bool) {
+    move || {
+        let pd = pipe_downloader.lock().unwrap();
+        (pd.get_progress(), pd.outcome().is_some())
+    }
+}
+
+/// Replayed events first, then discrete events as they happen interleaved with progress snapshots
+fn message_stream(
+    snapshot: impl Fn() -> (PipeDownloaderProgress, bool),
+    mut receiver: broadcast::Receiver<StreamEvent>,
+    replay: Vec<StreamEvent>,
+    interval: Duration,
+) -> impl Stream<Item = StreamMessage> {
+    stream! {
+        for event in replay {
+            yield StreamMessage::Event(event);
+        }
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let (progress, finished) = snapshot();
+                    if finished {
+                        // deliver events emitted before the outcome was set
+                        while let Ok(event) = receiver.try_recv() {
+                            yield StreamMessage::Event(event);
+                        }
+                        yield StreamMessage::Progress(Box::new(progress));
+                        yield StreamMessage::End;
+                        break;
+                    }
+                    yield StreamMessage::Progress(Box::new(progress));
+                },
+                event = receiver.recv() => match event {
+                    Ok(event) => yield StreamMessage::Event(event),
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("Stream client lagged, {} events skipped", skipped);
+                        // skipped events are gone, client has to resync from the progress snapshot
+                        yield StreamMessage::Lagged(skipped);
+                        yield StreamMessage::Progress(Box::new(snapshot().0));
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+    }
+}
+
+fn sse_message(event: &str, id: Option<u64>, data: &str) -> Bytes {
+    let id_line = id.map(|id| format!("id: {id}\n")).unwrap_or_default();
+    Bytes::from(format!("{id_line}event: {event}\ndata: {data}\n\n"))
+}
+
+/// Server-Sent Events stream: `progress` snapshots, `pipeline` events with ids, `lagged` when
+/// events were dropped for slow client and final `end`
+pub async fn progress_sse_endpoint(
+    req: HttpRequest,
+    query: web::Query<StreamQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<HttpResponse> {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .or(query.last_event_id);
+    let job = server_data.selected_job(query.job)?;
+    let (receiver, replay) = job.stream_hub.subscribe_since(last_event_id);
+    let messages = message_stream(
+        progress_snapshot(job.pipe_downloader.clone()),
+        receiver,
+        replay,
+        query.interval(&server_data),
+    );
+    let body = messages.map(|message| {
+        Ok::<_, actix_web::Error>(match message {
+            StreamMessage::Event(event) => sse_message(
+                "pipeline",
+                Some(event.id),
+                &serde_json::to_string(&event.event).unwrap(),
+            ),
+            StreamMessage::Progress(progress) => {
+                sse_message("progress", None, &serde_json::to_string(&progress).unwrap())
+            }
+            StreamMessage::Lagged(skipped) => {
+                sse_message("lagged", None, &json!({ "skipped": skipped }).to_string())
+            }
+            StreamMessage::End => sse_message("end", None, "{}"),
+        })
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body))
+}
+
+/// WebSocket stream with the same messages as SSE, sent as JSON objects with `type` field
+pub async fn progress_ws_endpoint(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<StreamQuery>,
+    server_data: Data<Box<ServerData>>,
+) -> actix_web::Result<HttpResponse> {
+    let job = server_data.selected_job(query.job)?;
+    let (response, mut session, mut client_messages) = actix_ws::handle(&req, body)?;
+    let (receiver, replay) = job.stream_hub.subscribe_since(query.last_event_id);
+    let messages = message_stream(
+        progress_snapshot(job.pipe_downloader.clone()),
+        receiver,
+        replay,
+        query.interval(&server_data),
+    );
+
+    actix_web::rt::spawn(async move {
+        let mut messages = Box::pin(messages);
+        loop {
+            tokio::select! {
+                message = messages.next() => {
+                    let text = match message {
+                        Some(StreamMessage::Event(event)) => {
+                            json!({"type": "pipeline", "id": event.id, "event": event.event})
+                        }
+                        Some(StreamMessage::Progress(progress)) => {
+                            json!({"type": "progress", "progress": progress})
+                        }
+                        Some(StreamMessage::Lagged(skipped)) => {
+                            json!({"type": "lagged", "skipped": skipped})
+                        }
+                        Some(StreamMessage::End) | None => {
+                            let _ = session.text(json!({"type": "end"}).to_string()).await;
+                            break;
+                        }
+                    };
+                    if session.text(text.to_string()).await.is_err() {
+                        // client disconnected
+                        return;
+                    }
+                },
+                client_message = client_messages.next() => match client_message {
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_event(id: u64) -> StreamEvent {
+        StreamEvent {
+            id,
+            event: PipeDownloaderEvent::ChunkFinished {
+                chunk_no: id as usize,
+                thread_no: 0,
+                size: 1,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagged_client_resync() {
+        let (sender, receiver) = broadcast::channel(2);
+        for id in 1..=5 {
+            sender.send(stream_event(id)).unwrap();
+        }
+        drop(sender);
+        let snapshot = || (PipeDownloaderProgress::default(), false);
+        let messages: Vec<StreamMessage> = tokio::time::timeout(
+            Duration::from_secs(5),
+            message_stream(snapshot, receiver, Vec::new(), Duration::from_secs(3600)).collect(),
+        )
+        .await
+        .unwrap();
+
+        let lagged = messages
+            .iter()
+            .position(|m| matches!(m, StreamMessage::Lagged(3)))
+            .expect("lagged message not sent");
+        // fresh snapshot follows, so client doesn't have to wait for next tick
+        assert!(matches!(messages[lagged + 1], StreamMessage::Progress(_)));
+        let ids: Vec<u64> = messages[lagged..]
+            .iter()
+            .filter_map(|m| match m {
+                StreamMessage::Event(e) => Some(e.id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec![4, 5]);
+    }
+}
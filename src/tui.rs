@@ -0,0 +1,354 @@
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+use humansize::{SizeFormatter, DECIMAL};
+use pipe_downloader_lib::PipeDownloaderProgress;
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use crate::jobs::JobHandle;
+use crate::options::CliOptions;
+use crate::signals::{self, SignalAction, Signals};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Lowest speed limit set with `-`, per connection
+const MIN_SPEED_LIMIT: usize = 10_000;
+const KEYS_HELP: &str = "[p] pause  [r] resume  [+/-] speed limit  [u] unlimited  [q] stop";
+
+/// Interactive progress is shown only when stdout is a terminal,
+/// otherwise (or with --json, --ndjson, --no-tui) progress is printed as lines.
+/// With --list or `-o -` stdout is used for the data.
+pub fn is_enabled(opt: &CliOptions) -> bool {
+    !opt.json
+        && !opt.ndjson
+        && !opt.no_tui
+        && !opt.list
+        && !opt.output_to_stdout()
+        && std::io::stdout().is_terminal()
+}
+
+/// Raw mode and alternate screen, restored on drop so the terminal is usable after panic too
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(
+            std::io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide
+        )?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(
+            std::io::stdout(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+enum KeyAction {
+    Pause,
+    Resume,
+    Stop,
+    SpeedUp,
+    SpeedDown,
+    Unlimited,
+}
+
+fn key_action(key: KeyEvent) -> Option<KeyAction> {
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+    // Ctrl+C does not send SIGINT in raw mode
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        return match key.code {
+            KeyCode::Char('c') => Some(KeyAction::Stop),
+            _ => None,
+        };
+    }
+    match key.code {
+        KeyCode::Char('p') | KeyCode::Char(' ') => Some(KeyAction::Pause),
+        KeyCode::Char('r') => Some(KeyAction::Resume),
+        KeyCode::Char('q') | KeyCode::Esc => Some(KeyAction::Stop),
+        KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Up => Some(KeyAction::SpeedUp),
+        KeyCode::Char('-') | KeyCode::Down => Some(KeyAction::SpeedDown),
+        KeyCode::Char('u') => Some(KeyAction::Unlimited),
+        _ => None,
+    }
+}
+
+/// Speed limit is per connection, it is changed by 25%. Without a limit `-` starts
+/// from the current speed of one connection, `+` keeps the download unlimited.
+fn changed_speed_limit(progress: &PipeDownloaderProgress, faster: bool) -> Option<usize> {
+    let current = match progress.max_download_speed {
+        Some(limit) => limit,
+        None if faster => return None,
+        None => progress.current_download_speed / progress.download_threads.max(1),
+    };
+    let changed = if faster {
+        current + current / 4
+    } else {
+        current - current / 5
+    };
+    Some(changed.max(MIN_SPEED_LIMIT))
+}
+
+/// Show live progress until the download is finished. Returns true when stop was requested
+/// from the keyboard or by a signal.
+pub async fn run(job: &JobHandle, signals: &mut Signals) -> anyhow::Result<bool> {
+    let guard = TerminalGuard::enter()?;
+    let mut stdout = std::io::stdout();
+    let mut requested_stop = false;
+    loop {
+        let (progress, finished) = {
+            let pd = job.pipe_downloader.lock().unwrap();
+            (pd.get_progress(), pd.is_finished())
+        };
+        render(&mut stdout, &progress)?;
+        if finished {
+            break;
+        }
+
+        let next_render = Instant::now() + REFRESH_INTERVAL;
+        let mut action = None;
+        let mut signal_action = None;
+        while action.is_none() && signal_action.is_none() && Instant::now() < next_render {
+            while action.is_none() && event::poll(Duration::ZERO)? {
+                if let Event::Key(key) = event::read()? {
+                    action = key_action(key);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(KEY_POLL_INTERVAL) => {},
+                received = signals.recv() => signal_action = Some(received),
+            }
+        }
+        let pd = job.pipe_downloader.lock().unwrap();
+        match signal_action {
+            Some(SignalAction::Stop) => {
+                pd.signal_graceful_stop();
+                requested_stop = true;
+            }
+            Some(SignalAction::ForceExit) => {
+                drop(guard);
+                signals::force_exit();
+            }
+            Some(SignalAction::Pause) => pd.pause_download(),
+            Some(SignalAction::Resume) => pd.resume_download(),
+            None => {}
+        }
+        match action {
+            Some(KeyAction::Pause) => pd.pause_download(),
+            Some(KeyAction::Resume) => pd.resume_download(),
+            Some(KeyAction::Stop) if !requested_stop => {
+                pd.signal_stop();
+                requested_stop = true;
+            }
+            Some(KeyAction::SpeedUp) => {
+                pd.set_max_download_speed(changed_speed_limit(&progress, true))
+            }
+            Some(KeyAction::SpeedDown) => {
+                pd.set_max_download_speed(changed_speed_limit(&progress, false))
+            }
+            Some(KeyAction::Unlimited) => pd.set_max_download_speed(None),
+            Some(KeyAction::Stop) | None => {}
+        }
+    }
+    drop(guard);
+    // final state stays in the scrollback after leaving the alternate screen
+    println!(
+        "{}",
+        job.pipe_downloader
+            .lock()
+            .unwrap()
+            .get_progress_human_line()
+    );
+    Ok(requested_stop)
+}
+
+fn size(bytes: usize) -> String {
+    SizeFormatter::new(bytes, DECIMAL).to_string()
+}
+
+fn duration(secs: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+fn bar(done: usize, total: Option<usize>, width: usize) -> String {
+    let filled = match total {
+        Some(total) if total > 0 => (done.min(total) as f64 / total as f64 * width as f64) as usize,
+        _ => 0,
+    };
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled))
+}
+
+fn percent(done: usize, total: Option<usize>) -> String {
+    match total {
+        Some(total) if total > 0 => format!("{:5.1}%", done as f64 / total as f64 * 100.0),
+        _ => "    ?".to_string(),
+    }
+}
+
+fn status(progress: &PipeDownloaderProgress) -> String {
+    if let Some(error) = &progress.error {
+        format!("Failed: {error}")
+    } else if progress.finish_time.is_some() {
+        "Finished".to_string()
+    } else if progress.stop_requested || progress.graceful_stop_requested {
+        "Stopping".to_string()
+    } else if progress.paused_low_disk_space {
+        "Paused (low disk space)".to_string()
+    } else if progress.paused {
+        "Paused".to_string()
+    } else if progress.syncing {
+        "Syncing to disk".to_string()
+    } else {
+        "Downloading".to_string()
+    }
+}
+
+fn progress_lines(progress: &PipeDownloaderProgress, width: usize, height: usize) -> Vec<String> {
+    let bar_width = width.saturating_sub(60).clamp(10, 50);
+    let mut lines = vec![
+        format!(
+            "{} - {}",
+            progress
+                .download_url
+                .as_deref()
+                .unwrap_or("pipe_downloader"),
+            status(progress)
+        ),
+        String::new(),
+        format!(
+            "Download {} {} {:>10} / {:<10} {:>10}/s",
+            bar(progress.downloaded, progress.total_download_size, bar_width),
+            percent(progress.downloaded, progress.total_download_size),
+            size(progress.downloaded),
+            progress
+                .total_download_size
+                .map(size)
+                .unwrap_or_else(|| "?".to_string()),
+            size(progress.current_download_speed)
+        ),
+        format!(
+            "Unpack   {} {} {:>10} / {:<10} {:>10}/s",
+            bar(progress.unpacked, progress.total_unpack_size, bar_width),
+            percent(progress.unpacked, progress.total_unpack_size),
+            size(progress.unpacked),
+            progress
+                .total_unpack_size
+                .map(|total| {
+                    if progress.total_unpack_size_estimated {
+                        format!("~{}", size(total))
+                    } else {
+                        size(total)
+                    }
+                })
+                .unwrap_or_else(|| "?".to_string()),
+            size(progress.current_unpack_speed)
+        ),
+        format!(
+            "Elapsed {}  ETA {}  Speed limit {}",
+            duration(progress.elapsed_time_sec as u64),
+            progress
+                .eta_sec
+                .map(duration)
+                .unwrap_or_else(|| "unknown".to_string()),
+            progress
+                .max_download_speed
+                .map(|limit| format!("{}/s per connection", size(limit)))
+                .unwrap_or_else(|| "none".to_string())
+        ),
+        String::new(),
+        format!(
+            "Connections ({}, chunks left {} of {})",
+            progress.download_threads, progress.chunks_left, progress.chunks_total
+        ),
+    ];
+    // keep space for files and the keys help
+    let max_connections = height.saturating_sub(lines.len() + 6).max(1);
+    for stats in progress.thread_stats.iter().take(max_connections) {
+        let chunk = stats
+            .current_chunk
+            .and_then(|chunk_no| Some((chunk_no, progress.current_chunks.get(&chunk_no)?)));
+        let chunk = match chunk {
+            Some((chunk_no, chunk)) => format!(
+                "chunk {:<6} {} {}",
+                chunk_no,
+                bar(chunk.downloaded, Some(chunk.to_download), 10),
+                percent(chunk.downloaded, Some(chunk.to_download))
+            ),
+            None => format!("{:<31}", "idle"),
+        };
+        lines.push(format!(
+            "  #{:<3} {} {:>10}/s  requests {:<4} retries {:<3} {}",
+            stats.thread_no,
+            chunk,
+            size(stats.current_speed),
+            stats.requests,
+            stats.retries,
+            stats.last_error.as_deref().unwrap_or("")
+        ));
+    }
+    if progress.thread_stats.len() > max_connections {
+        lines.push(format!(
+            "  ... {} more",
+            progress.thread_stats.len() - max_connections
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push(format!("Files ({} unpacked)", progress.unpacked_files));
+    let max_files = height.saturating_sub(lines.len() + 2);
+    for file in progress.last_unpacked_files.iter().rev().take(max_files) {
+        lines.push(format!(
+            "  {:>10} {}{}",
+            size(file.file_size as usize),
+            file.file_name,
+            if file.finished { "" } else { " ..." }
+        ));
+    }
+    while lines.len() + 1 < height {
+        lines.push(String::new());
+    }
+    lines.push(KEYS_HELP.to_string());
+    lines
+}
+
+fn render(stdout: &mut std::io::Stdout, progress: &PipeDownloaderProgress) -> std::io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let (width, height) = (width as usize, height as usize);
+    queue!(stdout, cursor::MoveTo(0, 0))?;
+    for (idx, line) in progress_lines(progress, width, height)
+        .into_iter()
+        .take(height)
+        .enumerate()
+    {
+        if idx > 0 {
+            queue!(stdout, cursor::MoveToNextLine(1))?;
+        }
+        let line: String = line.chars().take(width).collect();
+        queue!(
+            stdout,
+            Print(line),
+            terminal::Clear(ClearType::UntilNewLine)
+        )?;
+    }
+    queue!(stdout, terminal::Clear(ClearType::FromCursorDown))?;
+    stdout.flush()
+}
@@ -4,47 +4,105 @@ use std::fs::File;
 
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use rand::{distributions::Alphanumeric, Rng};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
 use warp::Filter;
 
 use sha256::try_digest;
 use std::time::Duration;
-use tokio::try_join;
 
-use pipe_downloader_lib::PipeDownloaderOptions;
+use pipe_downloader_lib::{
+    DownloadError, DownloadSummary, DurabilityMode, OutputMode, PipeDownloader,
+    PipeDownloaderEvent, PipeDownloaderObserver, PipeDownloaderOptions, UnpackSizeSource,
+};
 use pipe_utils::{
     build_random_file, bzip_compress, gzip_compress, lz4_compress, xz_compress, zstd_compress,
 };
 
-#[derive(Debug, Clone)]
-struct Opt {
-    /// Localization of static files
-    pub serve_dir: PathBuf,
+/// Random archive served over http from a temporary directory,
+/// the directory is removed and the server stopped when dropped (also when an assertion fails)
+struct Fixture {
+    dir: TempDir,
+    /// sha256 of the archived files by name
+    file_info_map: HashMap<String, String>,
+    addr: SocketAddr,
+    server: JoinHandle<()>,
+}
 
-    /// Listen address
-    pub listen_addr: String,
+impl Fixture {
+    /// Builds foo.tar with 100 random files, compresses it with given formats and starts the server
+    async fn new(compressions: &[&str]) -> Fixture {
+        let dir = tempfile::Builder::new()
+            .prefix("static_")
+            .tempdir()
+            .unwrap();
+        let sd = dir.path();
 
-    /// Listen port
-    pub listen_port: u16,
-}
+        let file = File::create(sd.join("foo.tar")).unwrap();
+        let mut a = tar::Builder::new(file);
 
-async fn setup_server(opt: &Opt) {
-    let route = warp::path("static").and(warp::fs::dir(opt.serve_dir.clone()));
+        let mut file_info_map = HashMap::<String, String>::new();
+        for _i in 0..100 {
+            let file_name_str = format!("foo_{}.txt", rand_str(15));
+            let file_path = &sd.join(&file_name_str);
+            build_random_file(file_path, rand::thread_rng().gen_range(20000..500000))
+                .await
+                .unwrap();
+            let hex_digest = try_digest(file_path.as_path()).unwrap();
+            file_info_map.insert(file_name_str.clone(), hex_digest);
+            a.append_file(&file_name_str, &mut File::open(file_path).unwrap())
+                .unwrap();
+        }
+        a.finish().unwrap();
 
-    println!(
-        "Listening on {}:{}, serving static files: {}, {}//{}:{}/static",
-        opt.listen_addr,
-        opt.listen_port,
-        opt.serve_dir.display(),
-        "http:",
-        opt.listen_addr,
-        opt.listen_port
-    );
-    warp::serve(route)
-        .run(SocketAddr::from_str(&format!("{}:{}", opt.listen_addr, opt.listen_port)).unwrap())
-        .await
+        for compr in compressions {
+            let source = sd.join("foo.tar");
+            let destination = sd.join(format!("foo.tar.{compr}"));
+            match *compr {
+                "lz4" => lz4_compress(source, destination).await,
+                "gz" => gzip_compress(source, destination).await,
+                "bz2" => bzip_compress(source, destination).await,
+                "xz" => xz_compress(source, destination).await,
+                "zst" => zstd_compress(source, destination).await,
+                _ => panic!("Unknown compression {compr}"),
+            }
+            .unwrap();
+        }
+
+        // port 0 - every test gets own free port, so the tests can run in parallel
+        let route = warp::path("static").and(warp::fs::dir(sd.to_path_buf()));
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        println!(
+            "Listening on {}, serving static files: {}, http://{}/static",
+            addr,
+            sd.display(),
+            addr
+        );
+        Fixture {
+            dir,
+            file_info_map,
+            addr,
+            server: tokio::task::spawn(server),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn url(&self, file_name: &str) -> String {
+        format!("http://{}/static/{}", self.addr, file_name)
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
 }
 
 fn rand_str(len: usize) -> String {
@@ -55,91 +113,280 @@ fn rand_str(len: usize) -> String {
         .collect::<String>()
 }
 
+#[derive(Default)]
+struct CountingObserver {
+    started: AtomicUsize,
+    chunks_finished: AtomicUsize,
+    entries_extracted: AtomicUsize,
+}
+
+impl PipeDownloaderObserver for CountingObserver {
+    fn on_event(&self, event: &PipeDownloaderEvent) {
+        let counter = match event {
+            PipeDownloaderEvent::Started { .. } => &self.started,
+            PipeDownloaderEvent::ChunkFinished { .. } => &self.chunks_finished,
+            PipeDownloaderEvent::EntryExtracted { .. } => &self.entries_extracted,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Changes modification time of the served file when the download is started
+struct ModifyOnStart(PathBuf);
+
+impl PipeDownloaderObserver for ModifyOnStart {
+    fn on_event(&self, event: &PipeDownloaderEvent) {
+        if let PipeDownloaderEvent::Started { .. } = event {
+            File::options()
+                .append(true)
+                .open(&self.0)
+                .unwrap()
+                .set_modified(
+                    std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000),
+                )
+                .unwrap();
+        }
+    }
+}
+
+async fn wait_for_finish(pd: &PipeDownloader) -> Result<DownloadSummary, DownloadError> {
+    loop {
+        println!("{}", pd.get_progress_human_line());
+        if pd.is_finished() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+    }
+    pd.wait().await
+}
+
+async fn check_unpack(compr: &str) {
+    let fx = Fixture::new(&[compr]).await;
+    let observer = Arc::new(CountingObserver::default());
+    let pd = PipeDownloaderOptions {
+        chunk_size_decoder: 10000000,
+        chunk_size_downloader: 10000000,
+        max_download_speed: None,
+        force_no_chunks: false,
+        download_threads: 10,
+        ignore_symlinks: true,
+        ignore_directory_exists: true,
+        output_mode: OutputMode::Unpack,
+        durability: if compr == "gz" {
+            DurabilityMode::FsyncFiles
+        } else {
+            DurabilityMode::SyncAtEnd
+        },
+        ..Default::default()
+    }
+    .start_download_with_observer(
+        &fx.url(&format!("foo.tar.{compr}")),
+        Some(fx.path().join(format!("output_{compr}"))),
+        observer.clone(),
+    )
+    .await
+    .unwrap();
+
+    let summary = wait_for_finish(&pd).await.unwrap();
+    assert_eq!(summary.unpacked_files, 100);
+    let progress = pd.get_progress();
+    assert_eq!(progress.error, None);
+    let history = pd.get_history(None, None, None);
+    assert_eq!(history.points.last().unwrap().unpacked, progress.unpacked);
+    assert_eq!(history.points.last().unwrap().unpacked_files, 100);
+    assert_eq!(
+        progress
+            .thread_stats
+            .iter()
+            .map(|stats| stats.chunks_downloaded)
+            .sum::<usize>(),
+        progress.chunks_total
+    );
+    assert!(progress
+        .thread_stats
+        .iter()
+        .filter(|stats| stats.chunks_downloaded > 0)
+        .all(|stats| stats.requests > 0 && stats.time_to_first_byte_ms.is_some()));
+    assert_eq!(progress.total_unpack_size, Some(progress.unpacked));
+    assert!(progress.sync_time_sec > 0.0);
+    assert_eq!(observer.started.load(Ordering::SeqCst), 1);
+    assert!(observer.chunks_finished.load(Ordering::SeqCst) > 0);
+    assert_eq!(observer.entries_extracted.load(Ordering::SeqCst), 100);
+    match compr {
+        "gz" => assert_eq!(
+            progress.total_unpack_size_source,
+            Some(UnpackSizeSource::GzipTrailer)
+        ),
+        "xz" => assert_eq!(
+            progress.total_unpack_size_source,
+            Some(UnpackSizeSource::XzIndex)
+        ),
+        _ => {}
+    }
+    for (file_name, hex_digest) in &fx.file_info_map {
+        let unpacked = fx.path().join(format!("output_{compr}")).join(file_name);
+        assert_eq!(&try_digest(unpacked.as_path()).unwrap(), hex_digest);
+    }
+}
+
 #[tokio::test]
-async fn test_download_and_unpack() {
-    //let static_dir = "tmp/static".to_string();
-    let static_dir = format!("tmp/static_{}", rand_str(10));
+async fn test_unpack_lz4() {
+    check_unpack("lz4").await;
+}
 
-    let sd = Path::new(&static_dir);
+#[tokio::test]
+async fn test_unpack_gz() {
+    check_unpack("gz").await;
+}
 
-    fs::create_dir_all(sd).unwrap();
-    fs::create_dir_all(sd.join("subdir1")).unwrap();
-    fs::create_dir_all(sd.join("subdir2")).unwrap();
+#[tokio::test]
+async fn test_unpack_bz2() {
+    check_unpack("bz2").await;
+}
 
-    //fs::copy(sd.join("foo.txt"), sd.join("subdir1/foo.txt")).unwrap();
+#[tokio::test]
+async fn test_unpack_xz() {
+    check_unpack("xz").await;
+}
 
-    let file = File::create(sd.join("foo.tar")).unwrap();
-    let mut a = tar::Builder::new(file);
+#[tokio::test]
+async fn test_unpack_zst() {
+    check_unpack("zst").await;
+}
 
-    let mut file_info_map = HashMap::<String, String>::new();
-    for _i in 0..100 {
-        let file_name_str = format!("foo_{}.txt", rand_str(15));
-        let file_path = &sd.join(&file_name_str);
-        build_random_file(file_path, rand::thread_rng().gen_range(20000..500000))
-            .await
-            .unwrap();
-        let hex_digest = try_digest(file_path.as_path()).unwrap();
-        println!("{} {}", hex_digest, &file_name_str);
-        file_info_map.insert(file_name_str.clone(), hex_digest);
-        a.append_file(&file_name_str, &mut File::open(file_path).unwrap())
-            .unwrap();
+#[tokio::test]
+async fn test_verify() {
+    let fx = Fixture::new(&["lz4"]).await;
+    let url = fx.url("foo.tar.lz4");
+    let verify_dir = fx.path().join("output_lz4");
+    let pd = PipeDownloaderOptions::default()
+        .start_download(&url, Some(verify_dir.clone()))
+        .await
+        .unwrap();
+    wait_for_finish(&pd).await.unwrap();
+
+    let pd = PipeDownloaderOptions {
+        output_mode: OutputMode::Verify,
+        ..Default::default()
     }
-    let f1 = lz4_compress(sd.join("foo.tar"), sd.join("foo.tar.lz4"));
-    let f2 = gzip_compress(sd.join("foo.tar"), sd.join("foo.tar.gz"));
-    let f3 = bzip_compress(sd.join("foo.tar"), sd.join("foo.tar.bz2"));
-    let f4 = xz_compress(sd.join("foo.tar"), sd.join("foo.tar.xz"));
-    let f5 = zstd_compress(sd.join("foo.tar"), sd.join("foo.tar.zst"));
-
-    try_join!(f1, f2, f3, f4, f5).unwrap();
-
-    let opt = Opt {
-        serve_dir: PathBuf::from(sd),
-        listen_addr: String::from("127.0.0.1"),
-        listen_port: 23752,
-    };
-
-    let move_opt = opt.clone();
-    let tsk = tokio::task::spawn(async move {
-        setup_server(&move_opt).await;
-    });
-
-    for compr in ["lz4", "gz", "bz2", "xz", "zst"].iter() {
-        let pd = PipeDownloaderOptions {
-            chunk_size_decoder: 10000000,
-            chunk_size_downloader: 10000000,
-            max_download_speed: None,
-            force_no_chunks: false,
-            download_threads: 10,
-            ignore_symlinks: true,
-            ignore_directory_exists: true,
-        }
+    .start_download(&url, Some(verify_dir.clone()))
+    .await
+    .unwrap();
+    let report = wait_for_finish(&pd).await.unwrap().verify_report.unwrap();
+    assert!(report.is_clean(), "{report:?}");
+    assert_eq!(report.matching, fx.file_info_map.len());
+
+    let changed_file = fx.file_info_map.keys().next().unwrap().clone();
+    fs::write(verify_dir.join(&changed_file), "changed").unwrap();
+    fs::write(verify_dir.join("extra.txt"), "extra").unwrap();
+    let pd = PipeDownloaderOptions {
+        output_mode: OutputMode::Verify,
+        ..Default::default()
+    }
+    .start_download(&url, Some(verify_dir.clone()))
+    .await
+    .unwrap();
+    let report = wait_for_finish(&pd).await.unwrap().verify_report.unwrap();
+    assert_eq!(report.extra, vec!["extra.txt".to_string()]);
+    assert_eq!(report.different.len(), 1);
+    assert_eq!(report.different[0].path, changed_file);
+    assert!(report.missing.is_empty());
+}
+
+#[tokio::test]
+async fn test_list() {
+    let fx = Fixture::new(&["lz4"]).await;
+    let url = fx.url("foo.tar.lz4");
+    let pd = PipeDownloaderOptions {
+        output_mode: OutputMode::List,
+        list_filter: Some("foo_*.txt".to_string()),
+        ..Default::default()
+    }
+    .start_download(&url, None)
+    .await
+    .unwrap();
+    let report = wait_for_finish(&pd).await.unwrap().list_report.unwrap();
+    assert_eq!(report.entries, fx.file_info_map.len());
+    assert_eq!(report.listed, fx.file_info_map.len());
+
+    let pd = PipeDownloaderOptions {
+        output_mode: OutputMode::List,
+        list_filter: Some("bar_*".to_string()),
+        list_stop_after_path: Some(fx.file_info_map.keys().next().unwrap().clone()),
+        ..Default::default()
+    }
+    .start_download(&url, None)
+    .await
+    .unwrap();
+    let report = wait_for_finish(&pd).await.unwrap().list_report.unwrap();
+    assert!(report.stop_after_path_found);
+    assert_eq!(report.listed, 0);
+}
+
+#[tokio::test]
+async fn test_missing_file() {
+    let fx = Fixture::new(&[]).await;
+    let err = PipeDownloaderOptions::default()
         .start_download(
-            format!(
-                "http://{}:{}/static/foo.tar.{}",
-                opt.listen_addr, opt.listen_port, compr
-            )
-            .as_str(),
-            Some(sd.join(format!("output_{}", compr))),
+            &fx.url("missing.tar.gz"),
+            Some(fx.path().join("output_missing")),
         )
         .await
+        .err()
         .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<DownloadError>(),
+        Some(DownloadError::HttpStatus { status: 404, .. })
+    ));
+}
 
-        let _current_time = std::time::Instant::now();
-        loop {
-            println!("{}", pd.get_progress_human_line());
-            if pd.is_finished() {
-                break;
-            }
-            // test pause
-            // if elapsed.as_secs() > 30 {
-            //     pd.pause_download();
-            //     break;
-            // }
+/// Serves first half of compressed foo.tar as single file, download has to fail in the decoder
+async fn check_truncated_single_file(compr: &str) {
+    let fx = Fixture::new(&[compr]).await;
+    let sd = fx.path();
+    let compressed = fs::read(sd.join(format!("foo.tar.{compr}"))).unwrap();
+    let file_name = format!("truncated.bin.{compr}");
+    fs::write(sd.join(&file_name), &compressed[..compressed.len() / 2]).unwrap();
+    let pd = PipeDownloaderOptions::default()
+        .start_download(&fx.url(&file_name), Some(sd.join("truncated.bin")))
+        .await
+        .unwrap();
+    let err = wait_for_finish(&pd).await.unwrap_err();
+    assert!(matches!(err, DownloadError::Decode { .. }), "{err}");
+    assert_eq!(pd.get_progress().error, Some(err));
+}
 
-            tokio::time::sleep(Duration::from_millis(1000)).await;
-        }
-    }
-    tsk.abort();
+#[tokio::test]
+async fn test_truncated_gz() {
+    check_truncated_single_file("gz").await;
+}
 
-    fs::remove_dir_all(sd).unwrap();
+#[tokio::test]
+async fn test_truncated_zst() {
+    check_truncated_single_file("zst").await;
+}
+
+#[tokio::test]
+async fn test_source_changed() {
+    // file modified during download is detected by Last-Modified change,
+    // it's modified after the url was probed and before the first chunk is requested
+    let fx = Fixture::new(&["lz4"]).await;
+    let sd = fx.path();
+    fs::copy(sd.join("foo.tar.lz4"), sd.join("changing.tar.lz4")).unwrap();
+    let pd = PipeDownloaderOptions {
+        chunk_size_downloader: 1000000,
+        download_threads: 2,
+        ..Default::default()
+    }
+    .start_download_with_observer(
+        &fx.url("changing.tar.lz4"),
+        Some(sd.join("output_changing")),
+        Arc::new(ModifyOnStart(sd.join("changing.tar.lz4"))),
+    )
+    .await
+    .unwrap();
+    let err = wait_for_finish(&pd).await.unwrap_err();
+    assert_eq!(err.kind(), "sourceChanged", "{err}");
+    assert_eq!(pd.get_progress().error, Some(err));
 }